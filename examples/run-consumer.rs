@@ -1,23 +1,50 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use clap::App;
 use clap::Arg;
+use log::error;
 use log::info;
 
-use rdkafka::config::ClientConfig;
 use rdkafka::config::RDKafkaLogLevel;
-use rdkafka::consumer::Consumer;
 use rdkafka::util::get_rdkafka_version;
+use tokio::net::TcpListener;
 
+use rust_with_kafka_tls::backpressure::InFlightLimits;
+use rust_with_kafka_tls::cert_watcher::consume_with_reload;
+use rust_with_kafka_tls::cert_watcher::CertWatcher;
+use rust_with_kafka_tls::connection_profile::ConnectionProfile;
 use rust_with_kafka_tls::consume_and_print::consume_and_print;
-use rust_with_kafka_tls::custom_context::CustomContext;
+use rust_with_kafka_tls::consume_and_print::PrintHandler;
+use rust_with_kafka_tls::consume_batches::consume_batches;
+use rust_with_kafka_tls::consume_batches::BatchSize;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::consume_partitions::consume_partitions;
 use rust_with_kafka_tls::custom_context::LoggingConsumer;
-use rust_with_kafka_tls::log_utils::setup_logger;
+use rust_with_kafka_tls::dead_letter::DeadLetterQueue;
+use rust_with_kafka_tls::host_overrides::HostOverrides;
+use rust_with_kafka_tls::log_utils::setup_logger_with_format;
+use rust_with_kafka_tls::log_utils::LogFormat;
+use rust_with_kafka_tls::metrics::Metrics;
+use rust_with_kafka_tls::replay::assign_from;
+use rust_with_kafka_tls::replay::StartPosition;
+use rust_with_kafka_tls::sasl::Authentication;
+use rust_with_kafka_tls::shutdown::shutdown_on_signals;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+
+/// group of the running consumers, replays must not commit to it
+const DEFAULT_GROUP_ID: &str = "example_consumer_group_id";
 
 // cargo build --example run-consumer && export RUST_BACKTRACE=1 && export RUST_LOG=info && ./target/debug/examples/run-consumer -b COMMA_DELIMITED_BROKER_LIST -g rust-consumer-testing -t testing
 
 #[tokio::main]
 async fn main() {
-    let comma_delimited_brokers = std::env::var("KAFKA_BROKERS")
-        .unwrap_or_else(|_| "localhost:9092".to_string());
+    let detected_profile = ConnectionProfile::detect()
+        .expect("KAFKA_PROFILE is not a connection profile");
+    let detected_log_format =
+        LogFormat::from_env().expect("KAFKA_LOG_FORMAT is not a log format");
+    let sasl_mechanism = std::env::var("KAFKA_SASL_MECHANISM")
+        .unwrap_or_else(|_| "SSL".to_string());
     let matches = App::new("consumer example")
         .version(option_env!("CARGO_PKG_VERSION").unwrap_or(""))
         .about("Simple command line consumer")
@@ -25,9 +52,11 @@ async fn main() {
             Arg::with_name("brokers")
                 .short("b")
                 .long("brokers")
-                .help("Broker list in kafka format")
-                .takes_value(true)
-                .default_value(&comma_delimited_brokers),
+                .help(
+                    "Broker list in kafka format \
+                    (default: KAFKA_BROKERS or the profile brokers)",
+                )
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("group-id")
@@ -35,7 +64,82 @@ async fn main() {
                 .long("group-id")
                 .help("Consumer group id")
                 .takes_value(true)
-                .default_value("example_consumer_group_id"),
+                .default_value(DEFAULT_GROUP_ID),
+        )
+        .arg(
+            Arg::with_name("group-instance-id")
+                .long("group-instance-id")
+                .help(
+                    "Join the group as a static member with this stable id \
+                    (example: the pod name)",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("batch-size")
+                .long("batch-size")
+                .help(
+                    "Handle messages in batches of up to N messages, \
+                    committing after each batch",
+                )
+                .takes_value(true)
+                .conflicts_with_all(&["key-workers", "partition-tasks"]),
+        )
+        .arg(
+            Arg::with_name("dlq-topic")
+                .long("dlq-topic")
+                .help("Republish messages that fail handling to this topic")
+                .takes_value(true)
+                .conflicts_with("batch-size"),
+        )
+        .arg(
+            Arg::with_name("from-beginning")
+                .long("from-beginning")
+                .help(
+                    "Assign every partition from its earliest message \
+                    instead of joining the group",
+                )
+                .conflicts_with_all(&["from-end", "from-timestamp", "offset"]),
+        )
+        .arg(
+            Arg::with_name("from-end")
+                .long("from-end")
+                .help(
+                    "Assign every partition from its next new message \
+                    instead of joining the group",
+                )
+                .conflicts_with_all(&["from-timestamp", "offset"]),
+        )
+        .arg(
+            Arg::with_name("from-timestamp")
+                .long("from-timestamp")
+                .help(
+                    "Assign every partition from its first message at or \
+                    after an RFC3339 timestamp instead of joining the group",
+                )
+                .takes_value(true)
+                .conflicts_with("offset"),
+        )
+        .arg(
+            Arg::with_name("host-override")
+                .long("host-override")
+                .help(
+                    "Connect to an ip instead of resolving a broker \
+                    hostname (format: hostname=ip, repeatable)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("key-workers")
+                .long("key-workers")
+                .help(
+                    "Handle messages on N concurrent workers, \
+                    keeping the order of each key",
+                )
+                .takes_value(true)
+                .conflicts_with("partition-tasks"),
         )
         .arg(
             Arg::with_name("log-conf")
@@ -43,6 +147,90 @@ async fn main() {
                 .help("Configure the logging format (example: 'rdkafka=trace')")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .help("Log line format: text, json or logfmt")
+                .takes_value(true)
+                .default_value(detected_log_format.name()),
+        )
+        .arg(
+            Arg::with_name("metrics-addr")
+                .long("metrics-addr")
+                .help(
+                    "Serve prometheus metrics on http://ADDR/metrics \
+                    (example: 127.0.0.1:9464)",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-in-flight")
+                .long("max-in-flight")
+                .help(
+                    "Pause the partitions while the key workers hold N \
+                    unfinished messages",
+                )
+                .takes_value(true)
+                .requires("key-workers"),
+        )
+        .arg(
+            Arg::with_name("max-in-flight-bytes")
+                .long("max-in-flight-bytes")
+                .help(
+                    "Pause the partitions while the key workers hold N \
+                    bytes of unfinished messages",
+                )
+                .takes_value(true)
+                .requires("key-workers"),
+        )
+        .arg(
+            Arg::with_name("max-messages")
+                .long("max-messages")
+                .help("Stop after handling N messages")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("offset")
+                .long("offset")
+                .help(
+                    "Assign a partition from an offset instead of joining \
+                    the group (format: PARTITION:OFFSET for a single \
+                    topic or TOPIC:PARTITION:OFFSET, repeatable)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("partition-tasks")
+                .long("partition-tasks")
+                .help(
+                    "Handle each assigned partition in its own task, \
+                    keeping the order within a partition",
+                ),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .help("Connection profile: plain, tls-internal or tls-external")
+                .takes_value(true)
+                .default_value(detected_profile.name()),
+        )
+        .arg(
+            Arg::with_name("sasl-mechanism")
+                .long("sasl-mechanism")
+                .help(
+                    "Authenticate with SSL (client certificates), \
+                    SCRAM-SHA-512, SCRAM-SHA-256 or OAUTHBEARER",
+                )
+                .takes_value(true)
+                .default_value(&sasl_mechanism),
+        )
+        .arg(
+            Arg::with_name("tls-stdin")
+                .long("tls-stdin")
+                .help("Read the client cert, key and CA PEM bundle from stdin"),
+        )
         .arg(
             Arg::with_name("topics")
                 .short("t")
@@ -52,54 +240,182 @@ async fn main() {
                 .multiple(true)
                 .required(true),
         )
+        .arg(
+            Arg::with_name("watch-tls")
+                .long("watch-tls")
+                .conflicts_with_all(&[
+                    "batch-size",
+                    "from-beginning",
+                    "from-end",
+                    "from-timestamp",
+                    "offset",
+                    "partition-tasks",
+                ])
+                .help("Rebuild the consumer when the tls files are rotated"),
+        )
         .get_matches();
 
-    setup_logger(true, matches.value_of("log-conf"));
+    let log_format: LogFormat = matches
+        .value_of("log-format")
+        .unwrap()
+        .parse()
+        .expect("--log-format must be text, json or logfmt");
+    setup_logger_with_format(log_format, true, matches.value_of("log-conf"));
 
     let (version_n, version_s) = get_rdkafka_version();
     info!("rd_kafka_version: 0x{:08x}, {}", version_n, version_s);
 
     let topics = matches.values_of("topics").unwrap().collect::<Vec<&str>>();
-    let brokers = matches.value_of("brokers").unwrap();
+    let profile: ConnectionProfile = matches
+        .value_of("profile")
+        .unwrap()
+        .parse()
+        .expect("--profile must be plain, tls-internal or tls-external");
+    let brokers = matches
+        .value_of("brokers")
+        .map(str::to_string)
+        .unwrap_or_else(|| profile.brokers_from_env());
     let group_id = matches.value_of("group-id").unwrap();
 
-    let context = CustomContext;
-    let consumer: LoggingConsumer = ClientConfig::new()
-        .set("group.id", group_id)
-        .set("bootstrap.servers", brokers)
-        .set("enable.partition.eof", "false")
-        .set("session.timeout.ms", "6000")
-        .set("enable.auto.commit", "true")
-        .set("security.protocol", "SSL")
-        .set(
-            "ssl.ca.location",
-            std::env::var("KAFKA_TLS_CLIENT_CA")
-                .unwrap_or_else(|_| "./kubernetes/tls/ca.pem".to_string()),
-        )
-        .set(
-            "ssl.key.location",
-            std::env::var("KAFKA_TLS_CLIENT_KEY").unwrap_or_else(|_| {
-                "./kubernetes/tls/client-key.pem".to_string()
-            }),
-        )
-        .set(
-            "ssl.certificate.location",
-            std::env::var("KAFKA_TLS_CLIENT_CERT")
-                .unwrap_or_else(|_| "./kubernetes/tls/client.pem".to_string()),
-        )
-        .set("enable.ssl.certificate.verification", "true")
-        .set_log_level(RDKafkaLogLevel::Debug)
-        .create_with_context(context)
-        .expect("Consumer creation failed");
+    let mut tls = KafkaTlsConfig::from_env(&brokers)
+        .profile(profile)
+        .host_overrides(
+            HostOverrides::from_env(
+                matches.values_of("host-override").into_iter().flatten(),
+            )
+            .expect("Invalid host override"),
+        );
+    if matches.is_present("tls-stdin") {
+        tls = tls
+            .pem_bundle("stdin", std::io::stdin())
+            .expect("Reading the tls bundle from stdin failed");
+    }
+    let auth =
+        Authentication::from_env(matches.value_of("sasl-mechanism").unwrap())
+            .expect("Reading the sasl credentials failed");
+    tls = tls.authentication(auth);
+    if let Some(instance_id) = matches.value_of("group-instance-id") {
+        tls = tls.set("group.instance.id", instance_id);
+    }
+
+    // stop on SIGINT or SIGTERM, commit handled offsets and leave the group
+    let shutdown = shutdown_on_signals();
+    if let Some(addr) = matches.value_of("metrics-addr") {
+        let metrics = Arc::new(Metrics::new());
+        let listener = TcpListener::bind(addr)
+            .await
+            .expect("Binding the metrics endpoint failed");
+        tokio::spawn(metrics.clone().serve(listener, shutdown.clone()));
+        tls = tls.metrics(metrics);
+    }
+    let consumer_tls = tls.clone().log_level(RDKafkaLogLevel::Debug);
 
     info!(
         "building consumer brokers={brokers} group_id={group_id} topics={:?}",
         topics
     );
 
-    consumer
-        .subscribe(&topics)
-        .expect("Can't subscribe to specified topics");
-
-    consume_and_print(&consumer).await
+    let mut options = ConsumeOptions::new().shutdown(shutdown);
+    if let Some(workers) = matches.value_of("key-workers") {
+        let workers: usize =
+            workers.parse().expect("--key-workers must be a number");
+        info!("handling messages on {workers} key workers");
+        options = options.key_workers(workers);
+    }
+    let mut limits = InFlightLimits::new();
+    if let Some(messages) = matches.value_of("max-in-flight") {
+        let messages: usize =
+            messages.parse().expect("--max-in-flight must be a number");
+        limits = limits.messages(messages);
+    }
+    if let Some(bytes) = matches.value_of("max-in-flight-bytes") {
+        let bytes: usize = bytes
+            .parse()
+            .expect("--max-in-flight-bytes must be a number");
+        limits = limits.bytes(bytes);
+    }
+    options = options.in_flight_limits(limits);
+    if let Some(max) = matches.value_of("max-messages") {
+        let max: usize = max.parse().expect("--max-messages must be a number");
+        info!("stopping after {max} messages");
+        options = options.max_messages(max);
+    }
+    let batch_size = matches.value_of("batch-size").map(|size| {
+        let size: usize = size.parse().expect("--batch-size must be a number");
+        info!("handling messages in batches of up to {size}");
+        size
+    });
+    if let Some(size) = batch_size {
+        options = options.batch_size(BatchSize::new().messages(size));
+    }
+    if let Some(dlq_topic) = matches.value_of("dlq-topic") {
+        info!("dead-lettering failed messages to topic={dlq_topic}");
+        let producer = tls
+            .create_producer()
+            .expect("Dead-letter producer creation failed");
+        options =
+            options.dead_letter(DeadLetterQueue::new(producer, dlq_topic));
+    }
+    let position = if matches.is_present("from-beginning") {
+        StartPosition::Beginning
+    } else if matches.is_present("from-end") {
+        StartPosition::End
+    } else if let Some(timestamp) = matches.value_of("from-timestamp") {
+        StartPosition::from_rfc3339(timestamp)
+            .expect("--from-timestamp must be an RFC3339 timestamp")
+    } else if let Some(offsets) = matches.values_of("offset") {
+        StartPosition::from_partition_offsets(offsets).expect(
+            "--offset must be PARTITION:OFFSET or TOPIC:PARTITION:OFFSET",
+        )
+    } else {
+        StartPosition::Committed
+    };
+    if position != StartPosition::Committed {
+        // the handled offsets would move the running group
+        if group_id == DEFAULT_GROUP_ID {
+            error!(
+                "replays commit to their group_id, pass a group of their \
+                own with -g instead of {DEFAULT_GROUP_ID}"
+            );
+            std::process::exit(1);
+        }
+        info!(
+            "assigning partitions at {position:?}, handled offsets are \
+            committed to group_id={group_id}"
+        );
+    }
+    let consumed = if matches.is_present("watch-tls") {
+        let mut watcher = CertWatcher::new(&consumer_tls);
+        consume_with_reload(
+            &mut watcher,
+            group_id,
+            &topics,
+            &PrintHandler,
+            &options,
+        )
+        .await
+    } else {
+        let consumer: LoggingConsumer = consumer_tls
+            .create_consumer(group_id)
+            .expect("Consumer creation failed");
+        assign_from(&consumer, &topics, &position, Duration::from_secs(10))
+            .expect("Can't start consuming the specified topics");
+        if batch_size.is_some() {
+            consume_batches(&consumer, &PrintHandler, &options).await
+        } else if matches.is_present("partition-tasks") {
+            consume_partitions(
+                Arc::new(consumer),
+                Arc::new(PrintHandler),
+                &options,
+            )
+            .await
+        } else {
+            consume_and_print(&consumer, &options).await
+        }
+    };
+    if let Err(e) = consumed {
+        error!("consumer stopped: {e}");
+        std::process::exit(1);
+    }
+    info!("consumer stopped");
 }
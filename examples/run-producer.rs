@@ -1,20 +1,35 @@
+use std::sync::Arc;
+
 use clap::App;
 use clap::Arg;
+use log::error;
 use log::info;
 
-use rdkafka::config::ClientConfig;
-use rdkafka::producer::FutureProducer;
 use rdkafka::util::get_rdkafka_version;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 
-use rust_with_kafka_tls::log_utils::setup_logger;
-use rust_with_kafka_tls::publish_messages::publish_messages;
+use rust_with_kafka_tls::cert_watcher::CertWatcher;
+use rust_with_kafka_tls::cert_watcher::ReloadingProducer;
+use rust_with_kafka_tls::connection_profile::ConnectionProfile;
+use rust_with_kafka_tls::host_overrides::HostOverrides;
+use rust_with_kafka_tls::log_utils::setup_logger_with_format;
+use rust_with_kafka_tls::log_utils::LogFormat;
+use rust_with_kafka_tls::metrics::Metrics;
+use rust_with_kafka_tls::publish_messages::publish_messages_with_metrics;
+use rust_with_kafka_tls::sasl::Authentication;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
 
 // cargo build --example run-producer && export RUST_BACKTRACE=1 && export RUST_LOG=info && ./target/debug/examples/run-producer -b COMMA_DELIMITED_BROKER_LIST -t testing
 
 #[tokio::main]
 async fn main() {
-    let comma_delimited_brokers = std::env::var("KAFKA_BROKERS")
-        .unwrap_or_else(|_| "localhost:9092".to_string());
+    let detected_profile = ConnectionProfile::detect()
+        .expect("KAFKA_PROFILE is not a connection profile");
+    let detected_log_format =
+        LogFormat::from_env().expect("KAFKA_LOG_FORMAT is not a log format");
+    let sasl_mechanism = std::env::var("KAFKA_SASL_MECHANISM")
+        .unwrap_or_else(|_| "SSL".to_string());
     let matches = App::new("producer example")
         .version(option_env!("CARGO_PKG_VERSION").unwrap_or(""))
         .about("Simple command line producer")
@@ -22,9 +37,22 @@ async fn main() {
             Arg::with_name("brokers")
                 .short("b")
                 .long("brokers")
-                .help("Broker list in kafka format")
+                .help(
+                    "Broker list in kafka format \
+                    (default: KAFKA_BROKERS or the profile brokers)",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("host-override")
+                .long("host-override")
+                .help(
+                    "Connect to an ip instead of resolving a broker \
+                    hostname (format: hostname=ip, repeatable)",
+                )
                 .takes_value(true)
-                .default_value(&comma_delimited_brokers),
+                .multiple(true)
+                .number_of_values(1),
         )
         .arg(
             Arg::with_name("log-conf")
@@ -32,6 +60,44 @@ async fn main() {
                 .help("Configure the logging format (example: 'rdkafka=trace')")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .help("Log line format: text, json or logfmt")
+                .takes_value(true)
+                .default_value(detected_log_format.name()),
+        )
+        .arg(
+            Arg::with_name("metrics-addr")
+                .long("metrics-addr")
+                .help(
+                    "Serve prometheus metrics on http://ADDR/metrics \
+                    while publishing (example: 127.0.0.1:9464)",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .help("Connection profile: plain, tls-internal or tls-external")
+                .takes_value(true)
+                .default_value(detected_profile.name()),
+        )
+        .arg(
+            Arg::with_name("sasl-mechanism")
+                .long("sasl-mechanism")
+                .help(
+                    "Authenticate with SSL (client certificates), \
+                    SCRAM-SHA-512, SCRAM-SHA-256 or OAUTHBEARER",
+                )
+                .takes_value(true)
+                .default_value(&sasl_mechanism),
+        )
+        .arg(
+            Arg::with_name("tls-stdin")
+                .long("tls-stdin")
+                .help("Read the client cert, key and CA PEM bundle from stdin"),
+        )
         .arg(
             Arg::with_name("topic")
                 .short("t")
@@ -40,40 +106,80 @@ async fn main() {
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            Arg::with_name("watch-tls")
+                .long("watch-tls")
+                .help("Rebuild the producer when the tls files are rotated"),
+        )
         .get_matches();
 
-    setup_logger(true, matches.value_of("log-conf"));
+    let log_format: LogFormat = matches
+        .value_of("log-format")
+        .unwrap()
+        .parse()
+        .expect("--log-format must be text, json or logfmt");
+    setup_logger_with_format(log_format, true, matches.value_of("log-conf"));
 
     let (version_n, version_s) = get_rdkafka_version();
     info!("rd_kafka_version: 0x{:08x}, {}", version_n, version_s);
 
     let topic = matches.value_of("topic").unwrap();
-    let brokers = matches.value_of("brokers").unwrap();
+    let profile: ConnectionProfile = matches
+        .value_of("profile")
+        .unwrap()
+        .parse()
+        .expect("--profile must be plain, tls-internal or tls-external");
+    let brokers = matches
+        .value_of("brokers")
+        .map(str::to_string)
+        .unwrap_or_else(|| profile.brokers_from_env());
 
-    let producer: &FutureProducer = &ClientConfig::new()
-        .set("bootstrap.servers", brokers)
-        .set("message.timeout.ms", "5000")
-        .set("security.protocol", "SSL")
-        .set(
-            "ssl.ca.location",
-            std::env::var("KAFKA_TLS_CLIENT_CA")
-                .unwrap_or_else(|_| "./kubernetes/tls/ca.pem".to_string()),
-        )
-        .set(
-            "ssl.key.location",
-            std::env::var("KAFKA_TLS_CLIENT_KEY").unwrap_or_else(|_| {
-                "./kubernetes/tls/client-key.pem".to_string()
-            }),
-        )
-        .set(
-            "ssl.certificate.location",
-            std::env::var("KAFKA_TLS_CLIENT_CERT")
-                .unwrap_or_else(|_| "./kubernetes/tls/client.pem".to_string()),
-        )
-        .set("enable.ssl.certificate.verification", "true")
-        .create()
-        .expect("Producer creation error");
+    let mut tls = KafkaTlsConfig::from_env(&brokers)
+        .profile(profile)
+        .host_overrides(
+            HostOverrides::from_env(
+                matches.values_of("host-override").into_iter().flatten(),
+            )
+            .expect("Invalid host override"),
+        );
+    if matches.is_present("tls-stdin") {
+        tls = tls
+            .pem_bundle("stdin", std::io::stdin())
+            .expect("Reading the tls bundle from stdin failed");
+    }
+    let auth =
+        Authentication::from_env(matches.value_of("sasl-mechanism").unwrap())
+            .expect("Reading the sasl credentials failed");
+    tls = tls.authentication(auth);
+    let shutdown = CancellationToken::new();
+    let mut metrics = None;
+    if let Some(addr) = matches.value_of("metrics-addr") {
+        let shared = Arc::new(Metrics::new());
+        let listener = TcpListener::bind(addr)
+            .await
+            .expect("Binding the metrics endpoint failed");
+        tokio::spawn(shared.clone().serve(listener, shutdown.clone()));
+        tls = tls.metrics(shared.clone());
+        metrics = Some(shared);
+    }
+    let producer =
+        ReloadingProducer::new(&tls).expect("Producer creation error");
+    if matches.is_present("watch-tls") {
+        let watching = producer.clone();
+        let stop = shutdown.clone();
+        tokio::spawn(async move {
+            let mut watcher = CertWatcher::new(&tls);
+            watching.watch(&mut watcher, &stop).await;
+        });
+    }
 
     info!("publishing messag to broker={brokers} topic={topic}");
-    publish_messages(&producer, &topic).await;
+    let published =
+        publish_messages_with_metrics(&producer.producer(), topic, metrics)
+            .await;
+    shutdown.cancel();
+    if let Err(e) = published {
+        error!("publishing failed: {e}");
+        std::process::exit(1);
+    }
 }
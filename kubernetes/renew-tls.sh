@@ -0,0 +1,80 @@
+#!/bin/bash
+
+# Re-issue the self-signed dev tls assets in ./kubernetes/tls from their
+# existing keys, so clients and brokers already trusting the CA keep working.
+# The clients check the certificates before connecting and refuse expired
+# ones, run this when they report an expired or soon to expire certificate.
+
+function yellow() { printf "\x1b[38;5;227m%s\e[0m " "${@}"; printf "\n"; }
+function green() { printf "\x1b[38;5;048m%s\e[0m " "${@}"; printf "\n"; }
+function red() { printf "\x1b[38;5;196m%s\e[0m " "${@}"; printf "\n"; }
+
+if [[ "${TLS_DIR}" == "" ]]; then
+    export TLS_DIR="./kubernetes/tls"
+fi
+if [[ "${CA_DAYS}" == "" ]]; then
+    export CA_DAYS="3650"
+fi
+if [[ "${CERT_DAYS}" == "" ]]; then
+    export CERT_DAYS="1825"
+fi
+
+SANS="DNS:*.redten.io,DNS:*.example.com,DNS:*.local,DNS:*.svc.cluster.local,DNS:localhost,IP:0.0.0.0,IP:127.0.0.1"
+
+# same key and subject, so certificates issued before stay valid
+function renew_ca() {
+    yellow "renewing ${TLS_DIR}/ca.pem for ${CA_DAYS} days"
+    openssl req -new -x509 -sha256 \
+        -key "${TLS_DIR}/ca-key.pem" \
+        -subj "/C=US/ST=NA/L=NA/O=NA/OU=NA/CN=Example CA" \
+        -days "${CA_DAYS}" \
+        -addext "keyUsage=critical,keyCertSign,cRLSign" \
+        -addext "basicConstraints=critical,CA:TRUE" \
+        -addext "subjectAltName=IP:0.0.0.0,IP:127.0.0.1" \
+        -out "${TLS_DIR}/ca.pem"
+    lt="$?"
+    if [[ "${lt}" -ne 0 ]]; then
+        red "failed to renew the ca - stopping"
+        exit 1
+    fi
+}
+
+# renew_cert NAME EXTENDED_KEY_USAGE
+function renew_cert() {
+    name="${1}"
+    usage="${2}"
+    yellow "renewing ${TLS_DIR}/${name}.pem for ${CERT_DAYS} days"
+    openssl req -new -sha256 \
+        -key "${TLS_DIR}/${name}-key.pem" \
+        -subj "/C=US/O=kafka/CN=kafka" \
+        | openssl x509 -req -sha256 \
+            -CA "${TLS_DIR}/ca.pem" \
+            -CAkey "${TLS_DIR}/ca-key.pem" \
+            -CAcreateserial \
+            -CAserial "${TLS_DIR}/ca.srl" \
+            -days "${CERT_DAYS}" \
+            -extfile <(printf "%s\n" \
+                "keyUsage=critical,digitalSignature,keyEncipherment" \
+                "extendedKeyUsage=${usage}" \
+                "basicConstraints=critical,CA:FALSE" \
+                "subjectKeyIdentifier=hash" \
+                "authorityKeyIdentifier=keyid" \
+                "subjectAltName=${SANS}") \
+            -out "${TLS_DIR}/${name}.pem"
+    lt="$?"
+    if [[ "${lt}" -ne 0 ]]; then
+        red "failed to renew ${name}.pem - stopping"
+        exit 1
+    fi
+}
+
+renew_ca
+renew_cert "client" "clientAuth"
+renew_cert "server" "serverAuth"
+cat "${TLS_DIR}/server.pem" "${TLS_DIR}/ca.pem" > "${TLS_DIR}/server-cert-chain.pem"
+rm -f "${TLS_DIR}/ca.srl"
+
+for f in ca client server; do
+    openssl x509 -in "${TLS_DIR}/${f}.pem" -noout -subject -enddate
+done
+green "renewed the tls assets in ${TLS_DIR}, redeploy them with ./kubernetes/deploy.sh"
@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::time::Instant;
+
+use log::info;
+use log::warn;
+use rdkafka::consumer::Consumer;
+use rdkafka::topic_partition_list::TopicPartitionList;
+
+use crate::custom_context::LoggingConsumer;
+
+/// InFlightLimits
+///
+/// How much received work the
+/// [`ConsumeOptions::key_workers`](crate::consume_options::ConsumeOptions::key_workers)
+/// pool may hold before the consume loop pauses its partitions with
+/// ``Consumer::pause``. A message is in flight from the moment it is
+/// received until its handler is done, and its size is its key plus
+/// payload bytes. The partitions resume once the work in flight
+/// drains to half of every limit. Limits that are not set are not
+/// checked.
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::backpressure::InFlightLimits;
+/// use rust_with_kafka_tls::consume_options::ConsumeOptions;
+///
+/// let options = ConsumeOptions::new().key_workers(8).in_flight_limits(
+///     InFlightLimits::new().messages(1000).bytes(64 * 1024 * 1024),
+/// );
+/// ```
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InFlightLimits {
+    pub(crate) messages: Option<usize>,
+    pub(crate) bytes: Option<usize>,
+}
+
+impl InFlightLimits {
+    /// new
+    ///
+    /// Create limits that never pause the consumer
+    ///
+    pub fn new() -> Self {
+        InFlightLimits::default()
+    }
+
+    /// messages
+    ///
+    /// Pause once ``messages`` messages are in flight
+    ///
+    pub fn messages(mut self, messages: usize) -> Self {
+        self.messages = Some(messages.max(1));
+        self
+    }
+
+    /// bytes
+    ///
+    /// Pause once the messages in flight hold ``bytes`` bytes of keys
+    /// and payloads
+    ///
+    pub fn bytes(mut self, bytes: usize) -> Self {
+        self.bytes = Some(bytes.max(1));
+        self
+    }
+
+    /// true when ``messages`` or ``bytes`` in flight reached a limit
+    pub(crate) fn reached(&self, messages: usize, bytes: usize) -> bool {
+        self.messages.is_some_and(|limit| messages >= limit)
+            || self.bytes.is_some_and(|limit| bytes >= limit)
+    }
+
+    /// true when ``messages`` and ``bytes`` in flight are down to half
+    /// of every limit
+    // is_none_or needs rust 1.82
+    #[allow(clippy::unnecessary_map_or)]
+    pub(crate) fn drained(&self, messages: usize, bytes: usize) -> bool {
+        self.messages.map_or(true, |limit| messages <= limit / 2)
+            && self.bytes.map_or(true, |limit| bytes <= limit / 2)
+    }
+}
+
+/// pauses and resumes the partitions of a consumer for its
+/// [`InFlightLimits`](crate::backpressure::InFlightLimits) and counts
+/// the time spent paused in the context metrics
+pub(crate) struct Backpressure {
+    limits: InFlightLimits,
+    paused: HashSet<(String, i32)>,
+    paused_since: Option<Instant>,
+}
+
+impl Backpressure {
+    pub(crate) fn new(limits: InFlightLimits) -> Self {
+        Backpressure {
+            limits,
+            paused: HashSet::new(),
+            paused_since: None,
+        }
+    }
+
+    /// pause the assignment when ``messages`` or ``bytes`` in flight
+    /// reached a limit and resume it once they drained
+    pub(crate) fn update(
+        &mut self,
+        consumer: &LoggingConsumer,
+        messages: usize,
+        bytes: usize,
+    ) {
+        match self.paused_since {
+            None if self.limits.reached(messages, bytes) => {
+                info!(
+                    messages = messages,
+                    bytes = bytes;
+                    "in-flight limit reached, pausing partitions"
+                );
+                let assignment = match consumer.assignment() {
+                    Ok(assignment) => assignment,
+                    Err(e) => {
+                        warn!("failed to read the assignment: {e}");
+                        return;
+                    }
+                };
+                let partitions = assignment
+                    .elements()
+                    .iter()
+                    .map(|p| (p.topic().to_string(), p.partition()))
+                    .collect();
+                self.paused_since = Some(Instant::now());
+                if let Some(metrics) = consumer.context().shared_metrics() {
+                    metrics.consumer_paused();
+                }
+                self.pause(consumer, partitions);
+            }
+            Some(since) if self.limits.drained(messages, bytes) => {
+                info!(
+                    messages = messages,
+                    bytes = bytes;
+                    "in-flight work drained, resuming partitions"
+                );
+                let resumed = tpl(self.paused.drain());
+                // partitions revoked while paused are skipped by
+                // librdkafka
+                if let Err(e) = consumer.resume(&resumed) {
+                    warn!("failed to resume partitions: {e}");
+                }
+                self.paused_since = None;
+                if let Some(metrics) = consumer.context().shared_metrics() {
+                    metrics.consumer_resumed(since.elapsed());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// keep pausing while paused: a message from a partition that is
+    /// not paused yet was assigned after the pause
+    pub(crate) fn received(
+        &mut self,
+        consumer: &LoggingConsumer,
+        topic: &str,
+        partition: i32,
+    ) {
+        let key = (topic.to_string(), partition);
+        if self.paused_since.is_some() && !self.paused.contains(&key) {
+            self.pause(consumer, vec![key]);
+        }
+    }
+
+    /// count the time paused when the loop stops while paused
+    pub(crate) fn finish(&mut self, consumer: &LoggingConsumer) {
+        if let Some(since) = self.paused_since.take() {
+            if let Some(metrics) = consumer.context().shared_metrics() {
+                metrics.consumer_resumed(since.elapsed());
+            }
+        }
+    }
+
+    fn pause(
+        &mut self,
+        consumer: &LoggingConsumer,
+        partitions: Vec<(String, i32)>,
+    ) {
+        if let Err(e) = consumer.pause(&tpl(partitions.iter().cloned())) {
+            warn!("failed to pause partitions: {e}");
+        }
+        self.paused.extend(partitions);
+    }
+}
+
+fn tpl(partitions: impl Iterator<Item = (String, i32)>) -> TopicPartitionList {
+    let mut tpl = TopicPartitionList::new();
+    for (topic, partition) in partitions {
+        tpl.add_partition(&topic, partition);
+    }
+    tpl
+}
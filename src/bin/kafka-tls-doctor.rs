@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use clap::App;
+use clap::Arg;
+
+use rust_with_kafka_tls::connection_profile::ConnectionProfile;
+use rust_with_kafka_tls::host_overrides::HostOverrides;
+use rust_with_kafka_tls::log_utils::setup_logger_with_format;
+use rust_with_kafka_tls::log_utils::LogFormat;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+use rust_with_kafka_tls::tls_doctor::TlsDoctor;
+
+// cargo build --bin kafka-tls-doctor && ./target/debug/kafka-tls-doctor -b COMMA_DELIMITED_BROKER_LIST
+
+fn main() {
+    let detected_profile = ConnectionProfile::detect()
+        .expect("KAFKA_PROFILE is not a connection profile");
+    let detected_log_format =
+        LogFormat::from_env().expect("KAFKA_LOG_FORMAT is not a log format");
+    let matches = App::new("kafka-tls-doctor")
+        .version(option_env!("CARGO_PKG_VERSION").unwrap_or(""))
+        .about("Diagnose client mTLS connections to each kafka broker")
+        .arg(
+            Arg::with_name("brokers")
+                .short("b")
+                .long("brokers")
+                .help(
+                    "Broker list in kafka format \
+                    (default: KAFKA_BROKERS or the profile brokers)",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .help("Connection profile: tls-internal or tls-external")
+                .takes_value(true)
+                .default_value(detected_profile.name()),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .help("Seconds each network step may take")
+                .takes_value(true)
+                .default_value("10"),
+        )
+        .arg(
+            Arg::with_name("host-override")
+                .long("host-override")
+                .help(
+                    "Connect to an ip instead of resolving a broker \
+                    hostname (format: hostname=ip, repeatable)",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("log-conf")
+                .long("log-conf")
+                .help("Configure the logging format (example: 'rdkafka=trace')")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .help("Log line format: text, json or logfmt")
+                .takes_value(true)
+                .default_value(detected_log_format.name()),
+        )
+        .arg(
+            Arg::with_name("tls-stdin")
+                .long("tls-stdin")
+                .help("Read the client cert, key and CA PEM bundle from stdin"),
+        )
+        .get_matches();
+
+    let log_format: LogFormat = matches
+        .value_of("log-format")
+        .unwrap()
+        .parse()
+        .expect("--log-format must be text, json or logfmt");
+    setup_logger_with_format(log_format, true, matches.value_of("log-conf"));
+
+    let profile: ConnectionProfile = matches
+        .value_of("profile")
+        .unwrap()
+        .parse()
+        .expect("--profile must be plain, tls-internal or tls-external");
+    let brokers = matches
+        .value_of("brokers")
+        .map(str::to_string)
+        .unwrap_or_else(|| profile.brokers_from_env());
+    if !profile.is_tls() {
+        eprintln!(
+            "the {profile} profile does not use tls, nothing to diagnose"
+        );
+        std::process::exit(2);
+    }
+    let timeout = matches
+        .value_of("timeout")
+        .unwrap()
+        .parse()
+        .map(Duration::from_secs)
+        .expect("--timeout must be a number of seconds");
+
+    let mut tls = KafkaTlsConfig::from_env(&brokers)
+        .profile(profile)
+        .host_overrides(
+            HostOverrides::from_env(
+                matches.values_of("host-override").into_iter().flatten(),
+            )
+            .expect("Invalid host override"),
+        );
+    if matches.is_present("tls-stdin") {
+        tls = tls
+            .pem_bundle("stdin", std::io::stdin())
+            .expect("Reading the tls bundle from stdin failed");
+    }
+
+    let mut passed = true;
+    match tls.check() {
+        Ok(report) => {
+            println!("client certificates PASS");
+            for ca in &report.ca {
+                println!("  CA {ca}");
+            }
+            for cert in &report.chain {
+                println!("  certificate {cert}");
+            }
+        }
+        Err(e) => {
+            passed = false;
+            println!("client certificates FAIL");
+            println!("  {e}");
+        }
+    }
+    for report in TlsDoctor::new(&tls).timeout(timeout).run() {
+        passed &= report.passed();
+        print!("{report}");
+    }
+    if !passed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use openssl::asn1::Asn1Time;
+use openssl::asn1::Asn1TimeRef;
+use openssl::error::ErrorStack;
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::X509NameRef;
+use openssl::x509::X509Ref;
+use openssl::x509::X509StoreContext;
+use openssl::x509::X509;
+
+/// how long before a certificate expires a warning is logged
+/// when no window is set (30 days)
+pub const DEFAULT_EXPIRY_WARNING: Duration =
+    Duration::from_secs(30 * 24 * 60 * 60);
+
+/// CertInfo
+///
+/// Details of one X.509 certificate from a tls asset
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertInfo {
+    /// subject distinguished name (example: ``CN=kafka, O=redten``)
+    pub subject: String,
+    /// issuer distinguished name
+    pub issuer: String,
+    /// subject alternative names (example: ``DNS:localhost``,
+    /// ``IP:127.0.0.1``)
+    pub sans: Vec<String>,
+    /// serial number in hex
+    pub serial: String,
+    /// start of the validity period
+    pub not_before: DateTime<Utc>,
+    /// end of the validity period
+    pub not_after: DateTime<Utc>,
+}
+
+impl CertInfo {
+    /// read the details of ``cert``
+    pub(crate) fn from_x509(cert: &X509Ref) -> Result<Self, ErrorStack> {
+        let sans = cert
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| {
+                        if let Some(dns) = name.dnsname() {
+                            Some(format!("DNS:{dns}"))
+                        } else if let Some(ip) = name.ipaddress() {
+                            ip_address(ip).map(|ip| format!("IP:{ip}"))
+                        } else if let Some(email) = name.email() {
+                            Some(format!("email:{email}"))
+                        } else {
+                            name.uri().map(|uri| format!("URI:{uri}"))
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(CertInfo {
+            subject: distinguished_name(cert.subject_name()),
+            issuer: distinguished_name(cert.issuer_name()),
+            sans,
+            serial: cert.serial_number().to_bn()?.to_hex_str()?.to_string(),
+            not_before: utc(cert.not_before())?,
+            not_after: utc(cert.not_after())?,
+        })
+    }
+
+    /// why the certificate is not valid at ``now``, if it is not
+    pub fn validity_error(&self, now: DateTime<Utc>) -> Option<String> {
+        if now < self.not_before {
+            Some(format!(
+                "{} is not valid before {}",
+                self.subject, self.not_before
+            ))
+        } else if now > self.not_after {
+            Some(format!("{} expired on {}", self.subject, self.not_after))
+        } else {
+            None
+        }
+    }
+
+    /// ``true`` when the certificate expires within ``window`` from now
+    pub fn expires_within(&self, window: Duration) -> bool {
+        match chrono::Duration::from_std(window) {
+            Ok(window) => self.not_after <= Utc::now() + window,
+            Err(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for CertInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "subject='{}' issuer='{}' sans=[{}] serial={} \
+            not_before={} not_after={}",
+            self.subject,
+            self.issuer,
+            self.sans.join(", "),
+            self.serial,
+            self.not_before,
+            self.not_after
+        )
+    }
+}
+
+/// TlsInspection
+///
+/// Report on the CA, client certificate and client key of a
+/// [`KafkaTlsConfig`](crate::tls_config::KafkaTlsConfig) created by
+/// [`KafkaTlsConfig::inspect`](crate::tls_config::KafkaTlsConfig::inspect)
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsInspection {
+    /// certificates in the CA file
+    pub ca: Vec<CertInfo>,
+    /// client certificate followed by any intermediates in the
+    /// certificate file
+    pub chain: Vec<CertInfo>,
+    /// ``true`` when the client key belongs to the client certificate
+    pub key_matches_cert: bool,
+    /// why the client certificate does not chain to the CA, ``None``
+    /// when it does
+    pub chain_error: Option<String>,
+}
+
+impl TlsInspection {
+    /// the client certificate
+    pub fn cert(&self) -> &CertInfo {
+        &self.chain[0]
+    }
+}
+
+/// every certificate in ``pem``, in file order
+pub(crate) fn parse_certs(pem: &[u8]) -> Result<Vec<X509>, ErrorStack> {
+    X509::stack_from_pem(pem)
+}
+
+/// verify_chain
+///
+/// Verify the first certificate of ``chain`` is signed by one of the
+/// ``ca`` certificates, the rest of ``chain`` are untrusted
+/// intermediates. Returns the openssl reason when it is not.
+///
+pub(crate) fn verify_chain(
+    ca: &[X509],
+    chain: &[X509],
+) -> Result<Option<String>, ErrorStack> {
+    let mut store = X509StoreBuilder::new()?;
+    for cert in ca {
+        store.add_cert(cert.clone())?;
+    }
+    let store = store.build();
+    let mut intermediates = Stack::new()?;
+    for cert in &chain[1..] {
+        intermediates.push(cert.clone())?;
+    }
+    let mut context = X509StoreContext::new()?;
+    context.init(&store, &chain[0], &intermediates, |context| {
+        Ok(match context.verify_cert()? {
+            true => None,
+            false => Some(context.error().error_string().to_string()),
+        })
+    })
+}
+
+/// ``CN=kafka, O=redten`` style name
+fn distinguished_name(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let field = entry.object().nid().short_name().unwrap_or("?");
+            // names in certificates are utf-8, printable or ia5 strings
+            let value = String::from_utf8_lossy(entry.data().as_slice());
+            format!("{field}={value}")
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn ip_address(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => {
+            let octets: [u8; 4] = bytes.try_into().ok()?;
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        16 => {
+            let octets: [u8; 16] = bytes.try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+fn utc(time: &Asn1TimeRef) -> Result<DateTime<Utc>, ErrorStack> {
+    let since_epoch = Asn1Time::from_unix(0)?.diff(time)?;
+    let seconds = i64::from(since_epoch.days) * 24 * 60 * 60
+        + i64::from(since_epoch.secs);
+    // asn1 times are always within the range of a DateTime
+    Ok(Utc.timestamp_opt(seconds, 0).unwrap())
+}
@@ -0,0 +1,352 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use log::info;
+use log::warn;
+use rdkafka::consumer::Consumer;
+use rdkafka::producer::Producer;
+use tokio_util::sync::CancellationToken;
+
+use crate::consume_messages::consume_messages;
+use crate::consume_options::ConsumeOptions;
+use crate::custom_context::LoggingConsumer;
+use crate::custom_context::LoggingProducer;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message_handler::MessageHandler;
+use crate::tls_config::KafkaTlsConfig;
+
+/// how often the tls files are checked when no interval is set
+pub const DEFAULT_CERT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// how long a replaced producer has to deliver its queued messages
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// contents of each watched file, ``None`` when it is not readable
+type Snapshot = Vec<Option<Vec<u8>>>;
+
+/// CertWatcher
+///
+/// Polls the CA, client key and client certificate files of a
+/// [`KafkaTlsConfig`](crate::tls_config::KafkaTlsConfig) for changes
+/// made by certificate rotation (Strimzi, cert-manager or a
+/// kubernetes secret volume update). Files are compared by contents
+/// so symlink swaps are detected. New material is checked before it
+/// is handed out, invalid, expired or half-written files are logged
+/// and skipped until the next change. The files are read and checked
+/// on the blocking thread pool. In-memory PEM assets never change and
+/// are not watched.
+///
+/// Use it with
+/// [`ReloadingProducer::watch`](crate::cert_watcher::ReloadingProducer::watch)
+/// or
+/// [`consume_with_reload`](crate::cert_watcher::consume_with_reload).
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use rust_with_kafka_tls::cert_watcher::CertWatcher;
+/// use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+///
+/// let tls = KafkaTlsConfig::from_env("localhost:9093");
+/// let watcher = CertWatcher::new(&tls).interval(Duration::from_secs(30));
+/// assert_eq!(watcher.reloads(), 0);
+/// ```
+///
+#[derive(Debug)]
+pub struct CertWatcher {
+    tls: KafkaTlsConfig,
+    files: Vec<PathBuf>,
+    interval: Duration,
+    snapshot: Snapshot,
+    rejected: Option<Snapshot>,
+    reloads: u64,
+}
+
+impl CertWatcher {
+    /// new
+    ///
+    /// Watch the tls files of ``tls`` starting from their current
+    /// contents
+    ///
+    pub fn new(tls: &KafkaTlsConfig) -> Self {
+        let files = tls.files();
+        let snapshot = read_files(&files);
+        CertWatcher {
+            tls: tls.clone(),
+            files,
+            interval: DEFAULT_CERT_CHECK_INTERVAL,
+            snapshot,
+            rejected: None,
+            reloads: 0,
+        }
+    }
+
+    /// how often the files are checked
+    /// (default: [`DEFAULT_CERT_CHECK_INTERVAL`](crate::cert_watcher::DEFAULT_CERT_CHECK_INTERVAL))
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// tls config the watched clients are built from
+    pub fn config(&self) -> &KafkaTlsConfig {
+        &self.tls
+    }
+
+    /// number of validated changes handed out by
+    /// [`changed`](crate::cert_watcher::CertWatcher::changed)
+    pub fn reloads(&self) -> u64 {
+        self.reloads
+    }
+
+    /// changed
+    ///
+    /// Wait until the watched files change and the new material
+    /// passes [`KafkaTlsConfig::check`](crate::tls_config::KafkaTlsConfig::check)
+    /// (only the CA with SASL authentication), then return the config
+    /// to rebuild clients with. Waits forever when nothing is watched.
+    ///
+    pub async fn changed(&mut self) -> KafkaTlsConfig {
+        loop {
+            tokio::time::sleep(self.interval).await;
+            let files = self.files.clone();
+            let current =
+                match tokio::task::spawn_blocking(move || read_files(&files))
+                    .await
+                {
+                    Ok(current) => current,
+                    Err(e) => {
+                        warn!("reading the tls assets failed: {e}");
+                        continue;
+                    }
+                };
+            if current == self.snapshot
+                || self.rejected.as_ref() == Some(&current)
+            {
+                continue;
+            }
+            let tls = self.tls.clone();
+            let checked = tokio::task::spawn_blocking(move || {
+                tls.preflight().map(|_| ())
+            })
+            .await
+            .unwrap_or_else(|e| {
+                Err(Error::Config(format!("tls check task failed: {e}")))
+            });
+            match checked {
+                Ok(()) => {
+                    info!("tls assets changed files={:?}", self.files);
+                    self.snapshot = current;
+                    self.rejected = None;
+                    self.reloads += 1;
+                    return self.tls.clone();
+                }
+                Err(e) => {
+                    warn!(
+                        "ignoring changed tls assets until they are valid: {e}"
+                    );
+                    self.rejected = Some(current);
+                }
+            }
+        }
+    }
+}
+
+/// read every file, unreadable files are kept as ``None`` so they are
+/// noticed once they appear
+fn read_files(files: &[PathBuf]) -> Snapshot {
+    files.iter().map(|path| fs::read(path).ok()).collect()
+}
+
+/// ReloadingProducer
+///
+/// Shared handle to a
+/// [`LoggingProducer`](crate::custom_context::LoggingProducer)
+/// that is rebuilt when the tls assets rotate. Take a producer with
+/// [`producer`](crate::cert_watcher::ReloadingProducer::producer)
+/// for each batch of sends, clones of the replaced producer keep
+/// working until they are dropped and its queued messages are
+/// flushed before the reload returns.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use rust_with_kafka_tls::cert_watcher::CertWatcher;
+/// use rust_with_kafka_tls::cert_watcher::ReloadingProducer;
+/// use rust_with_kafka_tls::publish_messages::publish_messages;
+/// use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+/// use tokio_util::sync::CancellationToken;
+///
+/// # async fn run() {
+/// let tls = KafkaTlsConfig::from_env("localhost:9093");
+/// let producer = ReloadingProducer::new(&tls).unwrap();
+/// let shutdown = CancellationToken::new();
+/// let watching = producer.clone();
+/// let token = shutdown.clone();
+/// tokio::spawn(async move {
+///     let mut watcher = CertWatcher::new(&tls);
+///     watching.watch(&mut watcher, &token).await;
+/// });
+/// publish_messages(&producer.producer(), "testing").await.unwrap();
+/// shutdown.cancel();
+/// # }
+/// ```
+///
+#[derive(Clone)]
+pub struct ReloadingProducer {
+    current: Arc<RwLock<LoggingProducer>>,
+}
+
+impl ReloadingProducer {
+    /// new
+    ///
+    /// Create the first producer from ``tls``
+    ///
+    /// # Errors
+    ///
+    /// Same as
+    /// [`KafkaTlsConfig::create_producer`](crate::tls_config::KafkaTlsConfig::create_producer)
+    ///
+    pub fn new(tls: &KafkaTlsConfig) -> Result<Self> {
+        Ok(ReloadingProducer {
+            current: Arc::new(RwLock::new(tls.create_producer()?)),
+        })
+    }
+
+    /// producer built from the latest tls assets
+    pub fn producer(&self) -> LoggingProducer {
+        self.current.read().unwrap().clone()
+    }
+
+    /// reload
+    ///
+    /// Build a producer from ``tls``, swap it in for new sends and
+    /// flush the messages still queued on the replaced producer
+    ///
+    /// # Errors
+    ///
+    /// The producer is not replaced when it cannot be created from
+    /// ``tls``. A replaced producer that cannot flush within 30
+    /// seconds is logged.
+    ///
+    pub async fn reload(&self, tls: &KafkaTlsConfig) -> Result<()> {
+        let producer = tls.create_producer()?;
+        let replaced =
+            std::mem::replace(&mut *self.current.write().unwrap(), producer);
+        let flushed =
+            tokio::task::spawn_blocking(move || replaced.flush(FLUSH_TIMEOUT))
+                .await
+                .map_err(|e| {
+                    Error::Config(format!("flush task failed: {e}"))
+                })?;
+        if let Err(e) = flushed {
+            warn!("replaced producer did not flush its queue: {e}");
+        }
+        Ok(())
+    }
+
+    /// watch
+    ///
+    /// Reload the producer every time ``watcher`` reports new tls
+    /// assets until ``shutdown`` is cancelled. A failed reload is
+    /// logged and the current producer stays in use.
+    ///
+    pub async fn watch(
+        &self,
+        watcher: &mut CertWatcher,
+        shutdown: &CancellationToken,
+    ) {
+        loop {
+            let tls = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                tls = watcher.changed() => tls,
+            };
+            match self.reload(&tls).await {
+                Ok(()) => info!("producer reloaded with rotated tls assets"),
+                Err(e) => warn!("keeping the current producer: {e}"),
+            }
+        }
+    }
+}
+
+/// consume_with_reload
+///
+/// Run [`consume_messages`](crate::consume_messages::consume_messages)
+/// with a consumer from the ``watcher`` config and rebuild it when the
+/// tls assets rotate. On a rotation the new consumer is created
+/// first, then the running loop finishes its in-flight message,
+/// commits the handled offsets and unsubscribes, and the new consumer
+/// resubscribes to ``topics``.
+///
+/// The partitions move to the new consumer with a group rebalance.
+/// Set a stable ``group.instance.id`` (for example the pod name) with
+/// [`KafkaTlsConfig::set`](crate::tls_config::KafkaTlsConfig::set)
+/// to join as a static member instead, so the new consumer takes over
+/// the partitions of the old one without a rebalance. A dead-letter
+/// producer in the ``options`` is not rebuilt.
+///
+/// # Arguments
+///
+/// * `watcher` - [`CertWatcher`](crate::cert_watcher::CertWatcher)
+///   for the consumer tls assets
+/// * `group_id` - consumer group to join
+/// * `topics` - topics to subscribe to
+/// * `handler` - [`MessageHandler`](crate::message_handler::MessageHandler)
+///   with the business logic for each message
+/// * `options` - [`ConsumeOptions`](crate::consume_options::ConsumeOptions)
+///   for the loop
+///
+/// # Errors
+///
+/// Same as [`consume_messages`](crate::consume_messages::consume_messages)
+/// plus creating or subscribing the first consumer. A consumer that
+/// cannot be rebuilt after a rotation is logged and the current one
+/// keeps consuming.
+///
+pub async fn consume_with_reload<H: MessageHandler>(
+    watcher: &mut CertWatcher,
+    group_id: &str,
+    topics: &[&str],
+    handler: &H,
+    options: &ConsumeOptions,
+) -> Result<()> {
+    let mut consumer = watcher.config().create_consumer(group_id)?;
+    loop {
+        consumer.subscribe(topics).map_err(Error::Kafka)?;
+        let stop = options.shutdown.child_token();
+        let loop_options = options.clone().shutdown(stop.clone());
+        let next = {
+            let consume = consume_messages(&consumer, handler, &loop_options);
+            tokio::pin!(consume);
+            let next = tokio::select! {
+                consumed = &mut consume => return consumed,
+                next = next_member(watcher, group_id) => next,
+            };
+            stop.cancel();
+            consume.await?;
+            next
+        };
+        info!("consumer rebuilt with rotated tls assets group_id={group_id}");
+        consumer = next;
+    }
+}
+
+/// wait for rotated tls assets and create a consumer from them
+async fn next_member(
+    watcher: &mut CertWatcher,
+    group_id: &str,
+) -> LoggingConsumer {
+    loop {
+        let tls = watcher.changed().await;
+        match tls.create_consumer(group_id) {
+            Ok(consumer) => return consumer,
+            Err(e) => warn!("keeping the current consumer: {e}"),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+/// CommitPolicy
+///
+/// How the consume loop in
+/// [`consume_messages`](crate::consume_messages::consume_messages)
+/// commits offsets. Only messages whose handler succeeded are
+/// committed or stored and a failed handler holds the commits of its
+/// partition at the failed offset, so a restart redelivers anything
+/// that was not handled (at-least-once).
+///
+/// The consumer must be created with the matching librdkafka
+/// settings from
+/// [`CommitPolicy::consumer_settings`](crate::commit_policy::CommitPolicy::consumer_settings),
+/// [`KafkaTlsConfig::commit_policy`](crate::tls_config::KafkaTlsConfig::commit_policy)
+/// applies them automatically.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CommitPolicy {
+    /// store the offset after the handler succeeds and let
+    /// librdkafka commit the stored offsets every
+    /// ``auto.commit.interval.ms``
+    Auto,
+    /// synchronously commit each message after the handler succeeds
+    PerMessageSync,
+    /// asynchronously commit each message after the handler succeeds
+    #[default]
+    PerMessageAsync,
+    /// store the offset after the handler succeeds and commit all
+    /// stored offsets on this interval
+    Periodic(Duration),
+    /// synchronously commit each message after the handler succeeds
+    /// and stop the loop with the handler error on the first failure,
+    /// so no offset at or after the failed message is committed
+    AfterHandlerSuccess,
+}
+
+impl CommitPolicy {
+    /// consumer_settings
+    ///
+    /// librdkafka consumer properties required by this policy
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use rdkafka::config::ClientConfig;
+    /// use rust_with_kafka_tls::commit_policy::CommitPolicy;
+    ///
+    /// let mut config = ClientConfig::new();
+    /// for (key, value) in CommitPolicy::Auto.consumer_settings() {
+    ///     config.set(key, value);
+    /// }
+    /// assert_eq!(config.get("enable.auto.offset.store"), Some("false"));
+    /// ```
+    ///
+    pub fn consumer_settings(&self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            CommitPolicy::Auto => vec![
+                ("enable.auto.commit", "true"),
+                ("enable.auto.offset.store", "false"),
+            ],
+            CommitPolicy::Periodic(_) => vec![
+                ("enable.auto.commit", "false"),
+                ("enable.auto.offset.store", "false"),
+            ],
+            CommitPolicy::PerMessageSync
+            | CommitPolicy::PerMessageAsync
+            | CommitPolicy::AfterHandlerSuccess => {
+                vec![("enable.auto.commit", "false")]
+            }
+        }
+    }
+
+    /// true when the loop stores offsets for librdkafka instead of
+    /// committing each message
+    pub(crate) fn stores_offsets(&self) -> bool {
+        matches!(self, CommitPolicy::Auto | CommitPolicy::Periodic(_))
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::error::Result;
+
+/// brokers of the ``plain`` listener from inside the kubernetes cluster
+pub const DEFAULT_PLAIN_BROKERS: &str = "dev-kafka-bootstrap.dev.svc:9092";
+/// brokers of the ``tls`` listener from inside the kubernetes cluster
+pub const DEFAULT_TLS_INTERNAL_BROKERS: &str =
+    "dev-kafka-bootstrap.dev.svc:9093";
+/// brokers of the ``external`` nodeport listener from outside the
+/// kubernetes cluster
+pub const DEFAULT_TLS_EXTERNAL_BROKERS: &str =
+    "cluster-0-broker-0.redten.io:32151,\
+    cluster-0-broker-1.redten.io:32152,\
+    cluster-0-broker-2.redten.io:32153";
+
+/// ConnectionProfile
+///
+/// Named listener of the strimzi cluster in ``kubernetes/dev.yaml``.
+/// A profile sets the ``security.protocol``, the default port for
+/// broker hosts given without one and the brokers to use when
+/// ``KAFKA_BROKERS`` is not set, so the same binaries work in-cluster
+/// and from a laptop.
+///
+/// - ``plain`` - ``plain`` listener, ``PLAINTEXT`` on port ``9092``
+/// - ``tls-internal`` - ``tls`` listener, ``SSL`` on port ``9093``
+/// - ``tls-external`` - ``external`` nodeport listener, ``SSL`` on the
+///   bootstrap port ``32150`` and ``32151``-``32153`` per broker
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::connection_profile::ConnectionProfile;
+///
+/// let profile: ConnectionProfile = "plain".parse().unwrap();
+/// assert_eq!(profile.security_protocol(), "PLAINTEXT");
+/// assert_eq!(profile.with_ports("kafka-0,kafka-1:9192"), "kafka-0:9092,kafka-1:9192");
+/// ```
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectionProfile {
+    /// unencrypted ``plain`` listener inside the cluster
+    Plain,
+    /// mTLS ``tls`` listener inside the cluster
+    TlsInternal,
+    /// mTLS ``external`` nodeport listener from outside the cluster
+    #[default]
+    TlsExternal,
+}
+
+impl ConnectionProfile {
+    /// detect
+    ///
+    /// Profile named by ``KAFKA_PROFILE``. When it is not set
+    /// clients running in a kubernetes pod (``KUBERNETES_SERVICE_HOST``
+    /// is set) use ``tls-internal`` and everything else uses
+    /// ``tls-external``.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Config`](crate::error::Error::Config) when
+    /// ``KAFKA_PROFILE`` is not a known profile
+    ///
+    pub fn detect() -> Result<Self> {
+        match std::env::var("KAFKA_PROFILE") {
+            Ok(name) => name.parse(),
+            Err(_) if std::env::var_os("KUBERNETES_SERVICE_HOST").is_some() => {
+                Ok(ConnectionProfile::TlsInternal)
+            }
+            Err(_) => Ok(ConnectionProfile::TlsExternal),
+        }
+    }
+
+    /// name used on the command line and in ``KAFKA_PROFILE``
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConnectionProfile::Plain => "plain",
+            ConnectionProfile::TlsInternal => "tls-internal",
+            ConnectionProfile::TlsExternal => "tls-external",
+        }
+    }
+
+    /// ``true`` when the listener is encrypted with tls
+    pub fn is_tls(&self) -> bool {
+        !matches!(self, ConnectionProfile::Plain)
+    }
+
+    /// ``security.protocol`` for clients authenticating with client
+    /// certificates (or none on the plain listener)
+    pub fn security_protocol(&self) -> &'static str {
+        match self.is_tls() {
+            true => "SSL",
+            false => "PLAINTEXT",
+        }
+    }
+
+    /// port appended to broker hosts given without one
+    pub fn port(&self) -> u16 {
+        match self {
+            ConnectionProfile::Plain => 9092,
+            ConnectionProfile::TlsInternal => 9093,
+            ConnectionProfile::TlsExternal => 32150,
+        }
+    }
+
+    /// brokers of this listener in the ``dev`` cluster
+    pub fn default_brokers(&self) -> &'static str {
+        match self {
+            ConnectionProfile::Plain => DEFAULT_PLAIN_BROKERS,
+            ConnectionProfile::TlsInternal => DEFAULT_TLS_INTERNAL_BROKERS,
+            ConnectionProfile::TlsExternal => DEFAULT_TLS_EXTERNAL_BROKERS,
+        }
+    }
+
+    /// ``KAFKA_BROKERS`` if it is set, otherwise the
+    /// [`default_brokers`](crate::connection_profile::ConnectionProfile::default_brokers)
+    pub fn brokers_from_env(&self) -> String {
+        std::env::var("KAFKA_BROKERS")
+            .unwrap_or_else(|_| self.default_brokers().to_string())
+    }
+
+    /// with_ports
+    ///
+    /// Append the profile
+    /// [`port`](crate::connection_profile::ConnectionProfile::port) to
+    /// every broker in the comma delimited ``brokers`` list that has
+    /// none
+    ///
+    pub fn with_ports(&self, brokers: &str) -> String {
+        brokers
+            .split(',')
+            .map(str::trim)
+            .filter(|broker| !broker.is_empty())
+            .map(|broker| match has_port(broker) {
+                true => broker.to_string(),
+                false => format!("{broker}:{}", self.port()),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl FromStr for ConnectionProfile {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "plain" | "plaintext" => Ok(ConnectionProfile::Plain),
+            "tls-internal" | "tls" => Ok(ConnectionProfile::TlsInternal),
+            "tls-external" | "external" => Ok(ConnectionProfile::TlsExternal),
+            _ => Err(Error::Config(format!(
+                "unknown connection profile {name}, expected plain, \
+                tls-internal or tls-external"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// ``true`` for ``host:port`` and ``[ipv6]:port`` brokers
+fn has_port(broker: &str) -> bool {
+    match broker.rsplit_once(':') {
+        Some((host, port)) => {
+            !port.is_empty()
+                && port.chars().all(|c| c.is_ascii_digit())
+                && (!host.contains(':') || host.ends_with(']'))
+        }
+        None => false,
+    }
+}
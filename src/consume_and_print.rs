@@ -1,17 +1,24 @@
+use async_trait::async_trait;
 use log::info;
-use log::warn;
-use rdkafka::consumer::CommitMode;
-use rdkafka::consumer::Consumer;
 use rdkafka::message::Headers;
 use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
 
+use crate::consume_messages::consume_messages;
+use crate::consume_options::ConsumeOptions;
 use crate::custom_context::LoggingConsumer;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message_handler::BatchHandler;
+use crate::message_handler::MessageHandler;
 
 /// consume_and_print
 ///
 /// Consume messages from kafka with an initalized
 /// [`rdkafka::consumer::Consumer`](rdkafka::consumer::Consumer)
 /// using client tls assets based off environment variables.
+/// Each message is logged by the built-in
+/// [`PrintHandler`](crate::consume_and_print::PrintHandler).
 ///
 /// # Optional - Set TLS Asset Paths
 ///
@@ -33,8 +40,10 @@ use crate::custom_context::LoggingConsumer;
 /// # Arguments
 ///
 /// * `consumer` - initialized
-/// [`rdkafka::consumer::Consumer`](rdkafka::consumer::Consumer)
-/// that is already subscribed to a list of ``topics`` with a ``group_id``
+///   [`rdkafka::consumer::Consumer`](rdkafka::consumer::Consumer)
+///   that is already subscribed to a list of ``topics`` with a ``group_id``
+/// * `options` - [`ConsumeOptions`](crate::consume_options::ConsumeOptions)
+///   with the shutdown token for stopping the loop
 ///
 /// # Examples
 ///
@@ -49,58 +58,105 @@ use crate::custom_context::LoggingConsumer;
 /// ./target/debug/examples/run-consumer -b $KAFKA_BROKERS -g rust-consumer-testing -t testing
 /// ```
 ///
-pub async fn consume_and_print(consumer: &LoggingConsumer) {
-    loop {
-        match consumer.recv().await {
-            Err(e) => warn!("Kafka error: {}", e),
-            Ok(m) => {
-                let payload = match m.payload_view::<str>() {
-                    None => "",
-                    Some(Ok(s)) => s,
-                    Some(Err(e)) => {
-                        warn!(
-                            "Error while deserializing message payload: {:?}",
-                            e
-                        );
-                        ""
-                    }
-                };
-                let mut header_str = String::from("");
-                if let Some(headers) = m.headers() {
-                    for i in 0..headers.count() {
-                        let header = headers.get(i).unwrap();
-                        let new_str = format!(
-                            "{i}:{:#?}=>{:?}",
-                            header.0,
-                            // https://doc.rust-lang.org/stable/std/str/fn.from_utf8.html
-                            std::str::from_utf8(header.1).unwrap()
-                        );
-                        if header_str.is_empty() {
-                            header_str = new_str;
-                        } else {
-                            header_str = header_str + ", " + &new_str;
-                        }
-                    }
-                }
-                let mut found_key = "";
-                if m.key().is_some() {
-                    found_key = std::str::from_utf8(m.key().unwrap()).unwrap();
-                }
-                info!(
-                    "key='{}' payload='{}', \
-                    topic={} partition={}, \
-                    offset={} timestamp={:?} \
-                    headers=[{header_str}]",
-                    // https://doc.rust-lang.org/stable/std/str/fn.from_utf8.html
-                    found_key,
-                    payload,
+/// # Errors
+///
+/// Returns [`Error::Commit`](crate::error::Error::Commit) when the
+/// offset commit fails. Messages with a key, header value or payload
+/// that is not utf-8 fail with
+/// [`Error::Deserialization`](crate::error::Error::Deserialization)
+/// and are dead-lettered when the ``options`` have a
+/// [`DeadLetterQueue`](crate::dead_letter::DeadLetterQueue),
+/// otherwise they are logged by the consume loop and left uncommitted.
+///
+pub async fn consume_and_print(
+    consumer: &LoggingConsumer,
+    options: &ConsumeOptions,
+) -> Result<()> {
+    consume_messages(consumer, &PrintHandler, options).await
+}
+
+/// PrintHandler
+///
+/// Built-in [`MessageHandler`](crate::message_handler::MessageHandler)
+/// that logs the key, payload, topic, partition, offset, timestamp
+/// and headers of each message with ``info!``. As a
+/// [`BatchHandler`](crate::message_handler::BatchHandler) it logs the
+/// batch size and then each message, a message that fails fails the
+/// batch.
+///
+pub struct PrintHandler;
+
+#[async_trait]
+impl MessageHandler for PrintHandler {
+    async fn handle(&self, m: &OwnedMessage) -> Result<()> {
+        let payload = match m.payload_view::<str>() {
+            None => "",
+            Some(Ok(s)) => s,
+            Some(Err(e)) => {
+                return Err(Error::Deserialization(format!(
+                    "payload is not utf-8 \
+                    topic={} partition={} offset={}: {e}",
                     m.topic(),
                     m.partition(),
-                    m.offset(),
-                    m.timestamp()
-                );
-                consumer.commit_message(&m, CommitMode::Async).unwrap();
+                    m.offset()
+                )))
             }
         };
+        let mut header_str = String::from("");
+        if let Some(headers) = m.headers() {
+            for (i, header) in headers.iter().enumerate() {
+                // https://doc.rust-lang.org/stable/std/str/fn.from_utf8.html
+                let value = std::str::from_utf8(header.value.unwrap_or(&[]))
+                    .map_err(|e| {
+                        Error::Deserialization(format!(
+                            "header {} is not utf-8 \
+                            topic={} partition={} offset={}: {e}",
+                            header.key,
+                            m.topic(),
+                            m.partition(),
+                            m.offset()
+                        ))
+                    })?;
+                let new_str = format!("{i}:{:#?}=>{:?}", header.key, value);
+                if header_str.is_empty() {
+                    header_str = new_str;
+                } else {
+                    header_str = header_str + ", " + &new_str;
+                }
+            }
+        }
+        let found_key = match m.key() {
+            Some(key) => std::str::from_utf8(key).map_err(|e| {
+                Error::Deserialization(format!(
+                    "key is not utf-8 \
+                    topic={} partition={} offset={}: {e}",
+                    m.topic(),
+                    m.partition(),
+                    m.offset()
+                ))
+            })?,
+            None => "",
+        };
+        info!(
+            topic = m.topic(),
+            partition = m.partition(),
+            offset = m.offset(),
+            key = found_key;
+            "payload='{}' timestamp={:?} headers=[{header_str}]",
+            payload,
+            m.timestamp()
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BatchHandler for PrintHandler {
+    async fn handle_batch(&self, messages: &[OwnedMessage]) -> Result<()> {
+        info!("batch of {} messages", messages.len());
+        for m in messages {
+            self.handle(m).await?;
+        }
+        Ok(())
     }
 }
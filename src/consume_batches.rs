@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use log::info;
+use log::warn;
+use rdkafka::consumer::CommitMode;
+use rdkafka::consumer::Consumer;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use tokio::time::Instant;
+
+use crate::commit_policy::CommitPolicy;
+use crate::consume_messages::commit_all;
+use crate::consume_messages::commit_interval;
+use crate::consume_messages::commit_stored;
+use crate::consume_messages::record_consumed;
+use crate::consume_messages::tick;
+use crate::consume_messages::HandledOffsets;
+use crate::consume_messages::RevokedPartitions;
+use crate::consume_options::ConsumeOptions;
+use crate::consume_partitions::Partition;
+use crate::custom_context::LoggingConsumer;
+use crate::error::Result;
+use crate::message_handler::BatchHandler;
+
+/// BatchSize
+///
+/// When the batch loop in
+/// [`consume_batches`](crate::consume_batches::consume_batches) hands
+/// its collected messages to the
+/// [`BatchHandler`](crate::message_handler::BatchHandler): once the
+/// batch holds ``messages`` messages, once the next message would
+/// take its keys and payloads over ``bytes`` bytes, or ``timeout``
+/// after its first message was received, whichever comes first
+/// (default: 500 messages, 1 MiB or 1 second). A message larger than
+/// ``bytes`` is handed over as a batch of its own.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use rust_with_kafka_tls::consume_batches::BatchSize;
+/// use rust_with_kafka_tls::consume_options::ConsumeOptions;
+///
+/// let options = ConsumeOptions::new().batch_size(
+///     BatchSize::new()
+///         .messages(1000)
+///         .bytes(4 * 1024 * 1024)
+///         .timeout(Duration::from_millis(250)),
+/// );
+/// ```
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchSize {
+    pub(crate) messages: usize,
+    pub(crate) bytes: usize,
+    pub(crate) timeout: Duration,
+}
+
+impl Default for BatchSize {
+    fn default() -> Self {
+        BatchSize {
+            messages: 500,
+            bytes: 1024 * 1024,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+impl BatchSize {
+    /// new
+    ///
+    /// Create the default batch size
+    ///
+    pub fn new() -> Self {
+        BatchSize::default()
+    }
+
+    /// messages
+    ///
+    /// Hand over the batch once it holds ``messages`` messages
+    ///
+    pub fn messages(mut self, messages: usize) -> Self {
+        self.messages = messages.max(1);
+        self
+    }
+
+    /// bytes
+    ///
+    /// Keep the keys and payloads of a batch under ``bytes`` bytes
+    ///
+    pub fn bytes(mut self, bytes: usize) -> Self {
+        self.bytes = bytes.max(1);
+        self
+    }
+
+    /// timeout
+    ///
+    /// Hand over the batch ``timeout`` after its first message was
+    /// received, even when it is not full
+    ///
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// consume_batches
+///
+/// Consume messages from kafka with an initialized
+/// [`LoggingConsumer`](crate::custom_context::LoggingConsumer),
+/// collect them into batches of the
+/// [`BatchSize`](crate::consume_batches::BatchSize) from the
+/// ``options`` and pass each batch to the ``handler``. Once the
+/// handler succeeds the highest offset of every partition in the
+/// batch is committed in one request using the
+/// [`CommitPolicy`](crate::commit_policy::CommitPolicy) from the
+/// ``options``. A failed batch holds the commits of its partitions at
+/// their lowest offset in the batch, so the next owner of a partition
+/// or a restart receives the batch again. A revoked partition drops
+/// its collected messages, handled offsets and failure hold, so the
+/// loop never hands over or commits a partition another member owns.
+/// The key workers, in-flight limits and dead letter queue of the
+/// ``options`` only apply to
+/// [`consume_messages`](crate::consume_messages::consume_messages).
+///
+/// With [`ConsumeOptions::max_messages`](crate::consume_options::ConsumeOptions::max_messages)
+/// the last batch is handed over as soon as it holds the last of
+/// those messages and the loop stops after it.
+///
+/// When the ``options`` shutdown token is cancelled the loop stops
+/// fetching, lets the batch being handled finish, commits the handled
+/// offsets synchronously and unsubscribes before returning. Messages
+/// collected for the next batch are not handled and are redelivered
+/// after a restart.
+///
+/// # Arguments
+///
+/// * `consumer` - initialized
+///   [`LoggingConsumer`](crate::custom_context::LoggingConsumer)
+///   that is already subscribed to a list of ``topics`` with a ``group_id``
+/// * `handler` - [`BatchHandler`](crate::message_handler::BatchHandler)
+///   with the business logic for each batch
+/// * `options` - [`ConsumeOptions`](crate::consume_options::ConsumeOptions)
+///   for the loop
+///
+/// # Errors
+///
+/// Returns [`Error::Commit`](crate::error::Error::Commit) when the
+/// offset commit fails and the handler error when the policy is
+/// [`CommitPolicy::AfterHandlerSuccess`](crate::commit_policy::CommitPolicy::AfterHandlerSuccess).
+/// In both cases the loop commits the handled offsets and
+/// unsubscribes first. Kafka errors from receiving messages are
+/// logged and the loop keeps consuming.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use async_trait::async_trait;
+/// use rdkafka::consumer::Consumer;
+/// use rdkafka::message::OwnedMessage;
+/// use rust_with_kafka_tls::consume_batches::consume_batches;
+/// use rust_with_kafka_tls::consume_batches::BatchSize;
+/// use rust_with_kafka_tls::consume_options::ConsumeOptions;
+/// use rust_with_kafka_tls::error::Result;
+/// use rust_with_kafka_tls::message_handler::BatchHandler;
+/// use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+///
+/// struct BulkInsert;
+///
+/// #[async_trait]
+/// impl BatchHandler for BulkInsert {
+///     async fn handle_batch(&self, messages: &[OwnedMessage]) -> Result<()> {
+///         println!("inserting {} rows", messages.len());
+///         Ok(())
+///     }
+/// }
+///
+/// # async fn run() {
+/// let consumer = KafkaTlsConfig::from_env("localhost:9093")
+///     .create_consumer("bulk-sink")
+///     .expect("Consumer creation failed");
+/// consumer.subscribe(&["testing"]).expect("subscribe");
+/// let options =
+///     ConsumeOptions::new().batch_size(BatchSize::new().messages(1000));
+/// consume_batches(&consumer, &BulkInsert, &options)
+///     .await
+///     .expect("consume");
+/// # }
+/// ```
+///
+pub async fn consume_batches<H: BatchHandler>(
+    consumer: &LoggingConsumer,
+    handler: &H,
+    options: &ConsumeOptions,
+) -> Result<()> {
+    let policy = options.commit_policy;
+    let size = options.batch_size;
+    let mut batch = Batch::default();
+    let mut handled = HandledOffsets::default();
+    let mut commit_interval = commit_interval(policy);
+    let mut remaining = options.max_messages;
+    let mut outcome = Ok(());
+    let revoked = RevokedPartitions::listen(consumer);
+    while remaining != Some(0) {
+        let deadline = batch.deadline;
+        let received = tokio::select! {
+            biased;
+            _ = options.shutdown.cancelled() => break,
+            _ = tick(&mut commit_interval) => {
+                if let Err(e) = commit_stored(consumer, CommitMode::Async) {
+                    outcome = Err(e);
+                    break;
+                }
+                continue;
+            }
+            _ = expire(deadline) => None,
+            received = consumer.recv() => Some(received),
+        };
+        // the rebalance callbacks run inside recv
+        let partitions = revoked.take();
+        batch.forget(&partitions);
+        handled.forget(&partitions);
+        let m = match received {
+            None => {
+                let flushed =
+                    flush(consumer, handler, policy, &mut batch, &mut handled)
+                        .await;
+                if let Err(e) = flushed {
+                    outcome = Err(e);
+                    break;
+                }
+                continue;
+            }
+            Some(Err(e)) => {
+                warn!("Kafka error: {}", e);
+                continue;
+            }
+            Some(Ok(m)) => m,
+        };
+        record_consumed(consumer, &m);
+        let bytes = m.key_len() + m.payload_len();
+        if !batch.messages.is_empty() && batch.bytes + bytes > size.bytes {
+            let flushed =
+                flush(consumer, handler, policy, &mut batch, &mut handled)
+                    .await;
+            if let Err(e) = flushed {
+                outcome = Err(e);
+                break;
+            }
+        }
+        batch.push(m.detach(), bytes, size.timeout);
+        remaining = remaining.map(|n| n - 1);
+        if batch.messages.len() >= size.messages
+            || batch.bytes >= size.bytes
+            || remaining == Some(0)
+        {
+            let flushed =
+                flush(consumer, handler, policy, &mut batch, &mut handled)
+                    .await;
+            if let Err(e) = flushed {
+                outcome = Err(e);
+                break;
+            }
+        }
+    }
+    info!("consumer shutting down");
+    handled.forget(&revoked.take());
+    let committed = if policy.stores_offsets() {
+        commit_stored(consumer, CommitMode::Sync)
+    } else {
+        handled.commit(consumer)
+    };
+    consumer.unsubscribe();
+    outcome.and(committed)
+}
+
+/// messages collected for the next batch
+#[derive(Default)]
+struct Batch {
+    messages: Vec<OwnedMessage>,
+    bytes: usize,
+    /// when the batch is handed over even if it is not full
+    deadline: Option<Instant>,
+}
+
+impl Batch {
+    fn push(&mut self, m: OwnedMessage, bytes: usize, timeout: Duration) {
+        if self.messages.is_empty() {
+            self.deadline = Some(Instant::now() + timeout);
+        }
+        self.messages.push(m);
+        self.bytes += bytes;
+    }
+
+    /// drop the collected messages of revoked ``partitions``
+    fn forget(&mut self, partitions: &[Partition]) {
+        if partitions.is_empty() {
+            return;
+        }
+        self.messages.retain(|m| {
+            !partitions.iter().any(|(topic, partition)| {
+                m.topic() == topic && m.partition() == *partition
+            })
+        });
+        self.bytes = self
+            .messages
+            .iter()
+            .map(|m| {
+                m.key().map_or(0, <[u8]>::len)
+                    + m.payload().map_or(0, <[u8]>::len)
+            })
+            .sum();
+        if self.messages.is_empty() {
+            self.deadline = None;
+        }
+    }
+}
+
+/// wait for the batch deadline, forever without one
+async fn expire(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// hand the batch to the handler and commit the highest offset of
+/// each of its partitions once it succeeds, a failed batch holds the
+/// commits of its partitions at their lowest offset in the batch
+async fn flush<H: BatchHandler>(
+    consumer: &LoggingConsumer,
+    handler: &H,
+    policy: CommitPolicy,
+    batch: &mut Batch,
+    handled: &mut HandledOffsets,
+) -> Result<()> {
+    let messages = std::mem::take(batch).messages;
+    if messages.is_empty() {
+        return Ok(());
+    }
+    // lowest and highest offset of every partition in the batch
+    let mut ranges = BTreeMap::new();
+    for m in &messages {
+        let (lowest, highest) = ranges
+            .entry((m.topic(), m.partition()))
+            .or_insert((m.offset(), m.offset()));
+        *lowest = m.offset().min(*lowest);
+        *highest = m.offset().max(*highest);
+    }
+    if let Err(e) = handler.handle_batch(&messages).await {
+        let first = &messages[0];
+        warn!(
+            topic = first.topic(),
+            partition = first.partition(),
+            offset = first.offset(),
+            messages = messages.len();
+            "Batch handler failed, holding the partition commits: {e}"
+        );
+        if policy == CommitPolicy::AfterHandlerSuccess {
+            return Err(e);
+        }
+        for ((topic, partition), (lowest, _)) in ranges {
+            handled.fail(topic, partition, lowest);
+        }
+        return Ok(());
+    }
+    let offsets: Vec<(&str, i32, i64)> = ranges
+        .into_iter()
+        .filter(|((topic, partition), (lowest, _))| {
+            handled.committable(topic, *partition, *lowest)
+        })
+        .map(|((topic, partition), (_, highest))| (topic, partition, highest))
+        .collect();
+    if offsets.is_empty() {
+        return Ok(());
+    }
+    commit_all(consumer, &offsets, policy)?;
+    for (topic, partition, offset) in offsets {
+        handled.insert(topic, partition, offset);
+    }
+    Ok(())
+}
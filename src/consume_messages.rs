@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+
+use log::info;
+use log::warn;
+use rdkafka::consumer::CommitMode;
+use rdkafka::consumer::Consumer;
+use rdkafka::error::RDKafkaErrorCode;
+use rdkafka::message::BorrowedMessage;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use rdkafka::topic_partition_list::Offset;
+use rdkafka::topic_partition_list::TopicPartitionList;
+use tokio::time;
+use tokio::time::Instant;
+use tokio::time::Interval;
+
+use crate::commit_policy::CommitPolicy;
+use crate::consume_options::ConsumeOptions;
+use crate::consume_partitions::Partition;
+use crate::consume_partitions::PartitionListener;
+use crate::custom_context::LoggingConsumer;
+use crate::error::Error;
+use crate::error::Result;
+use crate::keyed_workers::consume_keyed;
+use crate::message_handler::MessageHandler;
+
+/// consume_messages
+///
+/// Consume messages from kafka with an initialized
+/// [`LoggingConsumer`](crate::custom_context::LoggingConsumer) and
+/// pass each one to the ``handler``. Offsets are committed after the
+/// handler succeeds using the
+/// [`CommitPolicy`](crate::commit_policy::CommitPolicy) from the
+/// ``options``. Handler failures are logged and hold the commits of
+/// their partition: nothing at or after the failed offset is committed,
+/// so a restart or the next owner of the partition redelivers the
+/// failed message and everything after it. With a
+/// [`DeadLetterQueue`](crate::dead_letter::DeadLetterQueue) in the
+/// options the handler is retried and the message republished before
+/// it counts as failed. Received messages are counted in the consumer
+/// context [`Metrics`](crate::metrics::Metrics) when it has them.
+/// A revoked partition drops its handled offsets and failure hold, so
+/// the loop never commits a partition another member owns.
+///
+/// With [`ConsumeOptions::key_workers`](crate::consume_options::ConsumeOptions::key_workers)
+/// messages are handled concurrently by a pool of workers that keeps
+/// the order of each key, and a partition offset is committed once
+/// every earlier message of the partition is done.
+///
+/// With [`ConsumeOptions::max_messages`](crate::consume_options::ConsumeOptions::max_messages)
+/// the loop stops after handing that many messages to the handler.
+///
+/// When the ``options`` shutdown token is cancelled the loop stops
+/// fetching, lets the in-flight handler finish, commits the handled
+/// offsets synchronously and unsubscribes before returning.
+///
+/// # Arguments
+///
+/// * `consumer` - initialized
+///   [`LoggingConsumer`](crate::custom_context::LoggingConsumer)
+///   that is already subscribed to a list of ``topics`` with a ``group_id``
+/// * `handler` - [`MessageHandler`](crate::message_handler::MessageHandler)
+///   with the business logic for each message
+/// * `options` - [`ConsumeOptions`](crate::consume_options::ConsumeOptions)
+///   for the loop
+///
+/// # Errors
+///
+/// Returns [`Error::Commit`](crate::error::Error::Commit) when the
+/// offset commit fails and the handler error when the policy is
+/// [`CommitPolicy::AfterHandlerSuccess`](crate::commit_policy::CommitPolicy::AfterHandlerSuccess).
+/// In both cases the loop commits the handled offsets and
+/// unsubscribes first. Kafka errors from receiving messages are
+/// logged and the loop keeps consuming.
+///
+pub async fn consume_messages<H: MessageHandler>(
+    consumer: &LoggingConsumer,
+    handler: &H,
+    options: &ConsumeOptions,
+) -> Result<()> {
+    if let Some(workers) = options.key_workers {
+        return consume_keyed(consumer, handler, options, workers).await;
+    }
+    let policy = options.commit_policy;
+    let revoked = RevokedPartitions::listen(consumer);
+    let mut handled = HandledOffsets::default();
+    let mut commit_interval = commit_interval(policy);
+    let mut remaining = options.max_messages;
+    let mut outcome = Ok(());
+    while remaining != Some(0) {
+        let received = tokio::select! {
+            biased;
+            _ = options.shutdown.cancelled() => break,
+            _ = tick(&mut commit_interval) => {
+                if let Err(e) = commit_stored(consumer, CommitMode::Async) {
+                    outcome = Err(e);
+                    break;
+                }
+                continue;
+            }
+            received = consumer.recv() => received,
+        };
+        // the rebalance callbacks run inside recv
+        handled.forget(&revoked.take());
+        let m = match received {
+            Err(e) => {
+                warn!("Kafka error: {}", e);
+                continue;
+            }
+            Ok(m) => m,
+        };
+        record_consumed(consumer, &m);
+        remaining = remaining.map(|n| n - 1);
+        let m = m.detach();
+        match handle_message(handler, options, &m).await {
+            Ok(()) => {
+                if !handled.committable(m.topic(), m.partition(), m.offset()) {
+                    continue;
+                }
+                if let Err(e) = commit_handled(consumer, &m, policy) {
+                    outcome = Err(e);
+                    break;
+                }
+                handled.insert(m.topic(), m.partition(), m.offset());
+            }
+            Err(e) => {
+                warn!(
+                    topic = m.topic(),
+                    partition = m.partition(),
+                    offset = m.offset();
+                    "Handler failed, holding the partition commits: {e}"
+                );
+                if policy == CommitPolicy::AfterHandlerSuccess {
+                    outcome = Err(e);
+                    break;
+                }
+                handled.fail(m.topic(), m.partition(), m.offset());
+            }
+        };
+    }
+    info!("consumer shutting down");
+    handled.forget(&revoked.take());
+    let committed = if policy.stores_offsets() {
+        commit_stored(consumer, CommitMode::Sync)
+    } else {
+        handled.commit(consumer)
+    };
+    consumer.unsubscribe();
+    outcome.and(committed)
+}
+
+/// partitions revoked by the rebalance callbacks of a consumer since
+/// its consume loop last took them
+#[derive(Default)]
+pub(crate) struct RevokedPartitions(Mutex<Vec<Partition>>);
+
+impl RevokedPartitions {
+    /// collect the partitions ``consumer`` revokes while the returned
+    /// value lives
+    pub(crate) fn listen(consumer: &LoggingConsumer) -> Arc<Self> {
+        let revoked = Arc::new(RevokedPartitions::default());
+        let listener: Weak<dyn PartitionListener> =
+            Arc::downgrade(&revoked) as _;
+        consumer.context().listen_partitions(listener);
+        revoked
+    }
+
+    /// partitions revoked since the last call
+    pub(crate) fn take(&self) -> Vec<Partition> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl PartitionListener for RevokedPartitions {
+    fn assigning(&self, _partitions: &[Partition]) {}
+
+    fn revoking(&self, partitions: &[Partition]) {
+        self.0.lock().unwrap().extend_from_slice(partitions);
+    }
+}
+
+/// count a received message in the consumer context
+/// [`Metrics`](crate::metrics::Metrics) when it has them
+pub(crate) fn record_consumed(
+    consumer: &LoggingConsumer,
+    m: &BorrowedMessage<'_>,
+) {
+    if let Some(metrics) = consumer.context().shared_metrics() {
+        metrics.message_consumed(m.topic(), m.key_len() + m.payload_len());
+    }
+}
+
+/// run the ``handler``, through the dead letter queue in the
+/// ``options`` when they have one
+pub(crate) async fn handle_message<H: MessageHandler>(
+    handler: &H,
+    options: &ConsumeOptions,
+    m: &OwnedMessage,
+) -> Result<()> {
+    match &options.dead_letter {
+        Some(dead_letter) => dead_letter.handle(handler, m).await,
+        None => handler.handle(m).await,
+    }
+}
+
+/// commit or store the offset of a successfully handled message
+pub(crate) fn commit_handled(
+    consumer: &LoggingConsumer,
+    m: &OwnedMessage,
+    policy: CommitPolicy,
+) -> Result<()> {
+    commit_through(consumer, m.topic(), m.partition(), m.offset(), policy)
+}
+
+/// commit or store a ``topic`` ``partition`` up to and including
+/// ``offset``
+pub(crate) fn commit_through(
+    consumer: &LoggingConsumer,
+    topic: &str,
+    partition: i32,
+    offset: i64,
+    policy: CommitPolicy,
+) -> Result<()> {
+    commit_all(consumer, &[(topic, partition, offset)], policy)
+}
+
+/// commit or store every ``(topic, partition, offset)`` up to and
+/// including its offset, in one commit request
+pub(crate) fn commit_all(
+    consumer: &LoggingConsumer,
+    offsets: &[(&str, i32, i64)],
+    policy: CommitPolicy,
+) -> Result<()> {
+    let mode = match policy {
+        CommitPolicy::Auto | CommitPolicy::Periodic(_) => {
+            for (topic, partition, offset) in offsets {
+                consumer
+                    .store_offset(topic, *partition, *offset)
+                    .map_err(Error::Commit)?;
+            }
+            return Ok(());
+        }
+        CommitPolicy::PerMessageSync | CommitPolicy::AfterHandlerSuccess => {
+            CommitMode::Sync
+        }
+        CommitPolicy::PerMessageAsync => CommitMode::Async,
+    };
+    let mut tpl = TopicPartitionList::new();
+    for (topic, partition, offset) in offsets {
+        tpl.add_partition_offset(topic, *partition, Offset::Offset(offset + 1))
+            .map_err(Error::Commit)?;
+    }
+    consumer.commit(&tpl, mode).map_err(Error::Commit)
+}
+
+/// ``true`` for a commit that failed because the group is
+/// rebalancing or the coordinator is not reachable, the offsets stay
+/// uncommitted until a later commit covers them or the next owner of
+/// the partition handles the messages again
+pub(crate) fn commit_retriable(e: &Error) -> bool {
+    let code = match e {
+        Error::Commit(e) => e.rdkafka_error_code(),
+        _ => return false,
+    };
+    matches!(
+        code,
+        Some(
+            RDKafkaErrorCode::RebalanceInProgress
+                | RDKafkaErrorCode::IllegalGeneration
+                | RDKafkaErrorCode::UnknownMemberId
+                | RDKafkaErrorCode::NotCoordinator
+                | RDKafkaErrorCode::CoordinatorNotAvailable
+                | RDKafkaErrorCode::CoordinatorLoadInProgress
+                | RDKafkaErrorCode::RequestTimedOut
+                | RDKafkaErrorCode::BrokerTransportFailure
+                | RDKafkaErrorCode::AllBrokersDown
+        )
+    )
+}
+
+/// commit the offsets stored after successful handlers,
+/// having nothing stored yet is not an error
+pub(crate) fn commit_stored(
+    consumer: &LoggingConsumer,
+    mode: CommitMode,
+) -> Result<()> {
+    match consumer.commit_consumer_state(mode) {
+        Err(e)
+            if e.rdkafka_error_code() == Some(RDKafkaErrorCode::NoOffset) =>
+        {
+            Ok(())
+        }
+        committed => committed.map_err(Error::Commit),
+    }
+}
+
+/// interval of a [`CommitPolicy::Periodic`](crate::commit_policy::CommitPolicy::Periodic)
+/// policy, starting one period from now
+pub(crate) fn commit_interval(policy: CommitPolicy) -> Option<Interval> {
+    match policy {
+        CommitPolicy::Periodic(every) => {
+            Some(time::interval_at(Instant::now() + every, every))
+        }
+        _ => None,
+    }
+}
+
+/// wait for the next periodic commit, forever without an interval
+pub(crate) async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// next offset to commit for every partition with a handled message
+/// and the lowest failed offset of every partition holding its commits
+#[derive(Default)]
+pub(crate) struct HandledOffsets {
+    offsets: HashMap<(String, i32), i64>,
+    failed: HashMap<(String, i32), i64>,
+}
+
+impl HandledOffsets {
+    pub(crate) fn insert(&mut self, topic: &str, partition: i32, offset: i64) {
+        self.offsets
+            .insert((topic.to_string(), partition), offset + 1);
+    }
+
+    /// hold the commits of a partition at a failed ``offset``, so a
+    /// restart redelivers it
+    pub(crate) fn fail(&mut self, topic: &str, partition: i32, offset: i64) {
+        let failed = self
+            .failed
+            .entry((topic.to_string(), partition))
+            .or_insert(offset);
+        *failed = offset.min(*failed);
+    }
+
+    /// true when handled messages from ``offset`` on may be committed:
+    /// the partition has no failed message or ``offset`` is the
+    /// redelivered failed message, which releases the hold
+    pub(crate) fn committable(
+        &mut self,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+    ) -> bool {
+        let key = (topic.to_string(), partition);
+        match self.failed.get(&key) {
+            Some(failed) if offset > *failed => false,
+            Some(_) => {
+                self.failed.remove(&key);
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// synchronously commit the handled offsets
+    pub(crate) fn commit(&self, consumer: &LoggingConsumer) -> Result<()> {
+        commit_offsets(consumer, &self.offsets)
+    }
+
+    /// synchronously commit the handled offsets of ``partitions`` and
+    /// forget them, before the partitions are revoked
+    pub(crate) fn release(
+        &mut self,
+        consumer: &LoggingConsumer,
+        partitions: &[Partition],
+    ) -> Result<()> {
+        commit_offsets(consumer, &self.forget(partitions))
+    }
+
+    /// forget the handled offsets and failure holds of revoked
+    /// ``partitions`` without committing, the next owner commits them
+    /// from now on, and return the forgotten offsets
+    pub(crate) fn forget(
+        &mut self,
+        partitions: &[Partition],
+    ) -> HashMap<Partition, i64> {
+        let mut forgotten = HashMap::new();
+        for key in partitions {
+            self.failed.remove(key);
+            if let Some(offset) = self.offsets.remove(key) {
+                forgotten.insert(key.clone(), offset);
+            }
+        }
+        forgotten
+    }
+}
+
+/// synchronously commit the next ``offsets`` to consume
+fn commit_offsets(
+    consumer: &LoggingConsumer,
+    offsets: &HashMap<(String, i32), i64>,
+) -> Result<()> {
+    if offsets.is_empty() {
+        return Ok(());
+    }
+    let mut tpl = TopicPartitionList::new();
+    for ((topic, partition), offset) in offsets {
+        tpl.add_partition_offset(topic, *partition, Offset::Offset(*offset))
+            .map_err(Error::Commit)?;
+    }
+    consumer
+        .commit(&tpl, CommitMode::Sync)
+        .map_err(Error::Commit)
+}
@@ -0,0 +1,135 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::backpressure::InFlightLimits;
+use crate::commit_policy::CommitPolicy;
+use crate::consume_batches::BatchSize;
+use crate::dead_letter::DeadLetterQueue;
+
+/// ConsumeOptions
+///
+/// Settings for the consume loop in
+/// [`consume_messages`](crate::consume_messages::consume_messages)
+/// and the batch loop in
+/// [`consume_batches`](crate::consume_batches::consume_batches)
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::consume_options::ConsumeOptions;
+/// use tokio_util::sync::CancellationToken;
+///
+/// let shutdown = CancellationToken::new();
+/// let options = ConsumeOptions::new().shutdown(shutdown.clone());
+/// // later: stop fetching, commit and unsubscribe
+/// shutdown.cancel();
+/// ```
+///
+#[derive(Clone, Debug, Default)]
+pub struct ConsumeOptions {
+    pub(crate) shutdown: CancellationToken,
+    pub(crate) commit_policy: CommitPolicy,
+    pub(crate) dead_letter: Option<DeadLetterQueue>,
+    pub(crate) key_workers: Option<usize>,
+    pub(crate) in_flight_limits: InFlightLimits,
+    pub(crate) batch_size: BatchSize,
+    pub(crate) max_messages: Option<usize>,
+}
+
+impl ConsumeOptions {
+    /// new
+    ///
+    /// Create options for a loop that consumes until an error
+    ///
+    pub fn new() -> Self {
+        ConsumeOptions::default()
+    }
+
+    /// shutdown
+    ///
+    /// Stop the loop when ``token`` is cancelled. The message being
+    /// handled finishes, handled offsets are committed synchronously
+    /// and the consumer unsubscribes before the loop returns.
+    ///
+    pub fn shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    /// commit_policy
+    ///
+    /// How handled offsets are committed
+    /// (default: [`CommitPolicy::PerMessageAsync`](crate::commit_policy::CommitPolicy::PerMessageAsync))
+    ///
+    pub fn commit_policy(mut self, policy: CommitPolicy) -> Self {
+        self.commit_policy = policy;
+        self
+    }
+
+    /// dead_letter
+    ///
+    /// Retry failing handlers and republish messages that still fail
+    /// to the [`DeadLetterQueue`](crate::dead_letter::DeadLetterQueue)
+    /// topic. Dead-lettered messages count as handled and their
+    /// offsets are committed.
+    ///
+    pub fn dead_letter(mut self, dead_letter: DeadLetterQueue) -> Self {
+        self.dead_letter = Some(dead_letter);
+        self
+    }
+
+    /// key_workers
+    ///
+    /// Handle messages concurrently on ``workers`` workers instead of
+    /// one at a time. Each message goes to the worker picked by
+    /// [`worker_index`](crate::keyed_workers::worker_index), so
+    /// messages with the same key (or without a key in the same
+    /// partition) are handled in offset order. A partition offset is
+    /// only committed once every earlier message of the partition is
+    /// done, a failed message holds the commits of its partition.
+    ///
+    pub fn key_workers(mut self, workers: usize) -> Self {
+        self.key_workers = Some(workers.max(1));
+        self
+    }
+
+    /// in_flight_limits
+    ///
+    /// Pause the assigned partitions while the
+    /// [`key_workers`](crate::consume_options::ConsumeOptions::key_workers)
+    /// pool holds more received messages than the
+    /// [`InFlightLimits`](crate::backpressure::InFlightLimits) allow
+    /// (default: no limits). The loop without key workers handles one
+    /// message at a time and ignores the limits.
+    ///
+    pub fn in_flight_limits(mut self, limits: InFlightLimits) -> Self {
+        self.in_flight_limits = limits;
+        self
+    }
+
+    /// batch_size
+    ///
+    /// How many messages the batch loop in
+    /// [`consume_batches`](crate::consume_batches::consume_batches)
+    /// collects before handing them to its handler
+    /// (default: [`BatchSize::new`](crate::consume_batches::BatchSize::new))
+    ///
+    pub fn batch_size(mut self, size: BatchSize) -> Self {
+        self.batch_size = size;
+        self
+    }
+
+    /// max_messages
+    ///
+    /// Stop the loop once ``messages`` messages were handed to the
+    /// handler, for example to replay a fixed number of messages.
+    /// The loop waits for those handlers, commits and unsubscribes as
+    /// on shutdown. Used by
+    /// [`consume_messages`](crate::consume_messages::consume_messages),
+    /// [`consume_batches`](crate::consume_batches::consume_batches)
+    /// and [`consume_partitions`](crate::consume_partitions::consume_partitions).
+    ///
+    pub fn max_messages(mut self, messages: usize) -> Self {
+        self.max_messages = Some(messages);
+        self
+    }
+}
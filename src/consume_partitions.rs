@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::sync::mpsc as std_mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+
+use log::debug;
+use log::info;
+use log::warn;
+use rdkafka::consumer::stream_consumer::StreamPartitionQueue;
+use rdkafka::consumer::CommitMode;
+use rdkafka::consumer::Consumer;
+use rdkafka::error::KafkaResult;
+use rdkafka::message::BorrowedMessage;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use tokio::runtime::Handle;
+use tokio::runtime::RuntimeFlavor;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::commit_policy::CommitPolicy;
+use crate::consume_messages::commit_handled;
+use crate::consume_messages::commit_interval;
+use crate::consume_messages::commit_retriable;
+use crate::consume_messages::commit_stored;
+use crate::consume_messages::handle_message;
+use crate::consume_messages::record_consumed;
+use crate::consume_messages::tick;
+use crate::consume_messages::HandledOffsets;
+use crate::consume_options::ConsumeOptions;
+use crate::custom_context::CustomContext;
+use crate::custom_context::LoggingConsumer;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message_handler::MessageHandler;
+
+/// topic and partition
+pub(crate) type Partition = (String, i32);
+
+/// receiver of the assignment changes from the
+/// [`CustomContext`](crate::custom_context::CustomContext) rebalance
+/// callbacks, called on the thread polling the consumer before
+/// librdkafka applies the change
+pub(crate) trait PartitionListener: Send + Sync {
+    /// partitions about to be assigned, nothing was fetched for them
+    fn assigning(&self, partitions: &[Partition]);
+
+    /// partitions about to be revoked, the revoke completes once this
+    /// returns
+    fn revoking(&self, partitions: &[Partition]);
+}
+
+/// consume_partitions
+///
+/// Consume messages from kafka with one task per assigned partition,
+/// so a slow ``handler`` only stalls the partition it is handling.
+/// Each task reads its own split partition queue and handles the
+/// messages of that partition one at a time, keeping the partition
+/// order. Tasks are started and stopped by the rebalance callbacks in
+/// [`CustomContext`](crate::custom_context::CustomContext): the queue
+/// of an assigned partition is split and its task started before the
+/// assignment takes effect, and a revoke waits until the task of each
+/// revoked partition finished its in-flight message and commit. The
+/// waiting callback runs on the consumer loop, so the loop needs the
+/// multi-thread tokio runtime. The loop keeps polling the main
+/// consumer queue to serve rebalances and hands messages fetched
+/// before a partition was split to the partition task.
+///
+/// Offsets are committed after the handler succeeds with the
+/// [`CommitPolicy`](crate::commit_policy::CommitPolicy) from the
+/// ``options``, the same way
+/// [`consume_messages`](crate::consume_messages::consume_messages)
+/// commits them. A failed handler holds the commits of its partition
+/// at the failed offset, the next owner of the partition or a restart
+/// receives the message again.
+///
+/// With [`ConsumeOptions::max_messages`](crate::consume_options::ConsumeOptions::max_messages)
+/// the loop stops after the tasks handed that many messages to the
+/// handler.
+///
+/// When the ``options`` shutdown token is cancelled every task
+/// finishes its in-flight message, the handled offsets are committed
+/// synchronously and the consumer unsubscribes before returning.
+///
+/// # Arguments
+///
+/// * `consumer` - initialized
+///   [`LoggingConsumer`](crate::custom_context::LoggingConsumer)
+///   that is already subscribed to a list of ``topics`` with a
+///   ``group_id``, shared with the partition tasks
+/// * `handler` - [`MessageHandler`](crate::message_handler::MessageHandler)
+///   with the business logic for each message, called concurrently
+///   for different partitions
+/// * `options` - [`ConsumeOptions`](crate::consume_options::ConsumeOptions)
+///   for the loop
+///
+/// # Errors
+///
+/// Returns [`Error::Commit`](crate::error::Error::Commit) when an
+/// offset commit fails and the handler error when the policy is
+/// [`CommitPolicy::AfterHandlerSuccess`](crate::commit_policy::CommitPolicy::AfterHandlerSuccess).
+/// In both cases every partition task is stopped, the handled
+/// offsets are committed and the consumer unsubscribes first. Commits
+/// that fail during a rebalance or while the coordinator is not
+/// reachable are logged and leave the offset uncommitted.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::sync::Arc;
+/// use rdkafka::consumer::Consumer;
+/// use rust_with_kafka_tls::consume_and_print::PrintHandler;
+/// use rust_with_kafka_tls::consume_options::ConsumeOptions;
+/// use rust_with_kafka_tls::consume_partitions::consume_partitions;
+/// use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+///
+/// # async fn run() {
+/// let consumer = KafkaTlsConfig::from_env("cluster-0-broker-0.redten.io:32151")
+///     .create_consumer("rust-consumer-testing")
+///     .expect("Consumer creation failed");
+/// consumer.subscribe(&["testing"]).expect("Can't subscribe");
+/// consume_partitions(
+///     Arc::new(consumer),
+///     Arc::new(PrintHandler),
+///     &ConsumeOptions::new(),
+/// )
+/// .await
+/// .expect("consumer failed");
+/// # }
+/// ```
+///
+pub async fn consume_partitions<H: MessageHandler + 'static>(
+    consumer: Arc<LoggingConsumer>,
+    handler: Arc<H>,
+    options: &ConsumeOptions,
+) -> Result<()> {
+    let policy = options.commit_policy;
+    let mut commit_interval = commit_interval(policy);
+    let shared = Arc::new(Shared {
+        consumer: consumer.clone(),
+        handler,
+        options: options.clone(),
+        handled: Mutex::new(HandledOffsets::default()),
+        remaining: Mutex::new(options.max_messages),
+        error: Mutex::new(None),
+        finished: CancellationToken::new(),
+        stop: CancellationToken::new(),
+    });
+    let tasks = Arc::new(PartitionTasks {
+        shared: shared.clone(),
+        runtime: Handle::current(),
+        tasks: Mutex::new(HashMap::new()),
+    });
+    let listener: Weak<dyn PartitionListener> = Arc::downgrade(&tasks) as _;
+    consumer.context().listen_partitions(listener);
+    // partitions assigned before the loop listened for rebalances
+    let mut outcome = match consumer.assignment() {
+        Ok(assigned) => {
+            for e in assigned.elements() {
+                tasks.assign(e.topic(), e.partition());
+            }
+            Ok(())
+        }
+        Err(e) => Err(Error::Kafka(e)),
+    };
+    while outcome.is_ok() {
+        tokio::select! {
+            biased;
+            _ = options.shutdown.cancelled() => break,
+            _ = shared.finished.cancelled() => break,
+            _ = tick(&mut commit_interval) => {
+                if let Err(e) = commit_stored(&consumer, CommitMode::Async) {
+                    outcome = Err(e);
+                }
+            }
+            received = consumer.recv() => match received {
+                Err(e) => warn!("Kafka error: {}", e),
+                Ok(m) => tasks.dispatch(m),
+            },
+        };
+    }
+    info!("partition consumer shutting down");
+    let stopped = tasks.stop().await;
+    // later rebalances find no listener
+    drop(tasks);
+    if let Some(e) = shared.error.lock().unwrap().take() {
+        outcome = outcome.and(Err(e));
+    }
+    outcome = outcome.and(stopped);
+    let committed = if policy.stores_offsets() {
+        commit_stored(&consumer, CommitMode::Sync)
+    } else {
+        let handled = std::mem::take(&mut *shared.handled.lock().unwrap());
+        handled.commit(&consumer)
+    };
+    consumer.unsubscribe();
+    outcome.and(committed)
+}
+
+/// state shared by the loop and every partition task
+struct Shared<H> {
+    consumer: Arc<LoggingConsumer>,
+    handler: Arc<H>,
+    options: ConsumeOptions,
+    /// next offset to commit for every partition with a handled message
+    handled: Mutex<HandledOffsets>,
+    /// messages left to hand to the handler with ``max_messages``
+    remaining: Mutex<Option<usize>>,
+    /// first error of a task that stops the loop
+    error: Mutex<Option<Error>>,
+    /// cancelled by a task that stops the loop, with an error or after
+    /// taking the last of ``max_messages``
+    finished: CancellationToken,
+    /// cancelled when the loop shuts down
+    stop: CancellationToken,
+}
+
+impl<H> Shared<H> {
+    /// count a message against ``max_messages``, ``false`` once they
+    /// were all handed out
+    fn take_message(&self) -> bool {
+        let mut remaining = self.remaining.lock().unwrap();
+        match remaining.as_mut() {
+            None => true,
+            Some(0) => false,
+            Some(left) => {
+                *left -= 1;
+                if *left == 0 {
+                    self.finished.cancel();
+                }
+                true
+            }
+        }
+    }
+
+    /// keep the first error and stop the loop
+    fn fail(&self, e: Error) {
+        self.error.lock().unwrap().get_or_insert(e);
+        self.finished.cancel();
+    }
+}
+
+/// task consuming one partition
+struct PartitionTask {
+    /// messages for the partition received by the main consumer queue
+    buffered: UnboundedSender<OwnedMessage>,
+    revoked: CancellationToken,
+    /// disconnected once the task finished
+    done: std_mpsc::Receiver<()>,
+    handle: JoinHandle<()>,
+}
+
+/// running partition tasks by topic and partition
+struct PartitionTasks<H> {
+    shared: Arc<Shared<H>>,
+    /// runtime of the loop, the rebalance callbacks start tasks on it
+    runtime: Handle,
+    tasks: Mutex<HashMap<Partition, PartitionTask>>,
+}
+
+impl<H: MessageHandler + 'static> PartitionTasks<H> {
+    /// split the partition queue and start a task for it
+    fn assign(&self, topic: &str, partition: i32) {
+        let key = (topic.to_string(), partition);
+        let mut tasks = self.tasks.lock().unwrap();
+        if tasks.contains_key(&key) {
+            return;
+        }
+        let queue =
+            self.shared.consumer.split_partition_queue(topic, partition);
+        if queue.is_none() {
+            warn!(
+                topic = topic,
+                partition = partition;
+                "partition queue is not available, \
+                messages arrive through the main queue"
+            );
+        }
+        info!(topic = topic, partition = partition; "starting partition task");
+        let (buffered, receiver) = mpsc::unbounded_channel();
+        let (finished, done) = std_mpsc::channel();
+        let revoked = CancellationToken::new();
+        let shared = self.shared.clone();
+        let task_revoked = revoked.clone();
+        let handle = self.runtime.spawn(async move {
+            let result =
+                consume_partition(&shared, queue, receiver, &task_revoked)
+                    .await;
+            if let Err(e) = result {
+                shared.fail(e);
+            }
+            drop(finished);
+        });
+        tasks.insert(
+            key,
+            PartitionTask {
+                buffered,
+                revoked,
+                done,
+                handle,
+            },
+        );
+    }
+
+    /// hand a message from the main consumer queue to its partition task
+    fn dispatch(&self, m: BorrowedMessage<'_>) {
+        record_consumed(&self.shared.consumer, &m);
+        let key = (m.topic().to_string(), m.partition());
+        match self.tasks.lock().unwrap().get(&key) {
+            Some(task) => {
+                // a stopped task leaves the message uncommitted
+                let _ = task.buffered.send(m.detach());
+            }
+            None => debug!(
+                topic = m.topic(),
+                partition = m.partition(),
+                offset = m.offset();
+                "no task for the partition, leaving the message uncommitted"
+            ),
+        }
+    }
+
+    /// stop every task and wait for their in-flight messages
+    async fn stop(&self) -> Result<()> {
+        self.shared.stop.cancel();
+        let tasks: Vec<_> = self.tasks.lock().unwrap().drain().collect();
+        let mut outcome = Ok(());
+        for (_, task) in tasks {
+            if let Err(e) = task.handle.await {
+                if outcome.is_ok() {
+                    outcome = Err(Error::Handler(format!(
+                        "partition task failed: {e}"
+                    )));
+                }
+            }
+        }
+        outcome
+    }
+}
+
+impl<H: MessageHandler + 'static> PartitionListener for PartitionTasks<H> {
+    fn assigning(&self, partitions: &[Partition]) {
+        for (topic, partition) in partitions {
+            self.assign(topic, *partition);
+        }
+    }
+
+    fn revoking(&self, partitions: &[Partition]) {
+        let revoked: Vec<_> = {
+            let mut tasks = self.tasks.lock().unwrap();
+            partitions
+                .iter()
+                .filter_map(|key| tasks.remove(key).map(|task| (key, task)))
+                .collect()
+        };
+        for ((topic, partition), task) in &revoked {
+            info!(
+                topic = topic,
+                partition = *partition;
+                "stopping partition task"
+            );
+            task.revoked.cancel();
+        }
+        let wait = || {
+            for (_, task) in &revoked {
+                // only fails once the task finished
+                let _ = task.done.recv();
+            }
+        };
+        match Handle::try_current().map(|runtime| runtime.runtime_flavor()) {
+            // the callback holds a runtime worker, hand its other tasks
+            // to a new worker while waiting
+            Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(wait),
+            Ok(_) => warn!(
+                "revoking partitions without waiting for their tasks, \
+                partition tasks need the multi-thread runtime"
+            ),
+            Err(_) => wait(),
+        }
+        // commit before the next owner reads the committed offsets
+        let partitions: Vec<_> =
+            revoked.iter().map(|(key, _)| (*key).clone()).collect();
+        let committed = if self.shared.options.commit_policy.stores_offsets() {
+            commit_stored(&self.shared.consumer, CommitMode::Sync)
+        } else {
+            self.shared
+                .handled
+                .lock()
+                .unwrap()
+                .release(&self.shared.consumer, &partitions)
+        };
+        if let Err(e) = committed {
+            warn!("commit before the revoke failed: {e}");
+        }
+    }
+}
+
+/// handle the messages of one partition in offset order until it is
+/// revoked or the loop stops
+async fn consume_partition<H: MessageHandler>(
+    shared: &Shared<H>,
+    queue: Option<StreamPartitionQueue<CustomContext>>,
+    mut buffered: UnboundedReceiver<OwnedMessage>,
+    revoked: &CancellationToken,
+) -> Result<()> {
+    let policy = shared.options.commit_policy;
+    loop {
+        let m = tokio::select! {
+            biased;
+            _ = revoked.cancelled() => break,
+            _ = shared.stop.cancelled() => break,
+            // fetched before the partition was split, so older than
+            // anything in the partition queue
+            Some(m) = buffered.recv() => m,
+            received = next(&queue) => match received {
+                Err(e) => {
+                    warn!("Kafka error: {}", e);
+                    continue;
+                }
+                Ok(m) => {
+                    record_consumed(&shared.consumer, &m);
+                    m.detach()
+                }
+            },
+        };
+        if !shared.take_message() {
+            break;
+        }
+        match handle_message(&*shared.handler, &shared.options, &m).await {
+            Ok(()) => {
+                let committable = shared.handled.lock().unwrap().committable(
+                    m.topic(),
+                    m.partition(),
+                    m.offset(),
+                );
+                if !committable {
+                    continue;
+                }
+                match commit_handled(&shared.consumer, &m, policy) {
+                    Err(e) if commit_retriable(&e) => {
+                        warn!(
+                            topic = m.topic(),
+                            partition = m.partition(),
+                            offset = m.offset();
+                            "Commit failed, leaving the offset uncommitted: {e}"
+                        );
+                        continue;
+                    }
+                    committed => committed?,
+                }
+                shared.handled.lock().unwrap().insert(
+                    m.topic(),
+                    m.partition(),
+                    m.offset(),
+                );
+            }
+            Err(e) => {
+                warn!(
+                    topic = m.topic(),
+                    partition = m.partition(),
+                    offset = m.offset();
+                    "Handler failed, holding the partition commits: {e}"
+                );
+                if policy == CommitPolicy::AfterHandlerSuccess {
+                    return Err(e);
+                }
+                shared.handled.lock().unwrap().fail(
+                    m.topic(),
+                    m.partition(),
+                    m.offset(),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// next message of the partition queue, forever without a queue
+async fn next(
+    queue: &Option<StreamPartitionQueue<CustomContext>>,
+) -> KafkaResult<BorrowedMessage<'_>> {
+    match queue {
+        Some(queue) => queue.recv().await,
+        None => std::future::pending().await,
+    }
+}
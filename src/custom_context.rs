@@ -1,25 +1,291 @@
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+
+use log::debug;
+use log::log;
 use log::trace;
+use log::warn;
+use log::Level;
+use rdkafka::bindings::rd_kafka_resp_err_t;
+use rdkafka::bindings::rd_kafka_set_log_queue;
 use rdkafka::client::ClientContext;
+use rdkafka::client::OAuthToken;
+use rdkafka::config::RDKafkaLogLevel;
 use rdkafka::consumer::stream_consumer::StreamConsumer;
 use rdkafka::consumer::ConsumerContext;
 use rdkafka::consumer::Rebalance;
+use rdkafka::error::KafkaError;
 use rdkafka::error::KafkaResult;
+use rdkafka::error::RDKafkaErrorCode;
+use rdkafka::producer::FutureProducer;
 use rdkafka::topic_partition_list::TopicPartitionList;
+use rdkafka::types::RDKafka;
+use rdkafka::Statistics;
+
+use crate::consume_partitions::PartitionListener;
+use crate::host_overrides::install;
+use crate::host_overrides::HostOverrides;
+use crate::host_overrides::Resolver;
+use crate::metrics::Metrics;
+use crate::sasl::OAuthTokenProvider;
+
+/// StatsHandler
+///
+/// Receives the statistics librdkafka emits every
+/// ``statistics.interval.ms``, ``json`` is the document as librdkafka
+/// wrote it and ``stats`` the decoded form. Closures with the same
+/// signature are handlers too.
+///
+pub trait StatsHandler: Send + Sync {
+    /// stats
+    ///
+    /// Handle one statistics report, called on a librdkafka thread so
+    /// it should return quickly
+    ///
+    fn stats(&self, stats: &Statistics, json: &str);
+}
+
+impl<F> StatsHandler for F
+where
+    F: Fn(&Statistics, &str) + Send + Sync,
+{
+    fn stats(&self, stats: &Statistics, json: &str) {
+        self(stats, json)
+    }
+}
 
 // A context can be used to change the behavior of producers and consumers by adding callbacks
 // that will be executed by librdkafka.
-// This particular context sets up custom callbacks to log rebalancing events.
-pub struct CustomContext;
+// This particular context sets up custom callbacks to log rebalancing events
+// and hands out OAUTHBEARER tokens when a token provider is set. Broker
+// hostnames in the host overrides are resolved by a librdkafka resolve callback.
+// librdkafka log lines go to the ``log`` crate with a ``librdkafka::<facility>``
+// target and statistics go to the stats handler. Rebalances, commit failures
+// and statistics are recorded in the metrics when they are set. Assigned and
+// revoked partitions are handed to the partition consumer that listens for them
+// before the assignment change takes effect.
+#[derive(Clone, Default)]
+pub struct CustomContext {
+    token_provider: Option<Arc<dyn OAuthTokenProvider>>,
+    host_overrides: Option<Arc<HostOverrides>>,
+    resolver: Arc<Mutex<Option<Resolver>>>,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
+    metrics: Option<Arc<Metrics>>,
+    partition_listener: Arc<Mutex<Option<Weak<dyn PartitionListener>>>>,
+}
+
+impl CustomContext {
+    /// context that only logs, add the token provider, host
+    /// overrides, statistics handler and metrics with the setters
+    pub fn new() -> Self {
+        CustomContext::default()
+    }
+
+    /// provider called by librdkafka for every ``OAUTHBEARER`` token
+    /// refresh
+    pub fn token_provider(
+        mut self,
+        provider: Arc<dyn OAuthTokenProvider>,
+    ) -> Self {
+        self.token_provider = Some(provider);
+        self
+    }
+
+    /// broker hostnames to connect to without a dns lookup, see
+    /// [`HostOverrides`](crate::host_overrides::HostOverrides)
+    pub fn host_overrides(mut self, overrides: HostOverrides) -> Self {
+        self.host_overrides = Some(Arc::new(overrides));
+        self
+    }
+
+    /// handler for the statistics librdkafka emits when
+    /// ``statistics.interval.ms`` is set
+    pub fn stats_handler(mut self, handler: Arc<dyn StatsHandler>) -> Self {
+        self.stats_handler = Some(handler);
+        self
+    }
+
+    /// record rebalances, commit failures and statistics in
+    /// ``metrics``, the consume loop counts received messages there too
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// metrics shared with the consume loop
+    pub(crate) fn shared_metrics(&self) -> Option<&Arc<Metrics>> {
+        self.metrics.as_ref()
+    }
+
+    /// hand assigned and revoked partitions to ``listener`` while it
+    /// is alive, replacing any earlier listener
+    pub(crate) fn listen_partitions(
+        &self,
+        listener: Weak<dyn PartitionListener>,
+    ) {
+        *self.partition_listener.lock().unwrap() = Some(listener);
+    }
+
+    /// the partition listener, ``None`` once its consume loop returned
+    fn partition_listener(&self) -> Option<Arc<dyn PartitionListener>> {
+        self.partition_listener.lock().unwrap().as_ref()?.upgrade()
+    }
+
+    /// address override for a broker ``hostname``
+    pub fn resolve_host(&self, hostname: &str) -> Option<IpAddr> {
+        self.host_overrides.as_ref()?.get(hostname)
+    }
 
-impl ClientContext for CustomContext {}
+    /// ``true`` when clients need the resolve callback
+    pub(crate) fn overrides_hosts(&self) -> bool {
+        self.host_overrides.is_some()
+    }
+
+    /// install the resolve callback on the client ``rk`` created with
+    /// this context and add the ``brokers``, the overrides stay
+    /// registered until the client drops its context
+    pub(crate) fn resolve_brokers(
+        &self,
+        rk: *mut RDKafka,
+        brokers: &str,
+    ) -> crate::error::Result<()> {
+        match &self.host_overrides {
+            Some(overrides) => {
+                let resolver = install(rk, overrides, brokers)?;
+                *self.resolver.lock().unwrap() = Some(resolver);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl ClientContext for CustomContext {
+    const ENABLE_REFRESH_OAUTH_TOKEN: bool = true;
+
+    fn log(&self, level: RDKafkaLogLevel, fac: &str, log_message: &str) {
+        let target = format!("librdkafka::{}", fac.to_ascii_lowercase());
+        log!(
+            target: &target,
+            log_level(level),
+            facility = fac;
+            "{log_message}"
+        );
+    }
+
+    fn stats_raw(&self, statistics: &[u8]) {
+        let json = String::from_utf8_lossy(statistics);
+        if self.stats_handler.is_none() && self.metrics.is_none() {
+            debug!(target: "librdkafka::stats", "{json}");
+            return;
+        }
+        let stats = match serde_json::from_str::<Statistics>(&json) {
+            Ok(stats) => stats,
+            Err(e) => {
+                warn!(
+                    target: "librdkafka::stats",
+                    "statistics are not valid json: {e}"
+                );
+                return;
+            }
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.record_stats(&stats);
+        }
+        if let Some(handler) = &self.stats_handler {
+            handler.stats(&stats, &json);
+        }
+    }
+
+    fn error(&self, error: KafkaError, reason: &str) {
+        log!(target: "librdkafka", error_level(&error), "{error}: {reason}");
+    }
+
+    fn generate_oauth_token(
+        &self,
+        oauthbearer_config: Option<&str>,
+    ) -> Result<OAuthToken, Box<dyn Error>> {
+        trace!("Refreshing oauth token");
+        match &self.token_provider {
+            Some(provider) => provider.token(oauthbearer_config),
+            None => Err("no oauth token provider is set".into()),
+        }
+    }
+}
+
+/// forward_logs
+///
+/// Queue the log lines of the librdkafka client ``rk``, created with
+/// ``log.queue=true``, on its main queue. The client poll loop then
+/// hands them to [`ClientContext::log`] instead of librdkafka printing
+/// them to stderr from its internal threads.
+///
+pub(crate) fn forward_logs(rk: *mut RDKafka) -> crate::error::Result<()> {
+    let err = unsafe { rd_kafka_set_log_queue(rk, std::ptr::null_mut()) };
+    match err {
+        rd_kafka_resp_err_t::RD_KAFKA_RESP_ERR_NO_ERROR => Ok(()),
+        err => Err(KafkaError::Global(err.into()).into()),
+    }
+}
+
+/// ``log`` level of a client error, lost broker connections are
+/// retried by librdkafka and only warn
+fn error_level(error: &KafkaError) -> Level {
+    match error.rdkafka_error_code() {
+        Some(
+            RDKafkaErrorCode::BrokerTransportFailure
+            | RDKafkaErrorCode::AllBrokersDown
+            | RDKafkaErrorCode::Resolve
+            | RDKafkaErrorCode::NetworkException
+            | RDKafkaErrorCode::RequestTimedOut,
+        ) => Level::Warn,
+        _ => Level::Error,
+    }
+}
+
+/// ``log`` level of a librdkafka syslog level
+fn log_level(level: RDKafkaLogLevel) -> Level {
+    match level {
+        RDKafkaLogLevel::Emerg
+        | RDKafkaLogLevel::Alert
+        | RDKafkaLogLevel::Critical
+        | RDKafkaLogLevel::Error => Level::Error,
+        RDKafkaLogLevel::Warning => Level::Warn,
+        RDKafkaLogLevel::Notice | RDKafkaLogLevel::Info => Level::Info,
+        RDKafkaLogLevel::Debug => Level::Debug,
+    }
+}
+
+/// topic and partition of every element in ``tpl``
+fn partitions(tpl: &TopicPartitionList) -> Vec<(String, i32)> {
+    tpl.elements()
+        .iter()
+        .map(|e| (e.topic().to_string(), e.partition()))
+        .collect()
+}
 
 impl ConsumerContext for CustomContext {
     fn pre_rebalance(&self, rebalance: &Rebalance) {
         trace!("Pre rebalance {:?}", rebalance);
+        match (rebalance, self.partition_listener()) {
+            (Rebalance::Assign(tpl), Some(listener)) => {
+                listener.assigning(&partitions(tpl))
+            }
+            (Rebalance::Revoke(tpl), Some(listener)) => {
+                listener.revoking(&partitions(tpl))
+            }
+            _ => {}
+        }
     }
 
     fn post_rebalance(&self, rebalance: &Rebalance) {
         trace!("Post rebalance {:?}", rebalance);
+        if let Some(metrics) = &self.metrics {
+            metrics.rebalance(rebalance);
+        }
     }
 
     fn commit_callback(
@@ -28,8 +294,21 @@ impl ConsumerContext for CustomContext {
         _offsets: &TopicPartitionList,
     ) {
         trace!("Committing offsets: {:?}", result);
+        let failed = match &result {
+            Ok(()) => false,
+            // nothing was stored since the last commit
+            Err(e) => {
+                e.rdkafka_error_code() != Some(RDKafkaErrorCode::NoOffset)
+            }
+        };
+        if let (true, Some(metrics)) = (failed, &self.metrics) {
+            metrics.commit_failed();
+        }
     }
 }
 
 // A type alias with your custom consumer can be created for convenience.
 pub type LoggingConsumer = StreamConsumer<CustomContext>;
+
+// The producer counterpart shares the context to refresh OAUTHBEARER tokens.
+pub type LoggingProducer = FutureProducer<CustomContext>;
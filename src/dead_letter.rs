@@ -0,0 +1,187 @@
+use std::fmt;
+use std::time::Duration;
+
+use log::warn;
+use rdkafka::message::Headers;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+
+use crate::custom_context::LoggingProducer;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message_handler::MessageHandler;
+use crate::publish_options::PublishOptions;
+use crate::publish_records::publish_record;
+use crate::publish_records::DeliveryReport;
+use crate::publish_records::ProducerRecord;
+
+/// header with the topic the dead-lettered message was consumed from
+pub const DLQ_ORIGINAL_TOPIC_HEADER: &str = "dlq.original.topic";
+/// header with the partition the dead-lettered message was consumed from
+pub const DLQ_ORIGINAL_PARTITION_HEADER: &str = "dlq.original.partition";
+/// header with the offset of the dead-lettered message
+pub const DLQ_ORIGINAL_OFFSET_HEADER: &str = "dlq.original.offset";
+/// header with the last handler error
+pub const DLQ_ERROR_HEADER: &str = "dlq.error";
+/// header with how many times the handler was called
+pub const DLQ_ATTEMPTS_HEADER: &str = "dlq.attempts";
+
+/// DeadLetterQueue
+///
+/// Republishes messages that the
+/// [`MessageHandler`](crate::message_handler::MessageHandler) could
+/// not process to a dead-letter ``topic`` once the retries are
+/// exhausted. The original key, payload, headers and timestamp are
+/// kept and the ``dlq.*`` headers record where the message came from
+/// and why it failed. Deserialization failures are dead-lettered
+/// without retrying.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use rust_with_kafka_tls::consume_options::ConsumeOptions;
+/// use rust_with_kafka_tls::dead_letter::DeadLetterQueue;
+/// use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+///
+/// let producer = KafkaTlsConfig::from_env("localhost:9093")
+///     .create_producer()
+///     .expect("Producer creation error");
+/// let options = ConsumeOptions::new().dead_letter(
+///     DeadLetterQueue::new(producer, "testing.dlq")
+///         .max_retries(5)
+///         .retry_delay(Duration::from_millis(500)),
+/// );
+/// ```
+///
+#[derive(Clone)]
+pub struct DeadLetterQueue {
+    producer: LoggingProducer,
+    topic: String,
+    max_retries: u32,
+    retry_delay: Duration,
+}
+
+impl fmt::Debug for DeadLetterQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadLetterQueue")
+            .field("topic", &self.topic)
+            .field("max_retries", &self.max_retries)
+            .field("retry_delay", &self.retry_delay)
+            .finish()
+    }
+}
+
+impl DeadLetterQueue {
+    /// new
+    ///
+    /// Dead-letter failing messages to ``topic`` with ``producer``
+    /// after 3 retries 100 milliseconds apart
+    ///
+    pub fn new(producer: LoggingProducer, topic: &str) -> Self {
+        DeadLetterQueue {
+            producer,
+            topic: topic.to_string(),
+            max_retries: 3,
+            retry_delay: Duration::from_millis(100),
+        }
+    }
+
+    /// how many times to retry the handler before dead-lettering
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// how long to wait between handler retries
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// dead-letter topic name
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// handle
+    ///
+    /// Call the ``handler`` with bounded retries and dead-letter the
+    /// message if it still fails. Returns ``Ok`` once the message was
+    /// handled or dead-lettered so the offset can be committed.
+    ///
+    pub async fn handle<H: MessageHandler>(
+        &self,
+        handler: &H,
+        message: &OwnedMessage,
+    ) -> Result<()> {
+        let mut attempts = 0;
+        let error = loop {
+            attempts += 1;
+            match handler.handle(message).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    if matches!(e, Error::Deserialization(_))
+                        || attempts > self.max_retries
+                    {
+                        break e;
+                    }
+                    warn!(
+                        topic = message.topic(),
+                        partition = message.partition(),
+                        offset = message.offset(),
+                        attempt = attempts;
+                        "Handler failed, retrying: {e}"
+                    );
+                    tokio::time::sleep(self.retry_delay).await;
+                }
+            }
+        };
+        let report = self.publish(message, &error, attempts).await?;
+        warn!(
+            topic = message.topic(),
+            partition = message.partition(),
+            offset = message.offset(),
+            dead_letter_topic = self.topic.as_str(),
+            dead_letter_partition = report.partition,
+            dead_letter_offset = report.offset;
+            "Dead-lettered: {error}"
+        );
+        Ok(())
+    }
+
+    /// publish
+    ///
+    /// Republish ``message`` to the dead-letter topic with the
+    /// original topic, partition, offset, ``error`` and ``attempts``
+    /// headers
+    ///
+    pub async fn publish(
+        &self,
+        message: &OwnedMessage,
+        error: &Error,
+        attempts: u32,
+    ) -> Result<DeliveryReport> {
+        let mut record = ProducerRecord::new();
+        record.key = message.key().map(|key| key.to_vec());
+        record.payload = message.payload().map(|payload| payload.to_vec());
+        record.timestamp = message.timestamp().to_millis();
+        if let Some(headers) = message.headers() {
+            for header in headers.iter() {
+                record = record.header(header.key, header.value.unwrap_or(&[]));
+            }
+        }
+        let record = record
+            .header(DLQ_ORIGINAL_TOPIC_HEADER, message.topic())
+            .header(
+                DLQ_ORIGINAL_PARTITION_HEADER,
+                message.partition().to_string(),
+            )
+            .header(DLQ_ORIGINAL_OFFSET_HEADER, message.offset().to_string())
+            .header(DLQ_ERROR_HEADER, error.to_string())
+            .header(DLQ_ATTEMPTS_HEADER, attempts.to_string());
+        let options =
+            PublishOptions::new().queue_timeout(Duration::from_secs(5));
+        publish_record(&self.producer, &self.topic, &record, &options).await
+    }
+}
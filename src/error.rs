@@ -0,0 +1,129 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use rdkafka::error::KafkaError;
+use rdkafka::error::RDKafkaErrorCode;
+
+/// Error
+///
+/// Crate-wide error type returned by the producer, consumer and
+/// tls config apis so callers can decide how to react instead of
+/// the library panicking
+///
+#[derive(Debug)]
+pub enum Error {
+    /// invalid client settings or librdkafka configuration
+    Config(String),
+    /// a tls asset is missing, unreadable or not valid PEM, ``path``
+    /// is the file or where in-memory PEM contents came from
+    TlsAsset {
+        name: &'static str,
+        path: PathBuf,
+        reason: String,
+    },
+    /// a message key, header or payload could not be decoded
+    Deserialization(String),
+    /// a [`MessageHandler`](crate::message_handler::MessageHandler)
+    /// could not process a message
+    Handler(String),
+    /// committing consumer offsets failed
+    Commit(KafkaError),
+    /// kafka failed to deliver a published message
+    Delivery(KafkaError),
+    /// an operation did not complete before its deadline
+    Timeout(String),
+    /// any other librdkafka error
+    Kafka(KafkaError),
+}
+
+/// crate result alias using [`Error`](crate::error::Error)
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// delivery
+    ///
+    /// Convert a producer delivery failure into an
+    /// [`Error`](crate::error::Error), message timeouts are reported
+    /// as [`Error::Timeout`](crate::error::Error::Timeout)
+    ///
+    pub fn delivery(e: KafkaError) -> Self {
+        match e.rdkafka_error_code() {
+            Some(RDKafkaErrorCode::MessageTimedOut) => {
+                Error::Timeout(format!("message delivery timed out: {e}"))
+            }
+            _ => Error::Delivery(e),
+        }
+    }
+
+    /// is_retriable
+    ///
+    /// ``true`` for transient kafka failures that may succeed when
+    /// retried without writing a message twice: a full producer queue
+    /// and produce requests the partition leader rejected during a
+    /// leader election or with too few in-sync replicas. Timeouts and
+    /// transport failures of a delivery are not retriable because
+    /// librdkafka already retried the message internally and the
+    /// broker may have written it. Other operations are also retried
+    /// after transport failures and when all brokers are down.
+    /// Configuration, authorization, tls and handler errors are fatal.
+    ///
+    pub fn is_retriable(&self) -> bool {
+        let code = match self {
+            Error::Delivery(e) | Error::Kafka(e) => e.rdkafka_error_code(),
+            _ => return false,
+        };
+        match code {
+            Some(
+                RDKafkaErrorCode::QueueFull
+                | RDKafkaErrorCode::LeaderNotAvailable
+                | RDKafkaErrorCode::NotLeaderForPartition
+                | RDKafkaErrorCode::PreferredLeaderNotAvailable
+                | RDKafkaErrorCode::NotEnoughReplicas,
+            ) => true,
+            Some(
+                RDKafkaErrorCode::BrokerTransportFailure
+                | RDKafkaErrorCode::AllBrokersDown,
+            ) => matches!(self, Error::Kafka(_)),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(msg) => write!(f, "invalid config: {msg}"),
+            Error::TlsAsset { name, path, reason } => {
+                write!(f, "tls {name} {}: {reason}", path.display())
+            }
+            Error::Deserialization(msg) => {
+                write!(f, "deserialization failed: {msg}")
+            }
+            Error::Handler(msg) => write!(f, "handler failed: {msg}"),
+            Error::Commit(e) => write!(f, "offset commit failed: {e}"),
+            Error::Delivery(e) => write!(f, "message delivery failed: {e}"),
+            Error::Timeout(msg) => write!(f, "timed out: {msg}"),
+            Error::Kafka(e) => write!(f, "kafka error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Commit(e) | Error::Delivery(e) | Error::Kafka(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<KafkaError> for Error {
+    fn from(e: KafkaError) -> Self {
+        match e {
+            KafkaError::ClientConfig(..) | KafkaError::ClientCreation(_) => {
+                Error::Config(e.to_string())
+            }
+            _ => Error::Kafka(e),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::net::IpAddr;
+use std::os::raw::c_char;
+use std::os::raw::c_int;
+use std::os::raw::c_void;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use rdkafka::bindings::rd_kafka_brokers_add;
+use rdkafka::bindings::rd_kafka_conf;
+use rdkafka::bindings::rd_kafka_opaque;
+use rdkafka::types::RDKafka;
+use rdkafka::types::RDKafkaConf;
+
+use crate::error::Error;
+use crate::error::Result;
+
+/// signature of the librdkafka resolve callback, it mirrors
+/// ``getaddrinfo(3)``
+type ResolveCallback = unsafe extern "C" fn(
+    node: *const c_char,
+    service: *const c_char,
+    hints: *const libc::addrinfo,
+    res: *mut *mut libc::addrinfo,
+    opaque: *mut c_void,
+) -> c_int;
+
+extern "C" {
+    // part of librdkafka but not of the generated rdkafka-sys bindings
+    fn rd_kafka_conf_set_resolve_cb(
+        conf: *mut RDKafkaConf,
+        resolve_cb: Option<ResolveCallback>,
+    );
+}
+
+/// overrides of every live client with a resolve callback, keyed by
+/// the client opaque librdkafka hands to the callback
+static CLIENTS: Mutex<Vec<(usize, Arc<HostOverrides>)>> =
+    Mutex::new(Vec::new());
+
+/// Resolver
+///
+/// Registration of one client with the resolve callback. The client
+/// context owns it, so the entry is removed when the client is
+/// dropped and a later client created at the same address never sees
+/// the overrides of an earlier one.
+///
+#[derive(Debug)]
+pub(crate) struct Resolver {
+    opaque: usize,
+}
+
+impl Drop for Resolver {
+    fn drop(&mut self) {
+        if let Ok(mut clients) = CLIENTS.lock() {
+            clients.retain(|(key, _)| *key != self.opaque);
+        }
+    }
+}
+
+/// HostOverrides
+///
+/// Broker hostname to ip address map used instead of dns, like
+/// entries in ``/etc/hosts`` that only apply to the kafka clients.
+/// Connections go to the mapped address while tls hostname
+/// verification still checks the advertised broker name, so
+/// minikube or ``kubectl port-forward`` addresses work without
+/// editing ``/etc/hosts``. Hosts that are not in the map are
+/// resolved with dns as usual.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::net::Ipv4Addr;
+/// use rust_with_kafka_tls::host_overrides::HostOverrides;
+///
+/// let overrides = HostOverrides::parse(
+///     "cluster-0-broker-0.redten.io=192.168.49.2,\
+///     cluster-0-broker-1.redten.io=192.168.49.2",
+/// )
+/// .unwrap()
+/// .host("cluster-0-broker-2.redten.io", Ipv4Addr::LOCALHOST.into());
+/// assert_eq!(overrides.len(), 3);
+/// ```
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HostOverrides {
+    hosts: HashMap<String, IpAddr>,
+}
+
+impl HostOverrides {
+    /// empty map, every host is resolved with dns
+    pub fn new() -> Self {
+        HostOverrides::default()
+    }
+
+    /// parse
+    ///
+    /// Parse a comma delimited list of ``hostname=ip`` entries
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Config`](crate::error::Error::Config) for an entry
+    /// without a hostname or with an invalid ip address
+    ///
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut overrides = HostOverrides::new();
+        for entry in spec.split(',').map(str::trim) {
+            if entry.is_empty() {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                Error::Config(format!("host override {entry} {reason}"))
+            };
+            let (host, ip) = entry
+                .split_once('=')
+                .ok_or_else(|| invalid("is not hostname=ip"))?;
+            if host.trim().is_empty() {
+                return Err(invalid("has no hostname"));
+            }
+            let ip = ip
+                .trim()
+                .parse()
+                .map_err(|_| invalid("has an invalid ip address"))?;
+            overrides = overrides.host(host.trim(), ip);
+        }
+        Ok(overrides)
+    }
+
+    /// from_env
+    ///
+    /// [`parse`](crate::host_overrides::HostOverrides::parse) the
+    /// ``KAFKA_HOST_OVERRIDES`` environment variable followed by the
+    /// ``extra`` entries (for example from the command line), later
+    /// entries win. Empty when nothing is set.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`parse`](crate::host_overrides::HostOverrides::parse)
+    ///
+    pub fn from_env<'a, I>(extra: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut specs =
+            vec![std::env::var("KAFKA_HOST_OVERRIDES").unwrap_or_default()];
+        specs.extend(extra.into_iter().map(str::to_string));
+        HostOverrides::parse(&specs.join(","))
+    }
+
+    /// connect to ``ip`` instead of resolving ``hostname``
+    pub fn host(mut self, hostname: &str, ip: IpAddr) -> Self {
+        self.hosts.insert(hostname.to_ascii_lowercase(), ip);
+        self
+    }
+
+    /// address for ``hostname``, ``None`` when it is resolved with dns
+    pub fn get(&self, hostname: &str) -> Option<IpAddr> {
+        self.hosts.get(&hostname.to_ascii_lowercase()).copied()
+    }
+
+    /// number of overridden hostnames
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// ``true`` when no hostname is overridden
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+}
+
+/// install
+///
+/// Route the address lookups of the librdkafka client ``rk`` through
+/// ``overrides``, then add the ``brokers``. The client must have been
+/// created without ``bootstrap.servers`` so no broker thread exists
+/// before the callback is in place, see the safety comment inside for
+/// why the callback is set after ``rd_kafka_new``. The overrides
+/// apply until the returned [`Resolver`] is dropped, keep it in the
+/// client context.
+///
+pub(crate) fn install(
+    rk: *mut RDKafka,
+    overrides: &Arc<HostOverrides>,
+    brokers: &str,
+) -> Result<Resolver> {
+    let brokerlist = CString::new(brokers)
+        .map_err(|_| Error::Config(format!("invalid brokers {brokers}")))?;
+    let opaque = unsafe { rd_kafka_opaque(rk) } as usize;
+    {
+        let mut clients = CLIENTS.lock().unwrap();
+        clients.retain(|(key, _)| *key != opaque);
+        clients.push((opaque, overrides.clone()));
+    }
+    let resolver = Resolver { opaque };
+    // Setting the callback on the config before rd_kafka_new is not
+    // possible with rdkafka: StreamConsumer and FutureProducer build
+    // their native config inside from_config_and_context and cannot be
+    // created from one prepared here. So the callback is written into
+    // the config the running client copied, which librdkafka hands out
+    // as const and does not expect to change.
+    //
+    // rd_kafka_new already started the main thread and the internal
+    // broker thread, both run concurrently with this write but neither
+    // resolves hostnames, and the config is not read for a lookup
+    // until a broker thread connects. Without bootstrap.servers there
+    // are no such threads yet, the first ones are started by
+    // rd_kafka_brokers_add below, after the write, and thread creation
+    // orders the write before their reads. A client created with
+    // bootstrap.servers would race: its broker threads could resolve
+    // with getaddrinfo before the callback is set or read the pointer
+    // while it is written, so create_client removes them and this
+    // function adds them.
+    let added = unsafe {
+        rd_kafka_conf_set_resolve_cb(
+            rd_kafka_conf(rk) as *mut RDKafkaConf,
+            Some(resolve_cb),
+        );
+        rd_kafka_brokers_add(rk, brokerlist.as_ptr())
+    };
+    if added == 0 {
+        return Err(Error::Config(format!("no valid brokers in {brokers}")));
+    }
+    Ok(resolver)
+}
+
+/// address override for ``node`` of the client with ``opaque``
+fn lookup(opaque: *mut c_void, node: &str) -> Option<IpAddr> {
+    let clients = CLIENTS.lock().ok()?;
+    let (_, overrides) =
+        clients.iter().find(|(key, _)| *key == opaque as usize)?;
+    overrides.get(node)
+}
+
+/// librdkafka resolve callback, overridden hosts are resolved as
+/// numeric addresses and everything else with ``getaddrinfo``
+unsafe extern "C" fn resolve_cb(
+    node: *const c_char,
+    service: *const c_char,
+    hints: *const libc::addrinfo,
+    res: *mut *mut libc::addrinfo,
+    opaque: *mut c_void,
+) -> c_int {
+    if node.is_null() && service.is_null() && hints.is_null() {
+        if !res.is_null() && !(*res).is_null() {
+            libc::freeaddrinfo(*res);
+        }
+        return 0;
+    }
+    let ip = match node.is_null() {
+        true => None,
+        false => lookup(opaque, &CStr::from_ptr(node).to_string_lossy()),
+    };
+    let ip = match ip.and_then(|ip| CString::new(ip.to_string()).ok()) {
+        Some(ip) => ip,
+        None => return libc::getaddrinfo(node, service, hints, res),
+    };
+    let mut numeric: libc::addrinfo = std::mem::zeroed();
+    if !hints.is_null() {
+        numeric = *hints;
+    }
+    numeric.ai_flags |= libc::AI_NUMERICHOST;
+    libc::getaddrinfo(ip.as_ptr(), service, &numeric, res)
+}
@@ -0,0 +1,443 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+
+use futures::future::join_all;
+use log::info;
+use log::warn;
+use rdkafka::consumer::CommitMode;
+use rdkafka::consumer::Consumer;
+use rdkafka::message::BorrowedMessage;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+
+use crate::backpressure::Backpressure;
+use crate::commit_policy::CommitPolicy;
+use crate::consume_messages::commit_interval;
+use crate::consume_messages::commit_retriable;
+use crate::consume_messages::commit_stored;
+use crate::consume_messages::commit_through;
+use crate::consume_messages::handle_message;
+use crate::consume_messages::record_consumed;
+use crate::consume_messages::tick;
+use crate::consume_messages::HandledOffsets;
+use crate::consume_options::ConsumeOptions;
+use crate::consume_partitions::Partition;
+use crate::consume_partitions::PartitionListener;
+use crate::custom_context::LoggingConsumer;
+use crate::error::Result;
+use crate::message_handler::MessageHandler;
+
+/// worker_index
+///
+/// Worker out of ``workers`` that handles a message with ``key`` from
+/// ``partition``. Messages with the same key always go to the same
+/// worker, messages without a key are spread by partition.
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::keyed_workers::worker_index;
+///
+/// let worker = worker_index(Some(b"user-1".as_slice()), 0, 4);
+/// assert!(worker < 4);
+/// assert_eq!(worker, worker_index(Some(b"user-1".as_slice()), 0, 4));
+/// assert_eq!(worker_index(None, 2, 1), 0);
+/// ```
+///
+pub fn worker_index(
+    key: Option<&[u8]>,
+    partition: i32,
+    workers: usize,
+) -> usize {
+    let mut hasher = DefaultHasher::new();
+    match key {
+        Some(key) => key.hash(&mut hasher),
+        None => partition.hash(&mut hasher),
+    }
+    (hasher.finish() % workers.max(1) as u64) as usize
+}
+
+/// consume_keyed
+///
+/// The [`consume_messages`](crate::consume_messages::consume_messages)
+/// loop for
+/// [`ConsumeOptions::key_workers`](crate::consume_options::ConsumeOptions::key_workers):
+/// received messages are queued on the worker picked by
+/// [`worker_index`](crate::keyed_workers::worker_index) and each
+/// worker handles its queue in order, concurrently with the others.
+/// A partition is committed up to the offset before its lowest
+/// message that is not done yet. A failed handler holds the commits of
+/// its partition at the failed offset, so the next owner of the
+/// partition or a restart receives it again, and with
+/// [`CommitPolicy::AfterHandlerSuccess`](crate::commit_policy::CommitPolicy::AfterHandlerSuccess)
+/// it also stops the loop.
+///
+/// Every dispatched message carries the assignment epoch of its
+/// partition. A revoke ends the epoch in the rebalance callback: the
+/// workers drop the queued messages of the revoked partitions, the
+/// results of their in-flight messages are ignored and their handled
+/// offsets are forgotten before the loop commits anything else, so
+/// the loop never commits a partition another member owns. A message
+/// that is in a handler during the revoke still finishes, the next
+/// owner may handle it at the same time.
+///
+/// With
+/// [`ConsumeOptions::in_flight_limits`](crate::consume_options::ConsumeOptions::in_flight_limits)
+/// the assigned partitions are paused while the received messages
+/// that are not done yet reach a limit.
+///
+/// With
+/// [`ConsumeOptions::max_messages`](crate::consume_options::ConsumeOptions::max_messages)
+/// the loop stops receiving after that many messages and ends once
+/// they are all done.
+///
+/// On shutdown the workers finish the message they are handling,
+/// queued messages are left uncommitted.
+///
+pub(crate) async fn consume_keyed<H: MessageHandler>(
+    consumer: &LoggingConsumer,
+    handler: &H,
+    options: &ConsumeOptions,
+    workers: usize,
+) -> Result<()> {
+    let policy = options.commit_policy;
+    let stop = CancellationToken::new();
+    let epochs = Arc::new(Epochs::default());
+    let (done, mut finished) = mpsc::unbounded_channel();
+    let mut queues = Vec::with_capacity(workers);
+    let mut pool = Vec::with_capacity(workers);
+    for _ in 0..workers {
+        let (queue, messages) = mpsc::unbounded_channel();
+        queues.push(queue);
+        pool.push(work(
+            handler,
+            options,
+            messages,
+            done.clone(),
+            &epochs,
+            &stop,
+        ));
+    }
+    drop(done);
+    let listener: Weak<dyn PartitionListener> = Arc::downgrade(&epochs) as _;
+    consumer.context().listen_partitions(listener);
+    let stop = &stop;
+    let epochs = &epochs;
+    let dispatch = async move {
+        let mut in_flight = InFlight::default();
+        let mut backpressure = Backpressure::new(options.in_flight_limits);
+        let mut handled = HandledOffsets::default();
+        let mut commit_interval = commit_interval(policy);
+        let mut remaining = options.max_messages;
+        let mut outcome = Ok(());
+        loop {
+            // with max_messages the loop ends once the last one is done
+            if remaining == Some(0) && in_flight.messages == 0 {
+                break;
+            }
+            tokio::select! {
+                biased;
+                _ = options.shutdown.cancelled() => break,
+                Some(done) = finished.recv() => {
+                    in_flight.revoke(epochs, &mut handled);
+                    let completed = in_flight.complete(
+                        consumer,
+                        policy,
+                        &mut handled,
+                        done,
+                    );
+                    if let Err(e) = completed {
+                        outcome = Err(e);
+                        break;
+                    }
+                    in_flight.update(consumer, &mut backpressure);
+                }
+                _ = tick(&mut commit_interval) => {
+                    let committed = commit_stored(consumer, CommitMode::Async);
+                    if let Err(e) = committed {
+                        outcome = Err(e);
+                        break;
+                    }
+                }
+                received = consumer.recv(), if remaining != Some(0) => {
+                    // the rebalance callbacks run inside recv
+                    in_flight.revoke(epochs, &mut handled);
+                    match received {
+                        Err(e) => warn!("Kafka error: {}", e),
+                        Ok(m) => {
+                            record_consumed(consumer, &m);
+                            remaining = remaining.map(|n| n - 1);
+                            let partition = m.partition();
+                            let topic = m.topic();
+                            backpressure.received(consumer, topic, partition);
+                            let epoch = epochs.current(topic, partition);
+                            in_flight.dispatched(&m, epoch);
+                            in_flight.update(consumer, &mut backpressure);
+                            let worker =
+                                worker_index(m.key(), partition, workers);
+                            // workers only stop after the dispatch loop
+                            let _ = queues[worker].send((m.detach(), epoch));
+                        }
+                    }
+                }
+            }
+        }
+        info!("consumer shutting down");
+        backpressure.finish(consumer);
+        stop.cancel();
+        drop(queues);
+        in_flight.revoke(epochs, &mut handled);
+        while let Some(done) = finished.recv().await {
+            let completed =
+                in_flight.complete(consumer, policy, &mut handled, done);
+            if let (Err(e), true) = (completed, outcome.is_ok()) {
+                outcome = Err(e);
+            }
+        }
+        let committed = if policy.stores_offsets() {
+            commit_stored(consumer, CommitMode::Sync)
+        } else {
+            handled.commit(consumer)
+        };
+        outcome.and(committed)
+    };
+    let (outcome, _) = tokio::join!(dispatch, join_all(pool));
+    consumer.unsubscribe();
+    outcome
+}
+
+/// a message a worker finished handling in an assignment ``epoch``
+struct Done {
+    topic: String,
+    partition: i32,
+    offset: i64,
+    epoch: u64,
+    result: Result<()>,
+}
+
+/// handle the queued messages in order until the loop stops, skipping
+/// the messages of revoked assignment epochs
+async fn work<H: MessageHandler>(
+    handler: &H,
+    options: &ConsumeOptions,
+    mut messages: UnboundedReceiver<(OwnedMessage, u64)>,
+    done: UnboundedSender<Done>,
+    epochs: &Epochs,
+    stop: &CancellationToken,
+) {
+    loop {
+        let (m, epoch) = tokio::select! {
+            biased;
+            _ = stop.cancelled() => break,
+            m = messages.recv() => match m {
+                Some(m) => m,
+                None => break,
+            },
+        };
+        if !epochs.is_current(m.topic(), m.partition(), epoch) {
+            continue;
+        }
+        let result = handle_message(handler, options, &m).await;
+        if let Err(e) = &result {
+            warn!(
+                topic = m.topic(),
+                partition = m.partition(),
+                offset = m.offset();
+                "Handler failed, holding the partition commits: {e}"
+            );
+        }
+        let _ = done.send(Done {
+            topic: m.topic().to_string(),
+            partition: m.partition(),
+            offset: m.offset(),
+            epoch,
+            result,
+        });
+    }
+}
+
+/// assignment epochs of the partitions with dispatched messages,
+/// ended by the rebalance callbacks
+#[derive(Default)]
+struct Epochs {
+    /// current epoch by topic and partition
+    current: Mutex<HashMap<Partition, u64>>,
+    /// last epoch handed out
+    last: Mutex<u64>,
+    /// partitions revoked since the dispatch loop last looked
+    revoked: Mutex<Vec<Partition>>,
+}
+
+impl Epochs {
+    /// epoch of a partition, a new one after it was revoked
+    fn current(&self, topic: &str, partition: i32) -> u64 {
+        *self
+            .current
+            .lock()
+            .unwrap()
+            .entry((topic.to_string(), partition))
+            .or_insert_with(|| {
+                let mut last = self.last.lock().unwrap();
+                *last += 1;
+                *last
+            })
+    }
+
+    /// ``false`` once ``epoch`` of the partition was revoked
+    fn is_current(&self, topic: &str, partition: i32, epoch: u64) -> bool {
+        let key = (topic.to_string(), partition);
+        self.current.lock().unwrap().get(&key) == Some(&epoch)
+    }
+
+    /// partitions revoked since the last call
+    fn take_revoked(&self) -> Vec<Partition> {
+        std::mem::take(&mut *self.revoked.lock().unwrap())
+    }
+}
+
+impl PartitionListener for Epochs {
+    fn assigning(&self, _partitions: &[Partition]) {}
+
+    fn revoking(&self, partitions: &[Partition]) {
+        let mut current = self.current.lock().unwrap();
+        for key in partitions {
+            current.remove(key);
+        }
+        self.revoked.lock().unwrap().extend_from_slice(partitions);
+    }
+}
+
+/// offsets queued on the workers and not done yet, by topic and
+/// partition, with their total count and size
+#[derive(Default)]
+struct InFlight {
+    partitions: HashMap<Partition, Pending>,
+    messages: usize,
+    bytes: usize,
+}
+
+/// in-flight offsets of one partition assignment and their sizes
+struct Pending {
+    epoch: u64,
+    offsets: BTreeMap<i64, usize>,
+    /// offset after the highest queued message
+    next: i64,
+    /// highest offset with every earlier message done
+    done_through: i64,
+    /// lowest offset with a failed handler, commits stay below it
+    failed: Option<i64>,
+}
+
+impl InFlight {
+    fn dispatched(&mut self, m: &BorrowedMessage, epoch: u64) {
+        let offset = m.offset();
+        let bytes = m.key_len() + m.payload_len();
+        let pending = self
+            .partitions
+            .entry((m.topic().to_string(), m.partition()))
+            .or_insert_with(|| Pending {
+                epoch,
+                offsets: BTreeMap::new(),
+                next: offset,
+                done_through: offset - 1,
+                failed: None,
+            });
+        if pending.offsets.insert(offset, bytes).is_none() {
+            self.messages += 1;
+            self.bytes += bytes;
+        }
+        pending.next = pending.next.max(offset + 1);
+    }
+
+    /// drop the state and handled offsets of the partitions revoked
+    /// since the last call, their in-flight messages no longer count
+    /// and are never committed
+    fn revoke(&mut self, epochs: &Epochs, handled: &mut HandledOffsets) {
+        let revoked = epochs.take_revoked();
+        for key in &revoked {
+            if let Some(pending) = self.partitions.remove(key) {
+                self.messages -= pending.offsets.len();
+                self.bytes -= pending.offsets.values().sum::<usize>();
+            }
+        }
+        handled.forget(&revoked);
+    }
+
+    /// pause or resume the partitions for the work in flight
+    fn update(
+        &self,
+        consumer: &LoggingConsumer,
+        backpressure: &mut Backpressure,
+    ) {
+        backpressure.update(consumer, self.messages, self.bytes);
+    }
+
+    /// mark a message done and commit its partition when the lowest
+    /// in-flight offset moved forward, results from a revoked
+    /// assignment epoch are ignored
+    fn complete(
+        &mut self,
+        consumer: &LoggingConsumer,
+        policy: CommitPolicy,
+        handled: &mut HandledOffsets,
+        done: Done,
+    ) -> Result<()> {
+        let key = (done.topic, done.partition);
+        let pending = match self.partitions.get_mut(&key) {
+            Some(pending) if pending.epoch == done.epoch => pending,
+            _ => return Ok(()),
+        };
+        let failed = match (done.result, policy) {
+            // stays in flight so nothing from here on is committed
+            (Err(e), CommitPolicy::AfterHandlerSuccess) => return Err(e),
+            (result, _) => result.is_err(),
+        };
+        if let Some(bytes) = pending.offsets.remove(&done.offset) {
+            self.messages -= 1;
+            self.bytes -= bytes;
+        }
+        if failed {
+            let lowest = pending
+                .failed
+                .map_or(done.offset, |failed| failed.min(done.offset));
+            pending.failed = Some(lowest);
+        }
+        let lowest = pending
+            .offsets
+            .first_key_value()
+            .map(|(offset, _)| *offset)
+            .unwrap_or(pending.next);
+        let through =
+            pending.failed.map_or(lowest, |failed| failed.min(lowest)) - 1;
+        if through <= pending.done_through {
+            return Ok(());
+        }
+        let (topic, partition) = key;
+        match commit_through(consumer, &topic, partition, through, policy) {
+            // the next completion of the partition commits again
+            Err(e) if commit_retriable(&e) => {
+                warn!(
+                    topic = topic,
+                    partition = partition,
+                    offset = through;
+                    "Commit failed, leaving the offset uncommitted: {e}"
+                );
+                return Ok(());
+            }
+            committed => committed?,
+        }
+        pending.done_through = through;
+        handled.insert(&topic, partition, through);
+        Ok(())
+    }
+}
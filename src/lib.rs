@@ -11,6 +11,8 @@
 //!
 //! By default the ``./kubernetes/deploy.sh`` script will use the included tls assets in the repo: [./kubernetes/tls](https://github.com/jay-johnson/rust-with-strimzi-kafka-and-tls/tree/main/kubernetes/tls). Before going into production with these, please change these to your own to prevent security issues.
 //!
+//! The clients refuse expired certificates at startup. Once the included certificates expire, re-issue them from their existing keys with ``./kubernetes/renew-tls.sh`` and redeploy them with ``./kubernetes/deploy.sh``.
+//!
 //! If you want to use your own tls assets you can set these environment variables:
 //!
 //! - ``CA_FILE`` - path to your Certificate Authority (CA) file
@@ -46,14 +48,30 @@
 //! 192.168.49.2   cluster-0-broker-0.redten.io cluster-0-broker-1.redten.io cluster-0-broker-2.redten.io
 //! ```
 //!
+//! To skip the ``/etc/hosts`` edits, map the broker hostnames to an ip on the client side with ``KAFKA_HOST_OVERRIDES`` or the repeatable ``--host-override`` flag of the examples and ``kafka-tls-doctor``. Connections go to the mapped address while TLS hostname verification still checks the advertised broker names. In Rust pass a ``host_overrides::HostOverrides`` to ``KafkaTlsConfig::host_overrides``:
+//!
+//! ```bash
+//! export KAFKA_HOST_OVERRIDES=cluster-0-broker-0.redten.io=192.168.49.2,cluster-0-broker-1.redten.io=192.168.49.2,cluster-0-broker-2.redten.io=192.168.49.2
+//! ```
+//!
+//! Then run the ``kafka-tls-doctor`` binary with the same ``-b`` brokers and ``KAFKA_TLS_CLIENT_*`` environment variables as the examples. It checks the client certificates, then DNS, TCP, the TLS handshake, the broker certificate chain and hostname for every broker and finishes with a Kafka metadata request:
+//!
 //! ```bash
-//! echo "ssl test" | openssl s_client -connect \
-//!     cluster-0-broker-0.redten.io:32151 \
-//!     -key ./kubernetes/tls/client-key.pem \
-//!     -cert ./kubernetes/tls/client.pem \
-//!     -CAfile ./kubernetes/tls/ca.pem \
-//!     -verify_return_error \
-//!     && echo "strimzi kafka cluster is working with self-signed tls assets!"
+//! cargo build --bin kafka-tls-doctor
+//! ./target/debug/kafka-tls-doctor \
+//!     -b cluster-0-broker-0.redten.io:32151,cluster-0-broker-1.redten.io:32152,cluster-0-broker-2.redten.io:32153
+//! ```
+//!
+//! Each broker gets a pass/fail report and the doctor exits with ``1`` when any step fails:
+//!
+//! ```text
+//! broker cluster-0-broker-0.redten.io:32151 PASS
+//!   [PASS] dns: 127.0.0.1
+//!   [PASS] tcp: connected to 127.0.0.1:32151 in 0ms
+//!   [PASS] tls handshake: TLSv1.3
+//!   [PASS] certificate chain: CN=cluster-0-kafka issued by CN=Example CA valid until 2027-09-22 19:27:00 UTC
+//!   [PASS] hostname: cluster-0-broker-0.redten.io matches DNS:*.redten.io
+//!   [PASS] kafka metadata: 3 brokers, 1 topics
 //! ```
 //!
 //! ## Create Kafka Topic for Rust Messaging
@@ -82,12 +100,60 @@
 //! - ``KAFKA_TLS_CLIENT_KEY`` - path to the server key file
 //! - ``KAFKA_TLS_CLIENT_CERT`` - path to the server certificate file
 //!
+//! To keep secrets off the filesystem (for example when they come from a vault) export the PEM contents instead, these take precedence over the paths:
+//!
+//! - ``KAFKA_TLS_CLIENT_CA_PEM`` - Certificate Authority PEM contents
+//! - ``KAFKA_TLS_CLIENT_KEY_PEM`` - client key PEM contents
+//! - ``KAFKA_TLS_CLIENT_CERT_PEM`` - client certificate PEM contents
+//! - ``KAFKA_TLS_CLIENT_KEY_PASSWORD`` - password for an encrypted client key
+//!
+//! The examples also accept ``--tls-stdin`` to read the client certificate, key and CA as one PEM bundle from stdin, and ``KafkaTlsConfig`` takes PEM buffers with ``ca_pem``, ``key_pem`` and ``cert_pem``.
+//!
+//! ### SASL Authentication
+//!
+//! Listeners that authenticate with SASL instead of client certificates use ``security.protocol=SASL_SSL``, only the CA is needed to verify the brokers. Pass ``--sasl-mechanism`` to either example (or export ``KAFKA_SASL_MECHANISM``):
+//!
+//! - ``SCRAM-SHA-512`` or ``SCRAM-SHA-256`` - reads ``KAFKA_SASL_USERNAME`` and ``KAFKA_SASL_PASSWORD``
+//! - ``OAUTHBEARER`` - reads the token from the ``KAFKA_OAUTH_TOKEN_FILE`` file again on every refresh
+//!
+//! In Rust use ``KafkaTlsConfig::scram`` or ``KafkaTlsConfig::oauth_bearer`` with any ``sasl::OAuthTokenProvider`` (closures work too). ``custom_context::CustomContext`` calls the provider whenever librdkafka refreshes the token.
+//!
 //! ### Set Broker Addresses
 //!
 //! Export this environment variable to the correct broker fqdns and ports:
 //!
 //! - ``KAFKA_BROKERS`` - comma delimited list of kafka brokers (format: ``cluster-0-broker-0.redten.io:32151,cluster-0-broker-1.redten.io:32152,cluster-0-broker-2.redten.io:32153``)
 //!
+//! ### Connection Profiles
+//!
+//! ``kubernetes/dev.yaml`` exposes three listeners, pick one with ``--profile`` on the examples (or export ``KAFKA_PROFILE``). The profile sets the ``security.protocol``, appends its port to broker hosts given without one and supplies the brokers when neither ``-b`` nor ``KAFKA_BROKERS`` is set:
+//!
+//! - ``plain`` - ``PLAINTEXT`` on ``9092`` inside the cluster, no tls assets needed
+//! - ``tls-internal`` - mTLS on ``9093`` inside the cluster (default when running in a kubernetes pod)
+//! - ``tls-external`` - mTLS on the nodeports ``32151``-``32153`` from outside the cluster (default everywhere else)
+//!
+//! ```bash
+//! ./target/debug/examples/run-producer --profile plain -b dev-kafka-bootstrap.dev.svc -t testing
+//! ```
+//!
+//! In Rust set it with ``KafkaTlsConfig::profile(ConnectionProfile::Plain)``.
+//!
+//! ### Build TLS Clients in Rust
+//!
+//! The ``tls_config::KafkaTlsConfig`` builder reads the same environment variables, validates the tls assets are readable PEM files and creates producers and consumers:
+//!
+//! ```rust,no_run
+//! use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+//!
+//! let config = KafkaTlsConfig::from_env("cluster-0-broker-0.redten.io:32151");
+//! let producer = config.create_producer().expect("Producer creation error");
+//! let consumer = config
+//!     .create_consumer("rust-consumer-testing")
+//!     .expect("Consumer creation failed");
+//! ```
+//!
+//! Before any client is created the CA, client certificate and key are parsed and each certificate's subject, SANs, issuer and validity period is logged. Creating the client fails fast with a ``TlsAsset`` error when a certificate is expired or not yet valid, the key does not belong to the certificate or the certificate is not signed by the CA, instead of surfacing later as an SSL handshake error. A warning is logged for certificates that expire within 30 days (change it with ``expiry_warning``). Call ``KafkaTlsConfig::inspect`` for the full report.
+//!
 //! ### Start Consumer
 //!
 //! ```bash
@@ -98,6 +164,24 @@
 //! ./target/debug/examples/run-consumer -b $KAFKA_BROKERS -g rust-consumer-testing -t testing
 //! ```
 //!
+//! Stop the consumer with ``ctrl+c`` or ``SIGTERM``. It finishes the in-flight message, commits the handled offsets and unsubscribes before exiting.
+//!
+//! Pass ``--partition-tasks`` to handle each assigned partition in its own task with ``consume_partitions::consume_partitions``, so a slow message only stalls its own partition of the ``testing`` topic. Messages within a partition are still handled in offset order, and the tasks are started and stopped from the rebalance callbacks as partitions move between group members.
+//!
+//! Pass ``--batch-size 500`` to log messages in batches of up to 500 with ``consume_batches::consume_batches``. The highest offset of every partition in a batch is committed once the whole batch was handled. The dead letter queue only handles single messages, so ``--dlq-topic`` cannot be combined with ``--batch-size``.
+//!
+//! Pass ``--key-workers 8`` to handle messages on 8 concurrent workers (``ConsumeOptions::key_workers``). Messages are hashed onto a worker by key, so events for the same user id on ``user.events`` stay in order, and a partition offset is only committed once every earlier message of that partition was handled successfully.
+//!
+//! Add ``--max-in-flight 1000`` and/or ``--max-in-flight-bytes 67108864`` to bound the work the key workers hold (``ConsumeOptions::in_flight_limits`` with a ``backpressure::InFlightLimits``). Once the received messages that are not done yet reach a limit the assigned partitions are paused with ``Consumer::pause``, and they are resumed when the work drains to half of every limit. ``kafka_consumer_pauses_total`` and ``kafka_consumer_paused_seconds_total`` show how often and how long the consumer waited on its handlers.
+//!
+//! To replay an incident without resetting the whole group, start from ``--from-beginning``, ``--from-end``, ``--from-timestamp 2026-10-17T08:30:00Z`` or one ``--offset PARTITION:OFFSET`` per partition (``TOPIC:PARTITION:OFFSET`` when ``-t`` lists more than one topic), and stop after ``--max-messages N``. Replays assign the partitions directly (``replay::assign_from`` with a ``replay::StartPosition``, timestamps are resolved with ``offsets_for_times``) instead of joining the group, so the running members keep their partitions. Handled offsets are still committed to the ``-g`` group, so ``run-consumer`` refuses to replay with the default ``example_consumer_group_id`` and the replay needs a group of its own:
+//!
+//! ```bash
+//! ./target/debug/examples/run-consumer -b $KAFKA_BROKERS -g testing-replay -t testing --from-timestamp 2026-10-17T08:30:00Z --max-messages 100
+//! ```
+//!
+//! Pass ``--watch-tls`` to rebuild the consumer when the ``KAFKA_TLS_CLIENT_*`` files are rotated (Strimzi, cert-manager or an updated kubernetes secret). The new material is validated first, then the running consumer commits its handled offsets and the new one rejoins the group. Add ``--group-instance-id $POD_NAME`` to rejoin as the same static member without a rebalance. Reloading only supports the default one-message-at-a-time loop, so ``--watch-tls`` cannot be combined with ``--batch-size`` or ``--partition-tasks``. ``run-producer --watch-tls`` rebuilds the producer the same way. Library users get the same behavior from ``cert_watcher::consume_with_reload`` and ``cert_watcher::ReloadingProducer``.
+//!
 //! ### Start Producer
 //!
 //! ```bash
@@ -107,8 +191,169 @@
 //! export RUST_LOG=info
 //! ./target/debug/examples/run-producer -b $KAFKA_BROKERS -t testing
 //! ```
+//!
+//! ### Log Format
+//!
+//! Both examples and ``kafka-tls-doctor`` take ``--log-format`` (or ``KAFKA_LOG_FORMAT``) to pick the log line layout. Every line has an RFC3339 timestamp with the date and timezone, and messages carry ``topic``, ``partition``, ``offset`` and ``key`` as structured fields:
+//!
+//! - ``text`` - ``time (t: thread) LEVEL - target - message key=value`` (default)
+//! - ``json`` - one JSON object per line with ``time``, ``level``, ``thread``, ``target``, ``module``, ``file``, ``line``, ``message`` and the record fields in a nested ``fields`` object
+//! - ``logfmt`` - the same fields as ``key=value`` pairs, a record field named like one of the line keys is written as ``fields.<name>``
+//!
+//! ```bash
+//! ./target/debug/examples/run-consumer --log-format json -b $KAFKA_BROKERS -g rust-consumer-testing -t testing
+//! ```
+//!
+//! In Rust call ``log_utils::setup_logger_with_format(LogFormat::Json, true, None)`` and log fields with the ``log`` key/value syntax: ``info!(topic = "testing", partition = 0; "received")``.
+//!
+//! ### librdkafka Logs and Statistics
+//!
+//! Clients created by ``KafkaTlsConfig`` hand librdkafka's internal log lines to the ``log`` crate instead of stderr. Each line uses a ``librdkafka::<facility>`` target (for example ``librdkafka::fail`` or ``librdkafka::broker``) with a ``facility`` field, so ``--log-conf librdkafka=debug`` or ``--log-conf librdkafka::fail=warn`` filters them like any other module. Syslog levels map to ``error``, ``warn``, ``info`` and ``debug``.
+//!
+//! Forward the statistics librdkafka emits every ``statistics.interval.ms`` to a ``custom_context::StatsHandler`` (closures work too), it receives the raw JSON and the decoded ``rdkafka::Statistics``:
+//!
+//! ```rust,no_run
+//! use std::time::Duration;
+//! use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+//!
+//! let producer = KafkaTlsConfig::from_env("cluster-0-broker-0.redten.io:32151")
+//!     .stats_handler(Duration::from_secs(15), |stats: &rdkafka::Statistics, json: &str| {
+//!         println!("{} queued={} {json}", stats.name, stats.msg_cnt);
+//!     })
+//!     .create_producer()
+//!     .expect("Producer creation error");
+//! ```
+//!
+//! ### Metrics
+//!
+//! Pass ``--metrics-addr 127.0.0.1:9464`` to ``run-consumer`` or ``run-producer`` to serve prometheus metrics on ``http://127.0.0.1:9464/metrics``. Library users share one ``metrics::Metrics`` between ``KafkaTlsConfig::metrics`` (rebalances, commit failures and the librdkafka statistics, emitted every 15 seconds unless ``stats_handler`` sets another interval) and ``PublishOptions::metrics`` or ``publish_messages::publish_messages_with_metrics`` (acknowledged records and delivery failures), then spawn ``Metrics::serve``:
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//! use rust_with_kafka_tls::metrics::Metrics;
+//! use rust_with_kafka_tls::publish_options::PublishOptions;
+//! use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+//!
+//! # async fn run() {
+//! let metrics = Arc::new(Metrics::new());
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:9464").await.unwrap();
+//! tokio::spawn(metrics.clone().serve(listener, tokio_util::sync::CancellationToken::new()));
+//! let producer = KafkaTlsConfig::from_env("cluster-0-broker-0.redten.io:32151")
+//!     .metrics(metrics.clone())
+//!     .create_producer()
+//!     .expect("Producer creation error");
+//! let options = PublishOptions::new().metrics(metrics);
+//! # }
+//! ```
+//!
+//! | Metric | Type | Labels |
+//! |---|---|---|
+//! | ``kafka_messages_consumed_total``, ``kafka_consumed_bytes_total`` | counter | ``topic`` |
+//! | ``kafka_messages_produced_total``, ``kafka_produced_bytes_total`` | counter | ``topic`` |
+//! | ``kafka_delivery_failures_total`` | counter | ``topic`` |
+//! | ``kafka_commit_failures_total`` | counter | |
+//! | ``kafka_rebalances_total`` | counter | ``kind`` |
+//! | ``kafka_consumer_pauses_total``, ``kafka_consumer_paused_seconds_total`` | counter | |
+//! | ``kafka_consumer_lag`` | gauge | ``client``, ``topic``, ``partition`` |
+//! | ``kafka_client_queue_messages``, ``kafka_client_replyq`` | gauge | ``client`` |
+//! | ``kafka_broker_rtt_avg_seconds``, ``kafka_broker_rtt_p99_seconds`` | gauge | ``client``, ``broker`` |
+//! | ``kafka_broker_outbuf_requests``, ``kafka_broker_waitresp_requests`` | gauge | ``client``, ``broker`` |
+//!
+//! The consumer lag counts the messages after the committed offset, consumers only report statistics while they are polled.
+//!
+//! ### Publish Records
+//!
+//! ``publish_records::publish_records`` sends any iterator of records (``publish_stream`` takes a ``Stream``) and returns one delivery report with the partition and offset per record:
+//!
+//! ```rust,no_run
+//! use rust_with_kafka_tls::publish_options::PublishOptions;
+//! use rust_with_kafka_tls::publish_records::publish_records;
+//! use rust_with_kafka_tls::publish_records::ProducerRecord;
+//! use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+//!
+//! # async fn run() {
+//! let producer = KafkaTlsConfig::from_env("cluster-0-broker-0.redten.io:32151")
+//!     .create_producer()
+//!     .expect("Producer creation error");
+//! let records = vec![ProducerRecord::new().key("user-1").payload("login")];
+//! for report in publish_records(&producer, "testing", records, &PublishOptions::new()).await {
+//!     println!("{report:?}");
+//! }
+//! # }
+//! ```
+//!
+//! Set a ``retry_policy::RetryPolicy`` on the ``PublishOptions`` to resend records that failed with a retriable error (full producer queue, leader not available, not enough in-sync replicas) using exponential backoff until a total deadline. Delivery timeouts are not resent because librdkafka already retried the record and the broker may have written it, and fatal errors are returned right away.
+//!
+//! ### Consume Batches
+//!
+//! ``consume_batches::consume_batches`` hands messages to a ``message_handler::BatchHandler`` in bulk. A batch is handed over once it holds ``BatchSize::messages`` messages, once the next message would take it over ``BatchSize::bytes`` bytes, or ``BatchSize::timeout`` after its first message, and the highest offset of every partition in the batch is committed with the ``CommitPolicy`` only after the handler succeeds:
+//!
+//! ```rust,no_run
+//! use async_trait::async_trait;
+//! use rdkafka::consumer::Consumer;
+//! use rdkafka::message::OwnedMessage;
+//! use rust_with_kafka_tls::consume_batches::consume_batches;
+//! use rust_with_kafka_tls::consume_batches::BatchSize;
+//! use rust_with_kafka_tls::consume_options::ConsumeOptions;
+//! use rust_with_kafka_tls::error::Result;
+//! use rust_with_kafka_tls::message_handler::BatchHandler;
+//! use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+//!
+//! struct BulkInsert;
+//!
+//! #[async_trait]
+//! impl BatchHandler for BulkInsert {
+//!     async fn handle_batch(&self, messages: &[OwnedMessage]) -> Result<()> {
+//!         println!("inserting {} rows", messages.len());
+//!         Ok(())
+//!     }
+//! }
+//!
+//! # async fn run() {
+//! let consumer = KafkaTlsConfig::from_env("cluster-0-broker-0.redten.io:32151")
+//!     .create_consumer("bulk-sink")
+//!     .expect("Consumer creation failed");
+//! consumer.subscribe(&["testing"]).expect("subscribe");
+//! let size = BatchSize::new().messages(1000).bytes(4 * 1024 * 1024);
+//! let options = ConsumeOptions::new().batch_size(size);
+//! consume_batches(&consumer, &BulkInsert, &options).await.expect("consume");
+//! # }
+//! ```
+//!
+//! ### Run the Tests
+//!
+//! The integration tests in ``./tests`` run against librdkafka's in-process mock cluster so they need no network access, kubernetes cluster or tls assets:
+//!
+//! ```bash
+//! cargo test
+//! ```
+//!
+//! ``tests/tls.rs`` puts a TLS proxy that trusts ``kubernetes/tls/ca.pem`` in front of the mock cluster to check that valid client certificates connect while expired, wrong-CA or missing client certificates are rejected.
 
+pub mod backpressure;
+pub mod cert_inspect;
+pub mod cert_watcher;
+pub mod commit_policy;
+pub mod connection_profile;
 pub mod consume_and_print;
+pub mod consume_batches;
+pub mod consume_messages;
+pub mod consume_options;
+pub mod consume_partitions;
 pub mod custom_context;
+pub mod dead_letter;
+pub mod error;
+pub mod host_overrides;
+pub mod keyed_workers;
 pub mod log_utils;
+pub mod message_handler;
+pub mod metrics;
 pub mod publish_messages;
+pub mod publish_options;
+pub mod publish_records;
+pub mod replay;
+pub mod retry_policy;
+pub mod sasl;
+pub mod shutdown;
+pub mod tls_config;
+pub mod tls_doctor;
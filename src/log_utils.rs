@@ -1,15 +1,115 @@
+use std::fmt;
+use std::fmt::Write as _;
 use std::io::Write;
+use std::str::FromStr;
 use std::thread;
 
 use chrono::prelude::*;
 use env_logger::fmt::Formatter;
 use env_logger::Builder;
+use log::kv;
+use log::kv::Key;
+use log::kv::Value;
+use log::kv::VisitSource;
 use log::LevelFilter;
 use log::Record;
+use serde_json::Map;
+use serde_json::Value as JsonValue;
+
+use crate::error::Error;
+use crate::error::Result;
+
+/// LogFormat
+///
+/// Layout of each log line written by
+/// [`setup_logger_with_format`](crate::log_utils::setup_logger_with_format).
+/// Every format starts with an RFC3339 timestamp in the local timezone
+/// and ends with the structured key/value fields of the record (for
+/// example the ``topic``, ``partition``, ``offset`` and ``key`` of a
+/// consumed message).
+///
+/// - ``text`` - ``time (t: thread) LEVEL - target - message key=value``
+/// - ``json`` - one JSON object per line with ``time``, ``level``,
+///   ``thread``, ``target``, ``module``, ``file``, ``line``,
+///   ``message`` and the record fields in a nested ``fields`` object
+/// - ``logfmt`` - the same fields as ``key=value`` pairs, a record
+///   field named like one of the line keys is written as
+///   ``fields.<name>``
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::log_utils::LogFormat;
+///
+/// let format: LogFormat = "json".parse().unwrap();
+/// assert_eq!(format, LogFormat::Json);
+/// ```
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// human readable lines
+    #[default]
+    Text,
+    /// JSON lines for log pipelines
+    Json,
+    /// ``key=value`` lines
+    Logfmt,
+}
+
+impl LogFormat {
+    /// from_env
+    ///
+    /// Format named by ``KAFKA_LOG_FORMAT``, ``text`` when it is not
+    /// set
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Config`](crate::error::Error::Config) when
+    /// ``KAFKA_LOG_FORMAT`` is not a known format
+    ///
+    pub fn from_env() -> Result<Self> {
+        match std::env::var("KAFKA_LOG_FORMAT") {
+            Ok(name) => name.parse(),
+            Err(_) => Ok(LogFormat::Text),
+        }
+    }
+
+    /// name used on the command line and in ``KAFKA_LOG_FORMAT``
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+            LogFormat::Logfmt => "logfmt",
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            "logfmt" => Ok(LogFormat::Logfmt),
+            _ => Err(Error::Config(format!(
+                "unknown log format {name}, expected text, json or logfmt"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
 
 /// setup_logger
 ///
 /// Setup a logger for message processing by consumers or producers
+/// that writes [`LogFormat::Text`](crate::log_utils::LogFormat::Text)
+/// lines
 ///
 /// # Arguments
 ///
@@ -24,24 +124,36 @@ use log::Record;
 /// ```
 ///
 pub fn setup_logger(log_thread: bool, rust_log: Option<&str>) {
-    let output_format = move |formatter: &mut Formatter, record: &Record| {
-        let thread_name = if log_thread {
-            format!("(t: {}) ", thread::current().name().unwrap_or("unknown"))
-        } else {
-            "".to_string()
-        };
+    setup_logger_with_format(LogFormat::Text, log_thread, rust_log)
+}
 
-        let local_time: DateTime<Local> = Local::now();
-        let time_str = local_time.format("%H:%M:%S%.3f").to_string();
-        writeln!(
-            formatter,
-            "{} {}{} - {} - {}",
-            time_str,
-            thread_name,
-            record.level(),
-            record.target(),
-            record.args()
-        )
+/// setup_logger_with_format
+///
+/// Setup a logger for message processing by consumers or producers
+/// that writes each record with
+/// [`format_record`](crate::log_utils::format_record)
+///
+/// # Arguments
+///
+/// * `format` - [`LogFormat`](crate::log_utils::LogFormat) of each line
+/// * `log_thread` - flag for logging the processing thread
+/// * `rust_log` - string containing the logging level for the function caller
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::log_utils::setup_logger_with_format;
+/// use rust_with_kafka_tls::log_utils::LogFormat;
+/// setup_logger_with_format(LogFormat::Json, true, Some("info"));
+/// ```
+///
+pub fn setup_logger_with_format(
+    format: LogFormat,
+    log_thread: bool,
+    rust_log: Option<&str>,
+) {
+    let output_format = move |formatter: &mut Formatter, record: &Record| {
+        writeln!(formatter, "{}", format_record(format, log_thread, record))
     };
 
     let mut builder = Builder::new();
@@ -53,3 +165,186 @@ pub fn setup_logger(log_thread: bool, rust_log: Option<&str>) {
 
     builder.init();
 }
+
+/// format_record
+///
+/// Render one log ``record`` as a line (without the trailing newline)
+/// in the ``format``, stamped with the current local time
+///
+/// # Arguments
+///
+/// * `format` - [`LogFormat`](crate::log_utils::LogFormat) of the line
+/// * `log_thread` - flag for logging the name of the current thread
+/// * `record` - log record with optional key/value fields
+///
+/// # Examples
+///
+/// ```rust
+/// use log::Level;
+/// use log::Record;
+/// use rust_with_kafka_tls::log_utils::format_record;
+/// use rust_with_kafka_tls::log_utils::LogFormat;
+///
+/// let line = format_record(
+///     LogFormat::Logfmt,
+///     false,
+///     &Record::builder()
+///         .args(format_args!("received"))
+///         .level(Level::Info)
+///         .target("consumer")
+///         .key_values(&[("partition", 3)])
+///         .build(),
+/// );
+/// assert!(line.ends_with("msg=received partition=3"));
+/// ```
+///
+pub fn format_record(
+    format: LogFormat,
+    log_thread: bool,
+    record: &Record,
+) -> String {
+    let time = Local::now().to_rfc3339_opts(SecondsFormat::Millis, false);
+    let thread = match log_thread {
+        true => Some(thread::current().name().unwrap_or("unknown").to_string()),
+        false => None,
+    };
+    let mut fields = Fields::default();
+    // visiting only fails when the visitor returns an error
+    record.key_values().visit(&mut fields).ok();
+
+    let mut line = String::new();
+    if format == LogFormat::Text {
+        line.push_str(&time);
+        line.push(' ');
+        if let Some(thread) = &thread {
+            let _ = write!(line, "(t: {thread}) ");
+        }
+        let _ = write!(
+            line,
+            "{} - {} - {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        for (key, value) in &fields.0 {
+            line.push(' ');
+            push_logfmt_pair(&mut line, key, value);
+        }
+        return line;
+    }
+
+    let mut pairs = vec![
+        ("time", JsonValue::from(time)),
+        ("level", JsonValue::from(record.level().to_string())),
+    ];
+    if let Some(thread) = thread {
+        pairs.push(("thread", JsonValue::from(thread)));
+    }
+    pairs.extend(location(record));
+    let message = match format {
+        LogFormat::Json => "message",
+        _ => "msg",
+    };
+    pairs.push((message, JsonValue::from(record.args().to_string())));
+
+    if format == LogFormat::Json {
+        // the record fields are nested so they never repeat or replace
+        // the keys of the line, which keep their order
+        if !fields.0.is_empty() {
+            let fields: Map<String, JsonValue> = fields.0.into_iter().collect();
+            pairs.push(("fields", JsonValue::Object(fields)));
+        }
+        line.push('{');
+        for (i, (key, value)) in pairs.iter().enumerate() {
+            if i > 0 {
+                line.push(',');
+            }
+            let _ = write!(line, "{}:{value}", JsonValue::from(*key));
+        }
+        line.push('}');
+        return line;
+    }
+    for (i, (key, value)) in pairs.iter().enumerate() {
+        if i > 0 {
+            line.push(' ');
+        }
+        push_logfmt_pair(&mut line, key, value);
+    }
+    for (key, value) in &fields.0 {
+        line.push(' ');
+        // a record field named like a line key is prefixed so the
+        // line never has the same key twice
+        match pairs.iter().any(|(name, _)| name == key) {
+            true => {
+                push_logfmt_pair(&mut line, &format!("fields.{key}"), value)
+            }
+            false => push_logfmt_pair(&mut line, key, value),
+        }
+    }
+    line
+}
+
+/// key/value fields of a record in logging order, numbers and
+/// booleans keep their type
+#[derive(Default)]
+struct Fields(Vec<(String, JsonValue)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(
+        &mut self,
+        key: Key<'kvs>,
+        value: Value<'kvs>,
+    ) -> std::result::Result<(), kv::Error> {
+        let field = if let Some(number) = value.to_i64() {
+            JsonValue::from(number)
+        } else if let Some(number) = value.to_u64() {
+            JsonValue::from(number)
+        } else if let Some(number) =
+            value.to_f64().and_then(serde_json::Number::from_f64)
+        {
+            JsonValue::Number(number)
+        } else if let Some(flag) = value.to_bool() {
+            JsonValue::from(flag)
+        } else {
+            JsonValue::from(value.to_string())
+        };
+        self.0.push((key.to_string(), field));
+        Ok(())
+    }
+}
+
+/// ``target``, ``module``, ``file`` and ``line`` of a record, the
+/// last three only when the record has them
+fn location(record: &Record) -> Vec<(&'static str, JsonValue)> {
+    let mut pairs = vec![("target", JsonValue::from(record.target()))];
+    if let Some(module) = record.module_path() {
+        pairs.push(("module", JsonValue::from(module)));
+    }
+    if let Some(file) = record.file() {
+        pairs.push(("file", JsonValue::from(file)));
+    }
+    if let Some(number) = record.line() {
+        pairs.push(("line", JsonValue::from(number)));
+    }
+    pairs
+}
+
+/// append ``key=value``, quoting text that is empty or contains
+/// spaces, quotes, ``=`` or control characters as a JSON string
+fn push_logfmt_pair(line: &mut String, key: &str, value: &JsonValue) {
+    line.push_str(key);
+    line.push('=');
+    match value {
+        JsonValue::String(text)
+            if !text.is_empty()
+                && !text.chars().any(|c| {
+                    c.is_whitespace() || c.is_control() || c == '"' || c == '='
+                }) =>
+        {
+            line.push_str(text)
+        }
+        value => {
+            let _ = write!(line, "{value}");
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use async_trait::async_trait;
+use rdkafka::message::OwnedMessage;
+
+use crate::error::Result;
+
+/// MessageHandler
+///
+/// Business logic driven by the consume loop in
+/// [`consume_messages`](crate::consume_messages::consume_messages).
+/// The loop receives each message, hands it to the handler and
+/// commits the offset only after the handler returns ``Ok``.
+///
+/// # Examples
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use rdkafka::message::Message;
+/// use rdkafka::message::OwnedMessage;
+/// use rust_with_kafka_tls::error::Result;
+/// use rust_with_kafka_tls::message_handler::MessageHandler;
+///
+/// struct CountBytes;
+///
+/// #[async_trait]
+/// impl MessageHandler for CountBytes {
+///     async fn handle(&self, message: &OwnedMessage) -> Result<()> {
+///         let size = message.payload().map(|p| p.len()).unwrap_or(0);
+///         println!("offset={} bytes={size}", message.offset());
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+#[async_trait]
+pub trait MessageHandler: Send + Sync {
+    /// handle
+    ///
+    /// Process one ``message``. Returning an error leaves the
+    /// message offset uncommitted.
+    ///
+    async fn handle(&self, message: &OwnedMessage) -> Result<()>;
+}
+
+/// BatchHandler
+///
+/// Business logic driven by the batch loop in
+/// [`consume_batches`](crate::consume_batches::consume_batches) for
+/// sinks that write in bulk. The loop collects messages into a batch,
+/// hands the whole batch to the handler and commits the highest
+/// offset of every partition in the batch only after the handler
+/// returns ``Ok``.
+///
+/// # Examples
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use rdkafka::message::Message;
+/// use rdkafka::message::OwnedMessage;
+/// use rust_with_kafka_tls::error::Result;
+/// use rust_with_kafka_tls::message_handler::BatchHandler;
+///
+/// struct CountBatches;
+///
+/// #[async_trait]
+/// impl BatchHandler for CountBatches {
+///     async fn handle_batch(&self, messages: &[OwnedMessage]) -> Result<()> {
+///         let bytes: usize = messages
+///             .iter()
+///             .map(|m| m.payload().map(|p| p.len()).unwrap_or(0))
+///             .sum();
+///         println!("messages={} bytes={bytes}", messages.len());
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+#[async_trait]
+pub trait BatchHandler: Send + Sync {
+    /// handle_batch
+    ///
+    /// Process a batch of ``messages`` in the order they were
+    /// received. Returning an error leaves every offset of the batch
+    /// uncommitted.
+    ///
+    async fn handle_batch(&self, messages: &[OwnedMessage]) -> Result<()>;
+}
@@ -0,0 +1,427 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::debug;
+use log::info;
+use log::warn;
+use rdkafka::consumer::Rebalance;
+use rdkafka::Statistics;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+/// ``statistics.interval.ms`` used for clients with
+/// [`Metrics`](crate::metrics::Metrics) when no stats handler interval
+/// is set
+pub const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(15);
+
+/// largest http request head the ``/metrics`` endpoint reads
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// how long the ``/metrics`` endpoint waits for a client to send its
+/// request head and to read the response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// name, prometheus type and help text of every metric family in
+/// exposition order
+const FAMILIES: &[(&str, &str, &str)] = &[
+    (
+        "kafka_messages_consumed_total",
+        "counter",
+        "Messages received by the consume loop",
+    ),
+    (
+        "kafka_consumed_bytes_total",
+        "counter",
+        "Key and payload bytes received by the consume loop",
+    ),
+    (
+        "kafka_messages_produced_total",
+        "counter",
+        "Records acknowledged by the brokers",
+    ),
+    (
+        "kafka_produced_bytes_total",
+        "counter",
+        "Key and payload bytes of the acknowledged records",
+    ),
+    (
+        "kafka_delivery_failures_total",
+        "counter",
+        "Records that failed delivery after all retries",
+    ),
+    (
+        "kafka_commit_failures_total",
+        "counter",
+        "Offset commits rejected by the group coordinator",
+    ),
+    (
+        "kafka_rebalances_total",
+        "counter",
+        "Consumer group rebalances by kind (assign, revoke or error)",
+    ),
+    (
+        "kafka_consumer_pauses_total",
+        "counter",
+        "Times the consume loop paused its partitions at an in-flight limit",
+    ),
+    (
+        "kafka_consumer_paused_seconds_total",
+        "counter",
+        "Seconds the consume loop kept its partitions paused",
+    ),
+    (
+        "kafka_consumer_lag",
+        "gauge",
+        "Messages between the consumer position and the high watermark",
+    ),
+    (
+        "kafka_client_queue_messages",
+        "gauge",
+        "Messages waiting in the librdkafka producer queues",
+    ),
+    (
+        "kafka_client_replyq",
+        "gauge",
+        "Operations waiting in the librdkafka reply queue",
+    ),
+    (
+        "kafka_broker_rtt_avg_seconds",
+        "gauge",
+        "Average broker request round trip time",
+    ),
+    (
+        "kafka_broker_rtt_p99_seconds",
+        "gauge",
+        "99th percentile broker request round trip time",
+    ),
+    (
+        "kafka_broker_outbuf_requests",
+        "gauge",
+        "Requests waiting to be sent to the broker",
+    ),
+    (
+        "kafka_broker_waitresp_requests",
+        "gauge",
+        "Requests sent to the broker and waiting for a response",
+    ),
+];
+
+/// label names and values of one series
+type Labels = Vec<(&'static str, String)>;
+
+/// Metrics
+///
+/// Prometheus counters and gauges for producers and consumers. The
+/// consume loop counts received messages and bytes,
+/// [`publish_records`](crate::publish_records::publish_records) counts
+/// acknowledged records and delivery failures when the
+/// [`PublishOptions`](crate::publish_options::PublishOptions) have
+/// the metrics, and
+/// [`CustomContext`](crate::custom_context::CustomContext) counts
+/// rebalances and commit failures and keeps the consumer lag, round
+/// trip times and queue depths from the librdkafka statistics. The
+/// consume loop also counts how often and how long it paused its
+/// partitions at the
+/// [`InFlightLimits`](crate::backpressure::InFlightLimits).
+/// [`serve`](crate::metrics::Metrics::serve) exposes everything on
+/// ``/metrics``.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use rust_with_kafka_tls::metrics::Metrics;
+/// use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+///
+/// let metrics = Arc::new(Metrics::new());
+/// let config = KafkaTlsConfig::new("localhost:9093").metrics(metrics.clone());
+/// metrics.message_produced("testing", 12);
+/// assert!(metrics
+///     .render()
+///     .contains("kafka_messages_produced_total{topic=\"testing\"} 1"));
+/// ```
+///
+#[derive(Debug, Default)]
+pub struct Metrics {
+    series: Mutex<BTreeMap<&'static str, BTreeMap<Labels, f64>>>,
+}
+
+impl Metrics {
+    /// metrics with every series at zero
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// count a message with ``bytes`` of key and payload received from
+    /// ``topic``
+    pub fn message_consumed(&self, topic: &str, bytes: usize) {
+        let labels = vec![("topic", topic.to_string())];
+        self.add("kafka_messages_consumed_total", labels.clone(), 1.0);
+        self.add("kafka_consumed_bytes_total", labels, bytes as f64);
+    }
+
+    /// count a record with ``bytes`` of key and payload acknowledged by
+    /// ``topic``
+    pub fn message_produced(&self, topic: &str, bytes: usize) {
+        let labels = vec![("topic", topic.to_string())];
+        self.add("kafka_messages_produced_total", labels.clone(), 1.0);
+        self.add("kafka_produced_bytes_total", labels, bytes as f64);
+    }
+
+    /// count a record that could not be delivered to ``topic``
+    pub fn delivery_failed(&self, topic: &str) {
+        let labels = vec![("topic", topic.to_string())];
+        self.add("kafka_delivery_failures_total", labels, 1.0);
+    }
+
+    /// count a failed offset commit
+    pub fn commit_failed(&self) {
+        self.add("kafka_commit_failures_total", Vec::new(), 1.0);
+    }
+
+    /// count a consumer group ``rebalance``
+    pub fn rebalance(&self, rebalance: &Rebalance) {
+        let kind = match rebalance {
+            Rebalance::Assign(_) => "assign",
+            Rebalance::Revoke(_) => "revoke",
+            Rebalance::Error(_) => "error",
+        };
+        let labels = vec![("kind", kind.to_string())];
+        self.add("kafka_rebalances_total", labels, 1.0);
+    }
+
+    /// count a pause of the consume loop at an in-flight limit
+    pub fn consumer_paused(&self) {
+        self.add("kafka_consumer_pauses_total", Vec::new(), 1.0);
+    }
+
+    /// count the time the consume loop was ``paused`` once it resumes
+    pub fn consumer_resumed(&self, paused: Duration) {
+        self.add(
+            "kafka_consumer_paused_seconds_total",
+            Vec::new(),
+            paused.as_secs_f64(),
+        );
+    }
+
+    /// record_stats
+    ///
+    /// Replace the gauges of the client in ``stats`` with its consumer
+    /// lag per partition, queue depths and broker round trip times
+    ///
+    pub fn record_stats(&self, stats: &Statistics) {
+        let client = || ("client", stats.name.clone());
+        let mut gauges: Vec<(&'static str, Labels, f64)> = vec![
+            (
+                "kafka_client_queue_messages",
+                vec![client()],
+                stats.msg_cnt as f64,
+            ),
+            ("kafka_client_replyq", vec![client()], stats.replyq as f64),
+        ];
+        for broker in stats.brokers.values() {
+            let labels = vec![client(), ("broker", broker.name.clone())];
+            if let Some(rtt) = &broker.rtt {
+                gauges.push((
+                    "kafka_broker_rtt_avg_seconds",
+                    labels.clone(),
+                    rtt.avg as f64 / 1e6,
+                ));
+                gauges.push((
+                    "kafka_broker_rtt_p99_seconds",
+                    labels.clone(),
+                    rtt.p99 as f64 / 1e6,
+                ));
+            }
+            gauges.push((
+                "kafka_broker_outbuf_requests",
+                labels.clone(),
+                broker.outbuf_cnt as f64,
+            ));
+            gauges.push((
+                "kafka_broker_waitresp_requests",
+                labels,
+                broker.waitresp_cnt as f64,
+            ));
+        }
+        if stats.client_type == "consumer" {
+            for topic in stats.topics.values() {
+                // partition -1 holds messages without a partition yet
+                for partition in topic.partitions.values().filter(|p| {
+                    p.partition >= 0 && p.desired && p.consumer_lag >= 0
+                }) {
+                    gauges.push((
+                        "kafka_consumer_lag",
+                        vec![
+                            client(),
+                            ("topic", topic.topic.clone()),
+                            ("partition", partition.partition.to_string()),
+                        ],
+                        partition.consumer_lag as f64,
+                    ));
+                }
+            }
+        }
+
+        let mut series = self.series.lock().unwrap();
+        // drop the gauges of partitions and brokers the client no
+        // longer reports
+        for (name, kind, _) in FAMILIES {
+            if *kind != "gauge" {
+                continue;
+            }
+            if let Some(family) = series.get_mut(name) {
+                family.retain(|labels, _| {
+                    !labels.contains(&("client", stats.name.clone()))
+                });
+            }
+        }
+        for (name, labels, value) in gauges {
+            series.entry(name).or_default().insert(labels, value);
+        }
+    }
+
+    /// render
+    ///
+    /// Every series in the prometheus text exposition format
+    ///
+    pub fn render(&self) -> String {
+        let series = self.series.lock().unwrap();
+        let mut text = String::new();
+        for (name, kind, help) in FAMILIES {
+            let family = match series.get(name) {
+                Some(family) if !family.is_empty() => family,
+                _ => continue,
+            };
+            let _ = writeln!(text, "# HELP {name} {help}");
+            let _ = writeln!(text, "# TYPE {name} {kind}");
+            for (labels, value) in family {
+                text.push_str(name);
+                if !labels.is_empty() {
+                    text.push('{');
+                    for (i, (label, value)) in labels.iter().enumerate() {
+                        if i > 0 {
+                            text.push(',');
+                        }
+                        let _ = write!(text, "{label}=\"{}\"", escape(value));
+                    }
+                    text.push('}');
+                }
+                let _ = writeln!(text, " {value}");
+            }
+        }
+        text
+    }
+
+    /// serve
+    ///
+    /// Answer ``GET /metrics`` on the ``listener`` with
+    /// [`render`](crate::metrics::Metrics::render) until the
+    /// ``shutdown`` token is cancelled
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::sync::Arc;
+    /// use tokio::net::TcpListener;
+    /// use tokio_util::sync::CancellationToken;
+    /// use rust_with_kafka_tls::metrics::Metrics;
+    ///
+    /// # async fn run() {
+    /// let metrics = Arc::new(Metrics::new());
+    /// let listener = TcpListener::bind("127.0.0.1:9464").await.unwrap();
+    /// tokio::spawn(metrics.clone().serve(listener, CancellationToken::new()));
+    /// # }
+    /// ```
+    ///
+    pub async fn serve(
+        self: Arc<Self>,
+        listener: TcpListener,
+        shutdown: CancellationToken,
+    ) {
+        if let Ok(addr) = listener.local_addr() {
+            info!("serving metrics on http://{addr}/metrics");
+        }
+        loop {
+            let stream = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("metrics endpoint accept failed: {e}");
+                        continue;
+                    }
+                },
+            };
+            let metrics = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = metrics.respond(stream).await {
+                    debug!("metrics request failed: {e}");
+                }
+            });
+        }
+    }
+
+    /// answer one http request and close the connection, a client
+    /// that is slower than ``REQUEST_TIMEOUT`` to send the request or
+    /// read the response is dropped
+    async fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let head =
+            tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+                .await??;
+        let head = String::from_utf8_lossy(&head);
+        let mut request = head.lines().next().unwrap_or("").split(' ');
+        let (status, body) = match (request.next(), request.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "GET only\n".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\n\
+            Content-Type: text/plain; version=0.0.4\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n{body}",
+            body.len()
+        );
+        tokio::time::timeout(REQUEST_TIMEOUT, async {
+            stream.write_all(response.as_bytes()).await?;
+            stream.shutdown().await
+        })
+        .await?
+    }
+
+    fn add(&self, name: &'static str, labels: Labels, value: f64) {
+        let mut series = self.series.lock().unwrap();
+        *series.entry(name).or_default().entry(labels).or_default() += value;
+    }
+}
+
+/// read an http request head of up to ``MAX_REQUEST_HEAD`` bytes
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n")
+        && head.len() < MAX_REQUEST_HEAD
+    {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+    Ok(head)
+}
+
+/// escape a label value for the text exposition format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,15 +1,26 @@
-use log::info;
+use std::sync::Arc;
 use std::time::Duration;
 
-use rdkafka::message::OwnedHeaders;
+use log::info;
+use log::warn;
+
+use rdkafka::client::ClientContext;
 use rdkafka::producer::FutureProducer;
-use rdkafka::producer::FutureRecord;
+
+use crate::error::Result;
+use crate::metrics::Metrics;
+use crate::publish_options::PublishOptions;
+use crate::publish_records::publish_records;
+use crate::publish_records::ProducerRecord;
+use crate::retry_policy::RetryPolicy;
 
 /// publish_messages
 ///
-/// Publish messages to a kafka ``topic_name`` with an initialized
+/// Publish five demo messages to a kafka ``topic_name`` with an initialized
 /// [`rdkafka::producer::FutureProducer`](rdkafka::producer::FutureProducer)
-/// using client tls assets based off environment variables.
+/// using client tls assets based off environment variables. Use
+/// [`publish_records`](crate::publish_records::publish_records)
+/// to publish real traffic.
 ///
 /// ## Optional - Set TLS Asset Paths
 ///
@@ -31,8 +42,8 @@ use rdkafka::producer::FutureRecord;
 /// # Arguments
 ///
 /// * `producer` - initialized
-/// [`rdkafka::producer::FutureProducer`](rdkafka::producer::FutureProducer)
-/// that will publish messages to the kafka ``topic_name``
+///   [`rdkafka::producer::FutureProducer`](rdkafka::producer::FutureProducer)
+///   that will publish messages to the kafka ``topic_name``
 /// * `topic_name` - publish messages this kafka topic
 ///
 /// # Examples
@@ -48,34 +59,83 @@ use rdkafka::producer::FutureRecord;
 /// ./target/debug/examples/run-producer -b $KAFKA_BROKERS -t testing
 /// ```
 ///
-pub async fn publish_messages(producer: &FutureProducer, topic_name: &str) {
-    // This loop is non blocking: all messages will be sent one after the other, without waiting
-    // for the results.
-    let futures = (0..5)
-        .map(|i| async move {
-            // The send operation on the topic returns a future, which will be
-            // completed once the result or failure from Kafka is received.
-            let delivery_status = producer
-                .send(
-                    FutureRecord::to(topic_name)
-                        .payload(&format!("Message {}", i))
-                        .key(&format!("Key {}", i))
-                        .headers(
-                            OwnedHeaders::new()
-                                .add("header_key", "header_value"),
-                        ),
-                    Duration::from_secs(0),
-                )
-                .await;
+/// # Errors
+///
+/// Retriable failures like a full producer queue or a leader
+/// election are retried with exponential backoff for up to 30
+/// seconds. Waits for every delivery report and returns the first
+/// failure as
+/// [`Error::Delivery`](crate::error::Error::Delivery) or
+/// [`Error::Timeout`](crate::error::Error::Timeout)
+///
+pub async fn publish_messages<C: ClientContext + 'static>(
+    producer: &FutureProducer<C>,
+    topic_name: &str,
+) -> Result<()> {
+    publish_messages_with_metrics(producer, topic_name, None).await
+}
 
-            // This will be executed when the result is received.
-            info!("Delivery status for message {} received", i);
-            delivery_status
-        })
-        .collect::<Vec<_>>();
+/// publish_messages_with_metrics
+///
+/// Publish the demo messages like
+/// [`publish_messages`](crate::publish_messages::publish_messages)
+/// and count the acknowledged records and delivery failures in
+/// ``metrics``
+///
+/// # Arguments
+///
+/// * `producer` - initialized
+///   [`rdkafka::producer::FutureProducer`](rdkafka::producer::FutureProducer)
+///   that will publish messages to the kafka ``topic_name``
+/// * `topic_name` - publish messages this kafka topic
+/// * `metrics` - optional [`Metrics`](crate::metrics::Metrics) shared
+///   with the ``/metrics`` endpoint
+///
+/// # Errors
+///
+/// Same as
+/// [`publish_messages`](crate::publish_messages::publish_messages)
+///
+pub async fn publish_messages_with_metrics<C: ClientContext + 'static>(
+    producer: &FutureProducer<C>,
+    topic_name: &str,
+    metrics: Option<Arc<Metrics>>,
+) -> Result<()> {
+    let records = (0..5).map(|i| {
+        ProducerRecord::new()
+            .payload(format!("Message {}", i))
+            .key(format!("Key {}", i))
+            .header("header_key", "header_value")
+    });
+    let mut options = PublishOptions::new()
+        .queue_timeout(Duration::from_secs(1))
+        .retry(RetryPolicy::new().deadline(Duration::from_secs(30)));
+    if let Some(metrics) = metrics {
+        options = options.metrics(metrics);
+    }
+    let reports =
+        publish_records(producer, topic_name, records, &options).await;
 
-    // This loop will wait until all delivery statuses have been received.
-    for future in futures {
-        info!("Future completed. Result: {:?}", future.await);
+    // keep the first failure for the caller
+    let mut first_error = None;
+    for (i, report) in reports.into_iter().enumerate() {
+        match report {
+            Ok(report) => info!(
+                topic = topic_name,
+                partition = report.partition,
+                offset = report.offset;
+                "Delivery status for message {i} received"
+            ),
+            Err(e) => {
+                warn!("Delivery failed for message {i}: {e}");
+                if first_error.is_none() {
+                    first_error = Some(e);
+                }
+            }
+        }
+    }
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
     }
 }
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::metrics::Metrics;
+use crate::retry_policy::RetryPolicy;
+
+/// PublishOptions
+///
+/// Settings for
+/// [`publish_records`](crate::publish_records::publish_records) and
+/// [`publish_stream`](crate::publish_records::publish_stream)
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use rust_with_kafka_tls::publish_options::PublishOptions;
+///
+/// let options = PublishOptions::new()
+///     .queue_timeout(Duration::from_secs(1))
+///     .max_in_flight(500);
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct PublishOptions {
+    pub(crate) queue_timeout: Duration,
+    pub(crate) max_in_flight: usize,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) metrics: Option<Arc<Metrics>>,
+}
+
+impl Default for PublishOptions {
+    fn default() -> Self {
+        PublishOptions {
+            queue_timeout: Duration::from_secs(0),
+            max_in_flight: 1000,
+            retry: None,
+            metrics: None,
+        }
+    }
+}
+
+impl PublishOptions {
+    /// new
+    ///
+    /// Create options that fail immediately when the librdkafka
+    /// producer queue is full, allow 1000 records in flight and do
+    /// not retry failed deliveries
+    ///
+    pub fn new() -> Self {
+        PublishOptions::default()
+    }
+
+    /// how long to wait for space in the librdkafka producer queue
+    /// before a record fails with ``QueueFull``
+    pub fn queue_timeout(mut self, timeout: Duration) -> Self {
+        self.queue_timeout = timeout;
+        self
+    }
+
+    /// maximum records waiting for a delivery report at once
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// retry
+    ///
+    /// Resend records that failed with a retriable error using the
+    /// exponential backoff and deadline from ``policy``. Fatal errors
+    /// are returned on the first attempt.
+    ///
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// count acknowledged records and delivery failures in ``metrics``
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+}
@@ -0,0 +1,278 @@
+use futures::stream;
+use futures::Stream;
+use futures::StreamExt;
+use log::warn;
+use rdkafka::client::ClientContext;
+use rdkafka::message::Header;
+use rdkafka::message::OwnedHeaders;
+use rdkafka::producer::FutureProducer;
+use rdkafka::producer::FutureRecord;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::publish_options::PublishOptions;
+
+/// ProducerRecord
+///
+/// One message to publish with
+/// [`publish_records`](crate::publish_records::publish_records)
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::publish_records::ProducerRecord;
+///
+/// let record = ProducerRecord::new()
+///     .key("user-1")
+///     .payload(r#"{"event": "login"}"#)
+///     .header("source", "web")
+///     .partition(2);
+/// ```
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProducerRecord {
+    /// optional message key used for partitioning
+    pub key: Option<Vec<u8>>,
+    /// optional message payload
+    pub payload: Option<Vec<u8>>,
+    /// message headers in insertion order
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// explicit partition, otherwise the partitioner picks one
+    pub partition: Option<i32>,
+    /// message timestamp in milliseconds since the unix epoch
+    pub timestamp: Option<i64>,
+}
+
+impl ProducerRecord {
+    /// new
+    ///
+    /// Create an empty record without key, payload or headers
+    ///
+    pub fn new() -> Self {
+        ProducerRecord::default()
+    }
+
+    /// set the message key
+    pub fn key<K: AsRef<[u8]>>(mut self, key: K) -> Self {
+        self.key = Some(key.as_ref().to_vec());
+        self
+    }
+
+    /// set the message payload
+    pub fn payload<P: AsRef<[u8]>>(mut self, payload: P) -> Self {
+        self.payload = Some(payload.as_ref().to_vec());
+        self
+    }
+
+    /// append a message header
+    pub fn header<V: AsRef<[u8]>>(mut self, name: &str, value: V) -> Self {
+        self.headers
+            .push((name.to_string(), value.as_ref().to_vec()));
+        self
+    }
+
+    /// publish to this partition
+    pub fn partition(mut self, partition: i32) -> Self {
+        self.partition = Some(partition);
+        self
+    }
+
+    /// set the message timestamp in milliseconds since the unix epoch
+    pub fn timestamp(mut self, timestamp: i64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// bytes of key and payload
+    pub(crate) fn size(&self) -> usize {
+        self.key.as_ref().map_or(0, Vec::len)
+            + self.payload.as_ref().map_or(0, Vec::len)
+    }
+
+    /// build the borrowed rdkafka record for ``topic``
+    pub(crate) fn to_future_record<'a>(
+        &'a self,
+        topic: &'a str,
+    ) -> FutureRecord<'a, [u8], [u8]> {
+        let mut record = FutureRecord::to(topic);
+        if let Some(key) = &self.key {
+            record = record.key(&key[..]);
+        }
+        if let Some(payload) = &self.payload {
+            record = record.payload(&payload[..]);
+        }
+        if !self.headers.is_empty() {
+            let headers = self.headers.iter().fold(
+                OwnedHeaders::new(),
+                |headers, (name, value)| {
+                    headers.insert(Header {
+                        key: name,
+                        value: Some(&value[..]),
+                    })
+                },
+            );
+            record = record.headers(headers);
+        }
+        if let Some(partition) = self.partition {
+            record = record.partition(partition);
+        }
+        if let Some(timestamp) = self.timestamp {
+            record = record.timestamp(timestamp);
+        }
+        record
+    }
+}
+
+/// DeliveryReport
+///
+/// Where kafka stored a published record
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeliveryReport {
+    /// partition the record was written to
+    pub partition: i32,
+    /// offset of the record within the partition
+    pub offset: i64,
+}
+
+/// publish_records
+///
+/// Publish every record from ``records`` to the kafka ``topic_name``
+/// without waiting for each delivery before sending the next one,
+/// then wait for all the delivery reports.
+///
+/// # Arguments
+///
+/// * `producer` - initialized
+///   [`rdkafka::producer::FutureProducer`](rdkafka::producer::FutureProducer)
+/// * `topic_name` - publish the records to this kafka topic
+/// * `records` - iterator of
+///   [`ProducerRecord`](crate::publish_records::ProducerRecord)
+/// * `options` - [`PublishOptions`](crate::publish_options::PublishOptions)
+///   for the queue timeout, in-flight limit and retry policy
+///
+/// # Returns
+///
+/// One delivery result per record in the same order as ``records``.
+/// Failures are [`Error::Delivery`](crate::error::Error::Delivery) or
+/// [`Error::Timeout`](crate::error::Error::Timeout).
+///
+pub async fn publish_records<C, I>(
+    producer: &FutureProducer<C>,
+    topic_name: &str,
+    records: I,
+    options: &PublishOptions,
+) -> Vec<Result<DeliveryReport>>
+where
+    C: ClientContext + 'static,
+    I: IntoIterator<Item = ProducerRecord>,
+{
+    publish_stream(producer, topic_name, stream::iter(records), options).await
+}
+
+/// publish_stream
+///
+/// Same as [`publish_records`](crate::publish_records::publish_records)
+/// for an async [`Stream`](futures::Stream) of records
+///
+pub async fn publish_stream<C, S>(
+    producer: &FutureProducer<C>,
+    topic_name: &str,
+    records: S,
+    options: &PublishOptions,
+) -> Vec<Result<DeliveryReport>>
+where
+    C: ClientContext + 'static,
+    S: Stream<Item = ProducerRecord>,
+{
+    records
+        .map(|record| async move {
+            publish_record(producer, topic_name, &record, options).await
+        })
+        .buffered(options.max_in_flight)
+        .collect()
+        .await
+}
+
+/// publish_record
+///
+/// Publish one record and wait for the delivery report. With a
+/// [`RetryPolicy`](crate::retry_policy::RetryPolicy) set on
+/// ``options`` the record is resent after retriable failures until
+/// it is delivered, a fatal error occurs or the deadline passes. An
+/// attempt still waiting for its delivery report at the deadline
+/// fails with [`Error::Timeout`](crate::error::Error::Timeout) and
+/// may still be delivered.
+///
+pub async fn publish_record<C: ClientContext + 'static>(
+    producer: &FutureProducer<C>,
+    topic_name: &str,
+    record: &ProducerRecord,
+    options: &PublishOptions,
+) -> Result<DeliveryReport> {
+    let delivered = deliver(producer, topic_name, record, options).await;
+    if let Some(metrics) = &options.metrics {
+        match &delivered {
+            Ok(_) => metrics.message_produced(topic_name, record.size()),
+            Err(_) => metrics.delivery_failed(topic_name),
+        }
+    }
+    delivered
+}
+
+/// send the record until it is delivered or the retry policy gives up
+async fn deliver<C: ClientContext + 'static>(
+    producer: &FutureProducer<C>,
+    topic_name: &str,
+    record: &ProducerRecord,
+    options: &PublishOptions,
+) -> Result<DeliveryReport> {
+    let mut schedule = options.retry.as_ref().map(|policy| policy.start());
+    let mut attempt = 1;
+    loop {
+        let sent = send(producer, topic_name, record, options);
+        let sent = match &schedule {
+            // no attempt may overrun the deadline waiting for its report
+            Some(schedule) => tokio::time::timeout(schedule.remaining(), sent)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(Error::Timeout(format!(
+                        "publish to {topic_name} passed the retry deadline"
+                    )))
+                }),
+            None => sent.await,
+        };
+        let error = match sent {
+            Ok(report) => return Ok(report),
+            Err(e) => e,
+        };
+        let delay = match schedule.as_mut() {
+            Some(schedule) if error.is_retriable() => schedule.next_delay(),
+            _ => None,
+        };
+        let delay = match delay {
+            Some(delay) => delay,
+            None => return Err(error),
+        };
+        warn!(
+            "Publish to {topic_name} failed attempt={attempt}, \
+            retrying in {delay:?}: {error}"
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// send the record once and wait for the delivery report
+async fn send<C: ClientContext + 'static>(
+    producer: &FutureProducer<C>,
+    topic_name: &str,
+    record: &ProducerRecord,
+    options: &PublishOptions,
+) -> Result<DeliveryReport> {
+    producer
+        .send(record.to_future_record(topic_name), options.queue_timeout)
+        .await
+        .map(|(partition, offset)| DeliveryReport { partition, offset })
+        .map_err(|(e, _)| Error::delivery(e))
+}
@@ -0,0 +1,315 @@
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Utc;
+use log::info;
+use rdkafka::consumer::Consumer;
+use rdkafka::topic_partition_list::Offset;
+use rdkafka::topic_partition_list::TopicPartitionList;
+
+use crate::custom_context::LoggingConsumer;
+use crate::error::Error;
+use crate::error::Result;
+
+/// StartPosition
+///
+/// Where a consumer starts reading with
+/// [`assign_from`](crate::replay::assign_from). Every position other
+/// than ``Committed`` assigns the partitions directly instead of
+/// joining the consumer group, so the running group members keep
+/// their partitions while an incident is replayed.
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::replay::StartPosition;
+///
+/// let position = StartPosition::from_rfc3339("2026-10-17T08:30:00Z").unwrap();
+/// assert!(matches!(position, StartPosition::Timestamp(_)));
+/// let position = StartPosition::from_partition_offsets(["testing:2:42", "3:7"]);
+/// assert_eq!(
+///     position.unwrap(),
+///     StartPosition::Offsets(vec![
+///         (Some("testing".to_string()), 2, 42),
+///         (None, 3, 7),
+///     ])
+/// );
+/// ```
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum StartPosition {
+    /// subscribe and continue from the group's committed offsets
+    #[default]
+    Committed,
+    /// every partition from its earliest retained message
+    Beginning,
+    /// every partition from its next new message
+    End,
+    /// only the listed ``(topic, partition, offset)`` partitions, a
+    /// ``None`` topic is the one topic the consumer reads
+    Offsets(Vec<(Option<String>, i32, i64)>),
+    /// every partition from its first message at or after the
+    /// timestamp, found with ``offsets_for_times``
+    Timestamp(DateTime<Utc>),
+}
+
+impl StartPosition {
+    /// from_rfc3339
+    ///
+    /// Start at an RFC3339 timestamp such as
+    /// ``2026-10-17T08:30:00Z``
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`](crate::error::Error::Config) when
+    /// ``timestamp`` is not RFC3339
+    ///
+    pub fn from_rfc3339(timestamp: &str) -> Result<Self> {
+        DateTime::parse_from_rfc3339(timestamp)
+            .map(|at| StartPosition::Timestamp(at.with_timezone(&Utc)))
+            .map_err(|e| {
+                Error::Config(format!(
+                    "timestamp {timestamp} is not RFC3339: {e}"
+                ))
+            })
+    }
+
+    /// from_partition_offsets
+    ///
+    /// Start the listed partitions at explicit offsets, each value is
+    /// formatted as ``TOPIC:PARTITION:OFFSET`` or, when the consumer
+    /// reads a single topic, as ``PARTITION:OFFSET``
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Config`](crate::error::Error::Config) when a
+    /// value is not ``TOPIC:PARTITION:OFFSET`` or ``PARTITION:OFFSET``
+    /// or lists a partition twice
+    ///
+    pub fn from_partition_offsets<'a>(
+        values: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self> {
+        let mut offsets: Vec<(Option<String>, i32, i64)> = Vec::new();
+        for value in values {
+            let invalid = || {
+                Error::Config(format!(
+                    "offset {value} is not formatted as \
+                    TOPIC:PARTITION:OFFSET or PARTITION:OFFSET"
+                ))
+            };
+            // topic names cannot hold a colon
+            let parts: Vec<&str> = value.split(':').map(str::trim).collect();
+            let (topic, partition, offset) = match parts[..] {
+                [partition, offset] => (None, partition, offset),
+                [topic, partition, offset] if !topic.is_empty() => {
+                    (Some(topic.to_string()), partition, offset)
+                }
+                _ => return Err(invalid()),
+            };
+            let partition: i32 = partition.parse().map_err(|_| invalid())?;
+            let offset: i64 = offset.parse().map_err(|_| invalid())?;
+            if partition < 0 || offset < 0 {
+                return Err(invalid());
+            }
+            if offsets
+                .iter()
+                .any(|(t, p, _)| *t == topic && *p == partition)
+            {
+                return Err(Error::Config(format!(
+                    "partition {partition} of {} has more than one offset",
+                    topic.as_deref().unwrap_or("the topic")
+                )));
+            }
+            offsets.push((topic, partition, offset));
+        }
+        Ok(StartPosition::Offsets(offsets))
+    }
+}
+
+/// assign_from
+///
+/// Start an initialized
+/// [`LoggingConsumer`](crate::custom_context::LoggingConsumer) on
+/// ``topics`` at a [`StartPosition`](crate::replay::StartPosition).
+/// ``Committed`` subscribes with the consumer ``group_id`` as usual,
+/// every other position looks up the partitions of each topic,
+/// resolves timestamps with ``offsets_for_times`` and assigns the
+/// partitions at their start offsets. Assigned partitions are not
+/// rebalanced and offsets handled by the consume loops are still
+/// committed to the ``group_id``, so replays must use a group of
+/// their own or they move the offsets of the running group.
+///
+/// The start offsets are part of the assignment instead of a
+/// ``seek`` after it. ``seek`` only moves a partition that is already
+/// being fetched, so messages from the committed or reset offset may
+/// already be queued by then and reach the consume loop before the
+/// replayed ones. Assigning at the offsets makes the first fetch start
+/// there.
+///
+/// # Arguments
+///
+/// * `consumer` - initialized
+///   [`LoggingConsumer`](crate::custom_context::LoggingConsumer)
+///   that is not subscribed yet
+/// * `topics` - topics to consume
+/// * `position` - [`StartPosition`](crate::replay::StartPosition)
+///   for every partition
+/// * `timeout` - how long the metadata and timestamp lookups may
+///   take
+///
+/// # Errors
+///
+/// Returns [`Error::Kafka`](crate::error::Error::Kafka) when the
+/// lookups or the assignment fail and
+/// [`Error::Config`](crate::error::Error::Config) when a topic does
+/// not exist, an offset names a topic that is not in ``topics`` or a
+/// partition the topic does not have, or an offset without a topic is
+/// given for more than one topic
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use rust_with_kafka_tls::replay::assign_from;
+/// use rust_with_kafka_tls::replay::StartPosition;
+/// use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+///
+/// let consumer = KafkaTlsConfig::from_env("localhost:9093")
+///     .create_consumer("testing-replay")
+///     .expect("Consumer creation failed");
+/// let position = StartPosition::from_rfc3339("2026-10-17T08:30:00Z").unwrap();
+/// assign_from(&consumer, &["testing"], &position, Duration::from_secs(10))
+///     .expect("assign");
+/// ```
+///
+pub fn assign_from(
+    consumer: &LoggingConsumer,
+    topics: &[&str],
+    position: &StartPosition,
+    timeout: Duration,
+) -> Result<()> {
+    let start = match position {
+        StartPosition::Committed => {
+            return consumer.subscribe(topics).map_err(Error::Kafka);
+        }
+        StartPosition::Beginning => Offset::Beginning,
+        StartPosition::End => Offset::End,
+        // each listed partition has its own offset
+        StartPosition::Offsets(_) => Offset::Invalid,
+        StartPosition::Timestamp(at) => Offset::Offset(at.timestamp_millis()),
+    };
+    let listed = match position {
+        StartPosition::Offsets(offsets) => topic_offsets(offsets, topics)?,
+        _ => Vec::new(),
+    };
+    let mut tpl = TopicPartitionList::new();
+    for topic in topics {
+        let partitions = partitions(consumer, topic, timeout)?;
+        let offsets = match position {
+            StartPosition::Offsets(_) => {
+                let offsets: Vec<_> = listed
+                    .iter()
+                    .filter(|(t, _, _)| t == topic)
+                    .map(|(_, partition, offset)| (*partition, *offset))
+                    .collect();
+                if let Some((partition, _)) =
+                    offsets.iter().find(|(p, _)| !partitions.contains(p))
+                {
+                    return Err(Error::Config(format!(
+                        "topic {topic} has no partition {partition}"
+                    )));
+                }
+                offsets
+                    .into_iter()
+                    .map(|(partition, offset)| {
+                        (partition, Offset::Offset(offset))
+                    })
+                    .collect()
+            }
+            _ => partitions.iter().map(|p| (*p, start)).collect::<Vec<_>>(),
+        };
+        for (partition, offset) in offsets {
+            tpl.add_partition_offset(topic, partition, offset)
+                .map_err(Error::Kafka)?;
+        }
+    }
+    if let StartPosition::Timestamp(_) = position {
+        // partitions without a message since then start at the end
+        tpl = consumer
+            .offsets_for_times(tpl, timeout)
+            .map_err(Error::Kafka)?;
+        for elem in tpl.elements() {
+            elem.error().map_err(Error::Kafka)?;
+        }
+    }
+    for elem in tpl.elements() {
+        info!(
+            topic = elem.topic(),
+            partition = elem.partition();
+            "starting at {:?}",
+            elem.offset()
+        );
+    }
+    consumer.assign(&tpl).map_err(Error::Kafka)
+}
+
+/// offsets with the topic they apply to, an offset without a topic
+/// applies to the only one in ``topics``
+fn topic_offsets<'a>(
+    offsets: &'a [(Option<String>, i32, i64)],
+    topics: &[&'a str],
+) -> Result<Vec<(&'a str, i32, i64)>> {
+    let mut resolved: Vec<(&str, i32, i64)> = Vec::new();
+    for (topic, partition, offset) in offsets {
+        let topic = match (topic, topics) {
+            (Some(topic), _) if topics.contains(&topic.as_str()) => {
+                topic.as_str()
+            }
+            (Some(topic), _) => {
+                return Err(Error::Config(format!(
+                    "offset for topic {topic} that is not consumed"
+                )));
+            }
+            (None, [topic]) => *topic,
+            (None, _) => {
+                return Err(Error::Config(format!(
+                    "offset {partition}:{offset} needs a topic when more \
+                    than one is consumed, use TOPIC:PARTITION:OFFSET"
+                )));
+            }
+        };
+        if resolved
+            .iter()
+            .any(|(t, p, _)| *t == topic && p == partition)
+        {
+            return Err(Error::Config(format!(
+                "topic {topic} partition {partition} has more than one offset"
+            )));
+        }
+        resolved.push((topic, *partition, *offset));
+    }
+    Ok(resolved)
+}
+
+/// partition ids of ``topic`` from the cluster metadata
+fn partitions(
+    consumer: &LoggingConsumer,
+    topic: &str,
+    timeout: Duration,
+) -> Result<Vec<i32>> {
+    let metadata = consumer
+        .fetch_metadata(Some(topic), timeout)
+        .map_err(Error::Kafka)?;
+    let partitions: Vec<i32> = metadata
+        .topics()
+        .iter()
+        .filter(|t| t.name() == topic && t.error().is_none())
+        .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+        .collect();
+    if partitions.is_empty() {
+        return Err(Error::Config(format!(
+            "topic {topic} does not exist or has no partitions"
+        )));
+    }
+    Ok(partitions)
+}
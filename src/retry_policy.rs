@@ -0,0 +1,135 @@
+use std::time::Duration;
+use std::time::Instant;
+
+use backoff::backoff::Backoff;
+use backoff::ExponentialBackoff;
+
+/// RetryPolicy
+///
+/// Exponential backoff settings for retrying publishes that failed
+/// with a retriable kafka error (see
+/// [`Error::is_retriable`](crate::error::Error::is_retriable)).
+/// Retries stop once the total ``deadline`` since the first attempt
+/// has passed and the last error is returned. An attempt still waiting
+/// for its delivery report at the deadline fails with
+/// [`Error::Timeout`](crate::error::Error::Timeout).
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use rust_with_kafka_tls::publish_options::PublishOptions;
+/// use rust_with_kafka_tls::retry_policy::RetryPolicy;
+///
+/// let options = PublishOptions::new().retry(
+///     RetryPolicy::new()
+///         .initial_interval(Duration::from_millis(50))
+///         .max_interval(Duration::from_secs(2))
+///         .deadline(Duration::from_secs(30)),
+/// );
+/// ```
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub(crate) initial_interval: Duration,
+    pub(crate) max_interval: Duration,
+    pub(crate) multiplier: f64,
+    pub(crate) randomization_factor: f64,
+    pub(crate) deadline: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial_interval: Duration::from_millis(100),
+            max_interval: Duration::from_secs(5),
+            multiplier: 2.0,
+            randomization_factor: 0.5,
+            deadline: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// new
+    ///
+    /// Start retrying after 100 milliseconds, double the delay up to
+    /// 5 seconds between attempts and give up after 60 seconds
+    ///
+    pub fn new() -> Self {
+        RetryPolicy::default()
+    }
+
+    /// delay before the first retry
+    pub fn initial_interval(mut self, interval: Duration) -> Self {
+        self.initial_interval = interval;
+        self
+    }
+
+    /// upper bound for the delay between retries
+    pub fn max_interval(mut self, interval: Duration) -> Self {
+        self.max_interval = interval;
+        self
+    }
+
+    /// factor the delay grows by after each retry (minimum 1.0)
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// jitter applied to each delay, ``0.5`` picks a delay between
+    /// 50% below and 50% above the current interval
+    pub fn randomization_factor(mut self, factor: f64) -> Self {
+        self.randomization_factor = factor.clamp(0.0, 1.0);
+        self
+    }
+
+    /// total time allowed for all attempts before giving up
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// start a new backoff schedule that ends at the deadline
+    pub(crate) fn start(&self) -> RetrySchedule {
+        let mut backoff = ExponentialBackoff {
+            initial_interval: self.initial_interval,
+            max_interval: self.max_interval,
+            multiplier: self.multiplier,
+            randomization_factor: self.randomization_factor,
+            // the schedule enforces the deadline itself
+            max_elapsed_time: None,
+            ..ExponentialBackoff::default()
+        };
+        backoff.reset();
+        RetrySchedule {
+            backoff,
+            deadline: Instant::now() + self.deadline,
+        }
+    }
+}
+
+/// backoff state for one operation being retried
+pub(crate) struct RetrySchedule {
+    backoff: ExponentialBackoff,
+    deadline: Instant,
+}
+
+impl RetrySchedule {
+    /// time left for attempts before the deadline
+    pub(crate) fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// how long to wait before the next attempt, ``None`` once the
+    /// deadline would pass before the next attempt starts
+    pub(crate) fn next_delay(&mut self) -> Option<Duration> {
+        let delay = self.backoff.next_backoff()?;
+        let remaining = self.deadline.checked_duration_since(Instant::now())?;
+        if delay >= remaining {
+            return None;
+        }
+        Some(delay)
+    }
+}
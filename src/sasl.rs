@@ -0,0 +1,294 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use rdkafka::client::OAuthToken;
+
+use crate::error::Error;
+use crate::error::Result;
+
+/// how long a token read by a
+/// [`TokenFileProvider`](crate::sasl::TokenFileProvider) is used
+/// before it is read again when no lifetime is set
+pub const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// error type librdkafka accepts from token providers
+pub type TokenError = Box<dyn std::error::Error>;
+
+/// OAuthTokenProvider
+///
+/// Supplies ``OAUTHBEARER`` tokens to librdkafka. It is called through
+/// [`CustomContext`](crate::custom_context::CustomContext) when a client
+/// starts and again before the previous token's lifetime ends.
+/// Closures with the same signature are providers too.
+///
+pub trait OAuthTokenProvider: Send + Sync {
+    /// token
+    ///
+    /// Fetch a new token, ``oauthbearer_config`` is the
+    /// ``sasl.oauthbearer.config`` property if it is set
+    ///
+    fn token(
+        &self,
+        oauthbearer_config: Option<&str>,
+    ) -> std::result::Result<OAuthToken, TokenError>;
+}
+
+impl<F> OAuthTokenProvider for F
+where
+    F: Fn(Option<&str>) -> std::result::Result<OAuthToken, TokenError>
+        + Send
+        + Sync,
+{
+    fn token(
+        &self,
+        oauthbearer_config: Option<&str>,
+    ) -> std::result::Result<OAuthToken, TokenError> {
+        self(oauthbearer_config)
+    }
+}
+
+/// TokenFileProvider
+///
+/// [`OAuthTokenProvider`](crate::sasl::OAuthTokenProvider) that reads
+/// the token from a file on every refresh, for example a kubernetes
+/// projected service account token or a file kept current by a
+/// sidecar
+///
+/// # Examples
+///
+/// ```rust
+/// use std::time::Duration;
+/// use rust_with_kafka_tls::sasl::TokenFileProvider;
+///
+/// let provider = TokenFileProvider::new("/var/run/secrets/kafka/token")
+///     .principal("rust-consumer")
+///     .lifetime(Duration::from_secs(60));
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct TokenFileProvider {
+    path: PathBuf,
+    principal: String,
+    lifetime: Duration,
+}
+
+impl TokenFileProvider {
+    /// new
+    ///
+    /// Read the token from ``path`` for the ``kafka`` principal
+    ///
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        TokenFileProvider {
+            path: path.into(),
+            principal: "kafka".to_string(),
+            lifetime: DEFAULT_TOKEN_LIFETIME,
+        }
+    }
+
+    /// principal name reported with the token
+    pub fn principal(mut self, principal: &str) -> Self {
+        self.principal = principal.to_string();
+        self
+    }
+
+    /// how long each token is used, librdkafka refreshes it after
+    /// 80% of the lifetime
+    /// (default: [`DEFAULT_TOKEN_LIFETIME`](crate::sasl::DEFAULT_TOKEN_LIFETIME))
+    pub fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+}
+
+impl OAuthTokenProvider for TokenFileProvider {
+    fn token(
+        &self,
+        _oauthbearer_config: Option<&str>,
+    ) -> std::result::Result<OAuthToken, TokenError> {
+        let token = std::fs::read_to_string(&self.path).map_err(|e| {
+            format!("token file {} not readable: {e}", self.path.display())
+        })?;
+        let expires = SystemTime::now() + self.lifetime;
+        Ok(OAuthToken {
+            token: token.trim().to_string(),
+            principal_name: self.principal.clone(),
+            lifetime_ms: expires.duration_since(UNIX_EPOCH)?.as_millis() as i64,
+        })
+    }
+}
+
+/// ScramMechanism
+///
+/// Hash used for ``SCRAM`` authentication, strimzi ``scram-sha-512``
+/// listeners use [`ScramMechanism::Sha512`](crate::sasl::ScramMechanism::Sha512)
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScramMechanism {
+    Sha256,
+    Sha512,
+}
+
+/// Authentication
+///
+/// How clients authenticate to the brokers. Every mode encrypts
+/// traffic with tls and verifies the brokers with the CA.
+///
+#[derive(Clone, Default)]
+pub enum Authentication {
+    /// client certificate and key (``security.protocol=SSL``)
+    #[default]
+    Mtls,
+    /// username and password (``security.protocol=SASL_SSL``)
+    Scram {
+        mechanism: ScramMechanism,
+        username: String,
+        password: String,
+    },
+    /// ``OAUTHBEARER`` tokens from a provider
+    /// (``security.protocol=SASL_SSL``)
+    OAuthBearer(Arc<dyn OAuthTokenProvider>),
+}
+
+impl Authentication {
+    /// from_env
+    ///
+    /// Build the authentication for a ``mechanism`` name with the
+    /// credentials from the environment variables:
+    ///
+    /// - ``KAFKA_SASL_USERNAME`` and ``KAFKA_SASL_PASSWORD`` for
+    ///   ``SCRAM-SHA-512`` and ``SCRAM-SHA-256``
+    /// - ``KAFKA_OAUTH_TOKEN_FILE`` for ``OAUTHBEARER``, the token is
+    ///   read from this file on every refresh
+    ///
+    /// ``SSL`` or ``mtls`` select client certificates.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Config`](crate::error::Error::Config) for an unknown
+    /// mechanism or a missing environment variable
+    ///
+    pub fn from_env(mechanism: &str) -> Result<Self> {
+        let var = |name: &str| {
+            std::env::var(name).map_err(|_| {
+                Error::Config(format!("{mechanism} needs {name} to be set"))
+            })
+        };
+        let scram = |mechanism| -> Result<Self> {
+            Ok(Authentication::Scram {
+                mechanism,
+                username: var("KAFKA_SASL_USERNAME")?,
+                password: var("KAFKA_SASL_PASSWORD")?,
+            })
+        };
+        match mechanism.parse()? {
+            Mechanism::Mtls => Ok(Authentication::Mtls),
+            Mechanism::Scram(mechanism) => scram(mechanism),
+            Mechanism::OAuthBearer => {
+                Ok(Authentication::OAuthBearer(Arc::new(
+                    TokenFileProvider::new(var("KAFKA_OAUTH_TOKEN_FILE")?),
+                )))
+            }
+        }
+    }
+
+    /// ``true`` when the brokers authenticate the client with SASL
+    /// instead of a client certificate
+    pub fn is_sasl(&self) -> bool {
+        !matches!(self, Authentication::Mtls)
+    }
+
+    /// ``security.protocol`` and ``sasl.*`` librdkafka properties for
+    /// a listener with or without ``tls``
+    pub(crate) fn settings(&self, tls: bool) -> Vec<(&'static str, String)> {
+        let protocol = match (self.is_sasl(), tls) {
+            (false, true) => "SSL",
+            (false, false) => "PLAINTEXT",
+            (true, true) => "SASL_SSL",
+            (true, false) => "SASL_PLAINTEXT",
+        };
+        let mut settings = vec![("security.protocol", protocol.to_string())];
+        match self {
+            Authentication::Mtls => {}
+            Authentication::Scram {
+                mechanism,
+                username,
+                password,
+            } => settings.extend([
+                ("sasl.mechanism", Mechanism::Scram(*mechanism).to_string()),
+                ("sasl.username", username.clone()),
+                ("sasl.password", password.clone()),
+            ]),
+            Authentication::OAuthBearer(_) => settings
+                .push(("sasl.mechanism", Mechanism::OAuthBearer.to_string())),
+        }
+        settings
+    }
+
+    /// provider for the ``OAUTHBEARER`` token refresh callback
+    pub(crate) fn token_provider(&self) -> Option<Arc<dyn OAuthTokenProvider>> {
+        match self {
+            Authentication::OAuthBearer(provider) => Some(provider.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Authentication {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Authentication::Mtls => f.write_str("Mtls"),
+            Authentication::Scram {
+                mechanism,
+                username,
+                ..
+            } => f
+                .debug_struct("Scram")
+                .field("mechanism", mechanism)
+                .field("username", username)
+                .field("password", &"***")
+                .finish(),
+            Authentication::OAuthBearer(_) => f.write_str("OAuthBearer"),
+        }
+    }
+}
+
+/// mechanism names accepted on the command line and in
+/// ``KAFKA_SASL_MECHANISM``
+enum Mechanism {
+    Mtls,
+    Scram(ScramMechanism),
+    OAuthBearer,
+}
+
+impl FromStr for Mechanism {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "SSL" | "MTLS" | "TLS" => Ok(Mechanism::Mtls),
+            "SCRAM-SHA-256" => Ok(Mechanism::Scram(ScramMechanism::Sha256)),
+            "SCRAM-SHA-512" => Ok(Mechanism::Scram(ScramMechanism::Sha512)),
+            "OAUTHBEARER" | "OAUTH" => Ok(Mechanism::OAuthBearer),
+            _ => Err(Error::Config(format!(
+                "unknown sasl mechanism {name}, expected SSL, SCRAM-SHA-512, \
+                SCRAM-SHA-256 or OAUTHBEARER"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Mechanism::Mtls => "SSL",
+            Mechanism::Scram(ScramMechanism::Sha256) => "SCRAM-SHA-256",
+            Mechanism::Scram(ScramMechanism::Sha512) => "SCRAM-SHA-512",
+            Mechanism::OAuthBearer => "OAUTHBEARER",
+        })
+    }
+}
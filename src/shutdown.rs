@@ -0,0 +1,56 @@
+use log::info;
+use tokio_util::sync::CancellationToken;
+
+/// shutdown_on_signals
+///
+/// Create a
+/// [`CancellationToken`](tokio_util::sync::CancellationToken)
+/// that is cancelled when the process receives ``SIGINT``
+/// (ctrl+c) or ``SIGTERM`` (kubernetes pod termination). Pass it to
+/// [`ConsumeOptions::shutdown`](crate::consume_options::ConsumeOptions::shutdown)
+/// so the consume loop can stop cleanly.
+///
+/// Must be called from inside a tokio runtime.
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::consume_options::ConsumeOptions;
+/// use rust_with_kafka_tls::shutdown::shutdown_on_signals;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let options = ConsumeOptions::new().shutdown(shutdown_on_signals());
+/// }
+/// ```
+///
+pub fn shutdown_on_signals() -> CancellationToken {
+    let token = CancellationToken::new();
+    let signal_token = token.clone();
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        info!("shutdown signal received");
+        signal_token.cancel();
+    });
+    token
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::signal;
+    use tokio::signal::unix::SignalKind;
+
+    let mut terminate = signal(SignalKind::terminate())
+        .expect("failed to install SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to install ctrl+c handler");
+}
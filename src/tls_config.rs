@@ -0,0 +1,990 @@
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::info;
+use log::warn;
+use openssl::pkey::PKey;
+use openssl::pkey::Private;
+use openssl::x509::X509;
+use rdkafka::config::ClientConfig;
+use rdkafka::config::FromClientConfigAndContext;
+use rdkafka::config::RDKafkaLogLevel;
+use rdkafka::consumer::Consumer;
+use rdkafka::producer::Producer;
+use rdkafka::types::RDKafka;
+
+use crate::cert_inspect::parse_certs;
+use crate::cert_inspect::verify_chain;
+use crate::cert_inspect::CertInfo;
+use crate::cert_inspect::TlsInspection;
+use crate::cert_inspect::DEFAULT_EXPIRY_WARNING;
+use crate::commit_policy::CommitPolicy;
+use crate::connection_profile::ConnectionProfile;
+use crate::custom_context::forward_logs;
+use crate::custom_context::CustomContext;
+use crate::custom_context::LoggingConsumer;
+use crate::custom_context::LoggingProducer;
+use crate::custom_context::StatsHandler;
+use crate::error::Error;
+use crate::error::Result;
+use crate::host_overrides::HostOverrides;
+use crate::metrics::Metrics;
+use crate::metrics::DEFAULT_STATS_INTERVAL;
+use crate::sasl::Authentication;
+use crate::sasl::OAuthTokenProvider;
+use crate::sasl::ScramMechanism;
+
+/// default path to the Certificate Authority file when
+/// ``KAFKA_TLS_CLIENT_CA`` is not set
+pub const DEFAULT_TLS_CLIENT_CA: &str = "./kubernetes/tls/ca.pem";
+
+/// default path to the client key file when
+/// ``KAFKA_TLS_CLIENT_KEY`` is not set
+pub const DEFAULT_TLS_CLIENT_KEY: &str = "./kubernetes/tls/client-key.pem";
+
+/// default path to the client certificate file when
+/// ``KAFKA_TLS_CLIENT_CERT`` is not set
+pub const DEFAULT_TLS_CLIENT_CERT: &str = "./kubernetes/tls/client.pem";
+
+/// TlsSource
+///
+/// Where a tls asset is loaded from. Files are passed to librdkafka
+/// by path (``ssl.*.location``), PEM contents are passed in memory
+/// (``ssl.*.pem``) so secrets never have to be written to disk.
+///
+#[derive(Clone, PartialEq, Eq)]
+pub enum TlsSource {
+    /// PEM file on disk
+    File(PathBuf),
+    /// PEM contents held in memory, ``origin`` describes where they
+    /// came from in error messages (example: ``env:KAFKA_TLS_CLIENT_CA_PEM``)
+    Pem { origin: String, pem: Vec<u8> },
+}
+
+impl TlsSource {
+    /// describe the source for errors without exposing its contents
+    fn origin(&self) -> PathBuf {
+        match self {
+            TlsSource::File(path) => path.clone(),
+            TlsSource::Pem { origin, .. } => PathBuf::from(origin),
+        }
+    }
+}
+
+impl fmt::Debug for TlsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsSource::File(path) => f.debug_tuple("File").field(path).finish(),
+            TlsSource::Pem { origin, pem } => f
+                .debug_struct("Pem")
+                .field("origin", origin)
+                .field("pem", &format_args!("<{} bytes>", pem.len()))
+                .finish(),
+        }
+    }
+}
+
+/// parsed client tls assets
+pub(crate) struct TlsIdentity {
+    /// certificates in the CA file
+    pub(crate) ca: Vec<X509>,
+    /// client certificate followed by any intermediates
+    pub(crate) chain: Vec<X509>,
+    pub(crate) key: PKey<Private>,
+}
+
+/// KafkaTlsConfig
+///
+/// Builder for kafka clients that connect with mutual tls
+/// (``security.protocol=SSL``). The CA, client key and client
+/// certificate are loaded from files or from PEM contents in memory
+/// (see [`TlsSource`](crate::tls_config::TlsSource)). It checks
+/// the client tls assets before librdkafka is invoked (see
+/// [`check`](crate::tls_config::KafkaTlsConfig::check)) and can
+/// create both a
+/// [`LoggingProducer`](crate::custom_context::LoggingProducer)
+/// and a [`LoggingConsumer`](crate::custom_context::LoggingConsumer).
+///
+/// Clients can authenticate with SASL over tls
+/// (``security.protocol=SASL_SSL``) instead of a client certificate,
+/// see [`scram`](crate::tls_config::KafkaTlsConfig::scram) and
+/// [`oauth_bearer`](crate::tls_config::KafkaTlsConfig::oauth_bearer).
+/// Only the CA is needed then.
+///
+/// The [`ConnectionProfile`](crate::connection_profile::ConnectionProfile)
+/// picks the listener, the ``plain`` profile connects without tls and
+/// needs no tls assets at all.
+///
+/// [`host_overrides`](crate::tls_config::KafkaTlsConfig::host_overrides)
+/// connects to broker hostnames without dns while tls still verifies
+/// the advertised names.
+///
+/// # Examples
+///
+/// ```rust
+/// use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+/// let config = KafkaTlsConfig::new("localhost:9093")
+///     .ca_location("./kubernetes/tls/ca.pem")
+///     .key_location("./kubernetes/tls/client-key.pem")
+///     .cert_location("./kubernetes/tls/client.pem")
+///     .set("message.timeout.ms", "10000");
+/// assert!(config.validate().is_ok());
+///
+/// // secrets from a vault never touch the filesystem
+/// let ca = std::fs::read("./kubernetes/tls/ca.pem").unwrap();
+/// let config = KafkaTlsConfig::new("localhost:9093").ca_pem(ca);
+/// assert!(config.validate().is_ok());
+///
+/// // SCRAM credentials instead of a client certificate
+/// use rust_with_kafka_tls::sasl::ScramMechanism;
+/// let config = KafkaTlsConfig::new("localhost:9094")
+///     .ca_location("./kubernetes/tls/ca.pem")
+///     .key_location("./missing/client-key.pem")
+///     .scram(ScramMechanism::Sha512, "rust-client", "secret");
+/// assert!(config.validate().is_ok());
+/// ```
+///
+#[derive(Clone)]
+pub struct KafkaTlsConfig {
+    brokers: String,
+    ca: TlsSource,
+    key: TlsSource,
+    cert: TlsSource,
+    key_password: Option<String>,
+    profile: ConnectionProfile,
+    host_overrides: HostOverrides,
+    auth: Authentication,
+    certificate_verification: bool,
+    client_chain_check: bool,
+    expiry_warning: Duration,
+    log_level: Option<RDKafkaLogLevel>,
+    stats_interval: Option<Duration>,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
+    metrics: Option<Arc<Metrics>>,
+    commit_policy: CommitPolicy,
+    overrides: Vec<(String, String)>,
+}
+
+impl KafkaTlsConfig {
+    /// new
+    ///
+    /// Create a config for the comma delimited ``brokers`` list
+    /// using the default tls asset paths in ``./kubernetes/tls``
+    ///
+    pub fn new(brokers: &str) -> Self {
+        KafkaTlsConfig {
+            brokers: brokers.to_string(),
+            ca: TlsSource::File(PathBuf::from(DEFAULT_TLS_CLIENT_CA)),
+            key: TlsSource::File(PathBuf::from(DEFAULT_TLS_CLIENT_KEY)),
+            cert: TlsSource::File(PathBuf::from(DEFAULT_TLS_CLIENT_CERT)),
+            key_password: None,
+            profile: ConnectionProfile::default(),
+            host_overrides: HostOverrides::new(),
+            auth: Authentication::Mtls,
+            certificate_verification: true,
+            client_chain_check: true,
+            expiry_warning: DEFAULT_EXPIRY_WARNING,
+            log_level: None,
+            stats_interval: None,
+            stats_handler: None,
+            metrics: None,
+            commit_policy: CommitPolicy::default(),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// from_env
+    ///
+    /// Create a config for the comma delimited ``brokers`` list
+    /// with the tls assets from the environment variables:
+    ///
+    /// - ``KAFKA_TLS_CLIENT_CA`` - path to the Certificate Authority file
+    /// - ``KAFKA_TLS_CLIENT_KEY`` - path to the client key file
+    /// - ``KAFKA_TLS_CLIENT_CERT`` - path to the client certificate file
+    /// - ``KAFKA_TLS_CLIENT_CA_PEM`` - Certificate Authority PEM contents
+    /// - ``KAFKA_TLS_CLIENT_KEY_PEM`` - client key PEM contents
+    /// - ``KAFKA_TLS_CLIENT_CERT_PEM`` - client certificate PEM contents
+    /// - ``KAFKA_TLS_CLIENT_KEY_PASSWORD`` - password for an encrypted
+    ///   client key
+    ///
+    /// The ``*_PEM`` variables take precedence over the paths.
+    ///
+    pub fn from_env(brokers: &str) -> Self {
+        let mut config = KafkaTlsConfig::new(brokers);
+        if let Some(ca) = source_from_env("KAFKA_TLS_CLIENT_CA") {
+            config.ca = ca;
+        }
+        if let Some(key) = source_from_env("KAFKA_TLS_CLIENT_KEY") {
+            config.key = key;
+        }
+        if let Some(cert) = source_from_env("KAFKA_TLS_CLIENT_CERT") {
+            config.cert = cert;
+        }
+        if let Ok(password) = std::env::var("KAFKA_TLS_CLIENT_KEY_PASSWORD") {
+            config.key_password = Some(password);
+        }
+        config
+    }
+
+    /// path to the Certificate Authority file (``ssl.ca.location``)
+    pub fn ca_location<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.ca = TlsSource::File(path.into());
+        self
+    }
+
+    /// path to the client key file (``ssl.key.location``)
+    pub fn key_location<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.key = TlsSource::File(path.into());
+        self
+    }
+
+    /// path to the client certificate file (``ssl.certificate.location``)
+    pub fn cert_location<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.cert = TlsSource::File(path.into());
+        self
+    }
+
+    /// Certificate Authority PEM contents (``ssl.ca.pem``)
+    pub fn ca_pem<B: Into<Vec<u8>>>(mut self, pem: B) -> Self {
+        self.ca = memory_source(pem);
+        self
+    }
+
+    /// client key PEM contents (``ssl.key.pem``)
+    pub fn key_pem<B: Into<Vec<u8>>>(mut self, pem: B) -> Self {
+        self.key = memory_source(pem);
+        self
+    }
+
+    /// client certificate PEM contents (``ssl.certificate.pem``)
+    pub fn cert_pem<B: Into<Vec<u8>>>(mut self, pem: B) -> Self {
+        self.cert = memory_source(pem);
+        self
+    }
+
+    /// password for an encrypted client key (``ssl.key.password``)
+    pub fn key_password(mut self, password: &str) -> Self {
+        self.key_password = Some(password.to_string());
+        self
+    }
+
+    /// pem_bundle
+    ///
+    /// Load the client certificate, client key and CA from one PEM
+    /// bundle read from ``reader`` (for example stdin). The bundle
+    /// holds the client certificate chain, then the private key, then
+    /// the CA certificates:
+    ///
+    /// ```bash
+    /// cat client.pem client-key.pem ca.pem \
+    ///     | ./target/debug/examples/run-producer --tls-stdin -t testing
+    /// ```
+    ///
+    /// # Arguments
+    ///
+    /// * `origin` - names the bundle in errors (example: ``stdin``)
+    /// * `reader` - source of the PEM bundle
+    ///
+    /// # Errors
+    ///
+    /// [`Error::TlsAsset`](crate::error::Error::TlsAsset) when the
+    /// bundle is not readable or a certificate, key or CA block is
+    /// missing
+    ///
+    pub fn pem_bundle<R: Read>(
+        mut self,
+        origin: &str,
+        mut reader: R,
+    ) -> Result<Self> {
+        let mut bundle = String::new();
+        reader
+            .read_to_string(&mut bundle)
+            .map_err(|e| Error::TlsAsset {
+                name: "bundle",
+                path: PathBuf::from(origin),
+                reason: format!("not readable ({e})"),
+            })?;
+        let mut cert = String::new();
+        let mut key = String::new();
+        let mut ca = String::new();
+        for block in pem_blocks(&bundle) {
+            let target = if block.label.ends_with("PRIVATE KEY") {
+                &mut key
+            } else if key.is_empty() {
+                &mut cert
+            } else {
+                &mut ca
+            };
+            target.push_str(block.text);
+            target.push('\n');
+        }
+        for (name, pem) in [("certificate", &cert), ("key", &key), ("CA", &ca)]
+        {
+            if pem.is_empty() {
+                return Err(Error::TlsAsset {
+                    name,
+                    path: PathBuf::from(origin),
+                    reason: format!("bundle has no {name} PEM block"),
+                });
+            }
+        }
+        let source = |pem: String| TlsSource::Pem {
+            origin: origin.to_string(),
+            pem: pem.into_bytes(),
+        };
+        self.cert = source(cert);
+        self.key = source(key);
+        self.ca = source(ca);
+        Ok(self)
+    }
+
+    /// profile
+    ///
+    /// Connect to the listener of ``profile``, this sets the
+    /// ``security.protocol`` and appends the profile port to brokers
+    /// given without one
+    /// (default: [`ConnectionProfile::TlsExternal`](crate::connection_profile::ConnectionProfile::TlsExternal))
+    ///
+    pub fn profile(mut self, profile: ConnectionProfile) -> Self {
+        self.brokers = profile.with_ports(&self.brokers);
+        self.profile = profile;
+        self
+    }
+
+    /// host_overrides
+    ///
+    /// Connect to the mapped addresses instead of resolving the
+    /// broker hostnames, tls hostname verification still uses the
+    /// advertised names. Only applies to the producers and consumers
+    /// created by this config, not to a
+    /// [`client_config`](crate::tls_config::KafkaTlsConfig::client_config).
+    ///
+    pub fn host_overrides(mut self, overrides: HostOverrides) -> Self {
+        self.host_overrides = overrides;
+        self
+    }
+
+    /// how clients authenticate to the brokers
+    /// (default: [`Authentication::Mtls`](crate::sasl::Authentication::Mtls))
+    pub fn authentication(mut self, auth: Authentication) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// authenticate with ``SCRAM`` credentials over tls
+    /// (``security.protocol=SASL_SSL``)
+    pub fn scram(
+        self,
+        mechanism: ScramMechanism,
+        username: &str,
+        password: &str,
+    ) -> Self {
+        self.authentication(Authentication::Scram {
+            mechanism,
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    /// authenticate with ``OAUTHBEARER`` tokens from ``provider`` over
+    /// tls (``security.protocol=SASL_SSL``), librdkafka asks for a new
+    /// token before the current one expires
+    pub fn oauth_bearer<P: OAuthTokenProvider + 'static>(
+        self,
+        provider: P,
+    ) -> Self {
+        self.authentication(Authentication::OAuthBearer(Arc::new(provider)))
+    }
+
+    /// toggle ``enable.ssl.certificate.verification`` (default: ``true``)
+    pub fn certificate_verification(mut self, enabled: bool) -> Self {
+        self.certificate_verification = enabled;
+        self
+    }
+
+    /// require the client certificate to chain to the CA file
+    /// (default: ``true``), disable it when the brokers trust a
+    /// different clients CA than the CA that signed the brokers
+    pub fn client_chain_check(mut self, enabled: bool) -> Self {
+        self.client_chain_check = enabled;
+        self
+    }
+
+    /// log a warning when a certificate expires within ``window``
+    /// (default: [`DEFAULT_EXPIRY_WARNING`](crate::cert_inspect::DEFAULT_EXPIRY_WARNING))
+    pub fn expiry_warning(mut self, window: Duration) -> Self {
+        self.expiry_warning = window;
+        self
+    }
+
+    /// librdkafka log level for the created clients
+    pub fn log_level(mut self, level: RDKafkaLogLevel) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// call ``handler`` with the librdkafka statistics of every
+    /// created client each ``interval``
+    /// (``statistics.interval.ms``)
+    pub fn stats_handler<H: StatsHandler + 'static>(
+        mut self,
+        interval: Duration,
+        handler: H,
+    ) -> Self {
+        self.stats_interval = Some(interval);
+        self.stats_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// record rebalances, commit failures, received messages and the
+    /// librdkafka statistics of every created client in ``metrics``,
+    /// statistics are emitted every
+    /// [`DEFAULT_STATS_INTERVAL`](crate::metrics::DEFAULT_STATS_INTERVAL)
+    /// unless a [`stats_handler`](crate::tls_config::KafkaTlsConfig::stats_handler)
+    /// sets the interval
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// configure created consumers for this
+    /// [`CommitPolicy`](crate::commit_policy::CommitPolicy), use the same
+    /// policy in the [`ConsumeOptions`](crate::consume_options::ConsumeOptions)
+    pub fn commit_policy(mut self, policy: CommitPolicy) -> Self {
+        self.commit_policy = policy;
+        self
+    }
+
+    /// set an extra librdkafka property, these are applied last
+    /// and replace any value set by this builder
+    pub fn set(mut self, key: &str, value: &str) -> Self {
+        self.overrides.retain(|(k, _)| k != key);
+        self.overrides.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// comma delimited list of kafka brokers
+    pub fn brokers(&self) -> &str {
+        &self.brokers
+    }
+
+    /// broker hostnames resolved without dns
+    pub(crate) fn overrides(&self) -> &HostOverrides {
+        &self.host_overrides
+    }
+
+    /// CA, key and certificate files that librdkafka reads when a
+    /// client is created, in-memory PEM contents are not included
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        let sources = match (self.profile.is_tls(), self.auth.is_sasl()) {
+            (false, _) => vec![],
+            (true, true) => vec![&self.ca],
+            (true, false) => vec![&self.ca, &self.key, &self.cert],
+        };
+        sources
+            .into_iter()
+            .filter_map(|source| match source {
+                TlsSource::File(path) => Some(path.clone()),
+                TlsSource::Pem { .. } => None,
+            })
+            .collect()
+    }
+
+    /// validate
+    ///
+    /// Verify the CA, client key and client certificate exist, are
+    /// readable and contain PEM data before librdkafka is invoked.
+    /// An encrypted client key needs a
+    /// [`key_password`](crate::tls_config::KafkaTlsConfig::key_password).
+    /// With SASL authentication only the CA is verified and the
+    /// ``plain`` profile needs no tls assets.
+    ///
+    pub fn validate(&self) -> Result<()> {
+        match (self.profile.is_tls(), self.auth.is_sasl()) {
+            (false, _) => Ok(()),
+            (true, true) => {
+                validate_pem("CA", &self.ca, "CERTIFICATE").map(|_| ())
+            }
+            (true, false) => self.read_assets().map(|_| ()),
+        }
+    }
+
+    /// inspect
+    ///
+    /// Parse the validated CA, client certificate and client key and
+    /// report the subject, SANs, issuer and validity period of each
+    /// certificate, whether the key belongs to the certificate and
+    /// whether the certificate chains to the CA. Nothing is rejected
+    /// here, see [`check`](crate::tls_config::KafkaTlsConfig::check).
+    ///
+    /// # Errors
+    ///
+    /// [`Error::TlsAsset`](crate::error::Error::TlsAsset) when an asset
+    /// fails [`validate`](crate::tls_config::KafkaTlsConfig::validate)
+    /// or cannot be parsed
+    ///
+    pub fn inspect(&self) -> Result<TlsInspection> {
+        let TlsIdentity { ca, chain, key } = self.identity()?;
+        let key_matches_cert = chain[0]
+            .public_key()
+            .map(|public| public.public_eq(&key))
+            .unwrap_or(false);
+        let chain_error = verify_chain(&ca, &chain).map_err(|e| {
+            asset_error(
+                "certificate",
+                &self.cert,
+                format!("chain not verifiable ({e})"),
+            )
+        })?;
+        let details = |name, source, certs: &[X509]| {
+            certs
+                .iter()
+                .map(|cert| CertInfo::from_x509(cert))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| {
+                    asset_error(name, source, format!("unreadable ({e})"))
+                })
+        };
+        Ok(TlsInspection {
+            ca: details("CA", &self.ca, &ca)?,
+            chain: details("certificate", &self.cert, &chain)?,
+            key_matches_cert,
+            chain_error,
+        })
+    }
+
+    /// check
+    ///
+    /// [`inspect`](crate::tls_config::KafkaTlsConfig::inspect) the tls
+    /// assets, log each certificate and fail fast on material the
+    /// brokers would reject in the tls handshake. A warning is logged
+    /// for certificates that expire within the
+    /// [`expiry_warning`](crate::tls_config::KafkaTlsConfig::expiry_warning)
+    /// window. Called before every client is created.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::TlsAsset`](crate::error::Error::TlsAsset) when a CA or
+    /// client certificate is expired or not yet valid, the key does
+    /// not belong to the client certificate or the client certificate
+    /// is not signed by the CA (unless
+    /// [`client_chain_check`](crate::tls_config::KafkaTlsConfig::client_chain_check)
+    /// is disabled)
+    ///
+    pub fn check(&self) -> Result<TlsInspection> {
+        let report = self.inspect()?;
+        self.check_validity("CA", &self.ca, &report.ca)?;
+        self.check_validity("certificate", &self.cert, &report.chain)?;
+        if !report.key_matches_cert {
+            return Err(asset_error(
+                "key",
+                &self.key,
+                format!(
+                    "key does not match the certificate {}",
+                    report.cert().subject
+                ),
+            ));
+        }
+        if let Some(reason) = &report.chain_error {
+            if self.client_chain_check {
+                return Err(asset_error(
+                    "certificate",
+                    &self.cert,
+                    format!("not signed by the CA ({reason})"),
+                ));
+            }
+        }
+        Ok(report)
+    }
+
+    /// check the tls material the configured
+    /// [`Authentication`](crate::sasl::Authentication) needs before a
+    /// client is created, SASL clients only need a valid CA and
+    /// clients of the ``plain`` profile need nothing
+    pub(crate) fn preflight(&self) -> Result<()> {
+        if !self.profile.is_tls() {
+            return Ok(());
+        }
+        if !self.auth.is_sasl() {
+            return self.check().map(|_| ());
+        }
+        let pem = validate_pem("CA", &self.ca, "CERTIFICATE")?;
+        let ca = parse_certs(pem.as_bytes())
+            .and_then(|certs| {
+                certs
+                    .iter()
+                    .map(|cert| CertInfo::from_x509(cert))
+                    .collect::<std::result::Result<Vec<_>, _>>()
+            })
+            .map_err(|e| {
+                asset_error(
+                    "CA",
+                    &self.ca,
+                    format!("not a valid certificate ({e})"),
+                )
+            })?;
+        self.check_validity("CA", &self.ca, &ca)
+    }
+
+    /// client_config
+    ///
+    /// [`check`](crate::tls_config::KafkaTlsConfig::check) the tls
+    /// assets (only the CA with SASL authentication) and build the
+    /// [`rdkafka::config::ClientConfig`](rdkafka::config::ClientConfig)
+    /// with the brokers, tls settings and overrides
+    ///
+    pub fn client_config(&self) -> Result<ClientConfig> {
+        self.build_config(&[])
+    }
+
+    /// create_producer
+    ///
+    /// Create a [`LoggingProducer`](crate::custom_context::LoggingProducer)
+    /// from the validated config
+    ///
+    pub fn create_producer(&self) -> Result<LoggingProducer> {
+        self.create_client(
+            &[("message.timeout.ms", "5000")],
+            |producer: &LoggingProducer| producer.client().native_ptr(),
+        )
+    }
+
+    /// create_consumer
+    ///
+    /// Create a [`LoggingConsumer`](crate::custom_context::LoggingConsumer)
+    /// in the consumer group ``group_id`` from the validated config
+    ///
+    pub fn create_consumer(&self, group_id: &str) -> Result<LoggingConsumer> {
+        let mut defaults = vec![
+            ("group.id", group_id),
+            ("enable.partition.eof", "false"),
+            ("session.timeout.ms", "6000"),
+        ];
+        defaults.extend(self.commit_policy.consumer_settings());
+        self.create_client(&defaults, |consumer: &LoggingConsumer| {
+            consumer.client().native_ptr()
+        })
+    }
+
+    /// identity
+    ///
+    /// Parse the validated CA certificates, client certificate chain
+    /// and client key
+    ///
+    pub(crate) fn identity(&self) -> Result<TlsIdentity> {
+        let (ca_pem, key_pem, cert_pem) = self.read_assets()?;
+        let ca = parse_certs(ca_pem.as_bytes()).map_err(|e| {
+            asset_error(
+                "CA",
+                &self.ca,
+                format!("not a valid certificate ({e})"),
+            )
+        })?;
+        let chain = parse_certs(cert_pem.as_bytes()).map_err(|e| {
+            asset_error(
+                "certificate",
+                &self.cert,
+                format!("not a valid certificate ({e})"),
+            )
+        })?;
+        if chain.is_empty() {
+            return Err(asset_error(
+                "certificate",
+                &self.cert,
+                "no certificate found".to_string(),
+            ));
+        }
+        let key = match &self.key_password {
+            Some(password) => PKey::private_key_from_pem_passphrase(
+                key_pem.as_bytes(),
+                password.as_bytes(),
+            ),
+            None => PKey::private_key_from_pem(key_pem.as_bytes()),
+        }
+        .map_err(|e| {
+            asset_error("key", &self.key, format!("not a readable key ({e})"))
+        })?;
+        Ok(TlsIdentity { ca, chain, key })
+    }
+
+    /// create_client
+    ///
+    /// Create a client from the validated config with ``defaults``.
+    /// Once ``native`` returns the librdkafka handle its log lines are
+    /// routed through the client context. With host overrides the
+    /// client starts without ``bootstrap.servers``, they are added
+    /// after the resolve callback is installed.
+    ///
+    pub(crate) fn create_client<T, F>(
+        &self,
+        defaults: &[(&str, &str)],
+        native: F,
+    ) -> Result<T>
+    where
+        T: FromClientConfigAndContext<CustomContext>,
+        F: FnOnce(&T) -> *mut RDKafka,
+    {
+        let mut config = self.build_config(defaults)?;
+        config.set("log.queue", "true");
+        let context = self.context();
+        let brokers = config
+            .get("bootstrap.servers")
+            .unwrap_or_default()
+            .to_string();
+        if context.overrides_hosts() {
+            config.remove("bootstrap.servers");
+        }
+        let client = config.create_with_context(context.clone())?;
+        let rk = native(&client);
+        forward_logs(rk)?;
+        context.resolve_brokers(rk, &brokers)?;
+        Ok(client)
+    }
+
+    /// context that refreshes ``OAUTHBEARER`` tokens when the
+    /// authentication has a token provider, resolves the overridden
+    /// broker hostnames, forwards the statistics to the handler and
+    /// records the metrics
+    fn context(&self) -> CustomContext {
+        let mut context = CustomContext::new();
+        if let Some(handler) = &self.stats_handler {
+            context = context.stats_handler(handler.clone());
+        }
+        if let Some(metrics) = &self.metrics {
+            context = context.metrics(metrics.clone());
+        }
+        if let Some(provider) = self.auth.token_provider() {
+            context = context.token_provider(provider);
+        }
+        if !self.host_overrides.is_empty() {
+            context = context.host_overrides(self.host_overrides.clone());
+        }
+        context
+    }
+
+    /// log ``certs`` and fail on any that is expired or not yet valid,
+    /// warn about those expiring within the warning window
+    fn check_validity(
+        &self,
+        name: &'static str,
+        source: &TlsSource,
+        certs: &[CertInfo],
+    ) -> Result<()> {
+        let now = Utc::now();
+        for cert in certs {
+            info!("tls {name} {cert}");
+            if let Some(reason) = cert.validity_error(now) {
+                return Err(asset_error(name, source, reason));
+            }
+            if cert.expires_within(self.expiry_warning) {
+                warn!(
+                    "tls {name} {} expires on {}",
+                    cert.subject, cert.not_after
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// CA, key and certificate PEM contents that passed validation
+    fn read_assets(&self) -> Result<(String, String, String)> {
+        let ca = validate_pem("CA", &self.ca, "CERTIFICATE")?;
+        let key = validate_pem("key", &self.key, "PRIVATE KEY")?;
+        let cert = validate_pem("certificate", &self.cert, "CERTIFICATE")?;
+        let encrypted = key.contains("ENCRYPTED");
+        if encrypted && self.key_password.is_none() {
+            return Err(asset_error(
+                "key",
+                &self.key,
+                "key is encrypted and no key password is set".to_string(),
+            ));
+        }
+        Ok((ca, key, cert))
+    }
+
+    fn build_config(&self, defaults: &[(&str, &str)]) -> Result<ClientConfig> {
+        self.preflight()?;
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", &self.brokers);
+        for (key, value) in self.auth.settings(self.profile.is_tls()) {
+            config.set(key, value);
+        }
+        if self.profile.is_tls() {
+            config.set(
+                "enable.ssl.certificate.verification",
+                self.certificate_verification.to_string(),
+            );
+            set_source(&mut config, "ssl.ca", &self.ca);
+        }
+        if self.profile.is_tls() && !self.auth.is_sasl() {
+            set_source(&mut config, "ssl.key", &self.key);
+            set_source(&mut config, "ssl.certificate", &self.cert);
+            if let Some(password) = &self.key_password {
+                config.set("ssl.key.password", password);
+            }
+        }
+        let stats_interval = self
+            .stats_interval
+            .or_else(|| self.metrics.as_ref().map(|_| DEFAULT_STATS_INTERVAL));
+        if let Some(interval) = stats_interval {
+            config.set(
+                "statistics.interval.ms",
+                interval.as_millis().to_string(),
+            );
+        }
+        for (key, value) in defaults {
+            config.set(*key, *value);
+        }
+        for (key, value) in &self.overrides {
+            config.set(key, value);
+        }
+        if let Some(level) = self.log_level {
+            config.set_log_level(level);
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Debug for KafkaTlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaTlsConfig")
+            .field("brokers", &self.brokers)
+            .field("profile", &self.profile)
+            .field("host_overrides", &self.host_overrides)
+            .field("ca", &self.ca)
+            .field("key", &self.key)
+            .field("cert", &self.cert)
+            .field("key_password", &self.key_password.as_ref().map(|_| "***"))
+            .field("auth", &self.auth)
+            .field("certificate_verification", &self.certificate_verification)
+            .field("client_chain_check", &self.client_chain_check)
+            .field("expiry_warning", &self.expiry_warning)
+            .field("log_level", &self.log_level)
+            .field("stats_interval", &self.stats_interval)
+            .field("metrics", &self.metrics.is_some())
+            .field("commit_policy", &self.commit_policy)
+            .field("overrides", &self.overrides)
+            .finish()
+    }
+}
+
+/// ``{var}_PEM`` contents or the ``{var}`` path, if either is set
+fn source_from_env(var: &str) -> Option<TlsSource> {
+    let pem_var = format!("{var}_PEM");
+    if let Ok(pem) = std::env::var(&pem_var) {
+        return Some(TlsSource::Pem {
+            origin: format!("env:{pem_var}"),
+            pem: pem.into_bytes(),
+        });
+    }
+    std::env::var(var)
+        .ok()
+        .map(|path| TlsSource::File(path.into()))
+}
+
+fn asset_error(
+    name: &'static str,
+    source: &TlsSource,
+    reason: String,
+) -> Error {
+    Error::TlsAsset {
+        name,
+        path: source.origin(),
+        reason,
+    }
+}
+
+fn memory_source<B: Into<Vec<u8>>>(pem: B) -> TlsSource {
+    TlsSource::Pem {
+        origin: "memory".to_string(),
+        pem: pem.into(),
+    }
+}
+
+/// set ``{prefix}.location`` for files or ``{prefix}.pem`` for
+/// in-memory PEM contents
+fn set_source(config: &mut ClientConfig, prefix: &str, source: &TlsSource) {
+    match source {
+        TlsSource::File(path) => {
+            config.set(format!("{prefix}.location"), path.to_string_lossy())
+        }
+        // validate() already checked the contents are utf-8
+        TlsSource::Pem { pem, .. } => config.set(
+            format!("{prefix}.pem"),
+            String::from_utf8_lossy(pem).into_owned(),
+        ),
+    };
+}
+
+/// one ``-----BEGIN {label}-----`` to ``-----END {label}-----`` block
+struct PemBlock<'a> {
+    label: &'a str,
+    text: &'a str,
+}
+
+/// split ``contents`` into its PEM blocks, text outside of blocks is
+/// ignored
+fn pem_blocks(contents: &str) -> Vec<PemBlock<'_>> {
+    let mut blocks = Vec::new();
+    let mut rest = contents;
+    while let Some(start) = rest.find("-----BEGIN ") {
+        let after = &rest[start + "-----BEGIN ".len()..];
+        let label = match after.find("-----") {
+            Some(end) => &after[..end],
+            None => break,
+        };
+        let end_marker = format!("-----END {label}-----");
+        let end = match rest[start..].find(&end_marker) {
+            Some(end) => start + end + end_marker.len(),
+            None => break,
+        };
+        blocks.push(PemBlock {
+            label,
+            text: &rest[start..end],
+        });
+        rest = &rest[end..];
+    }
+    blocks
+}
+
+/// validate_pem
+///
+/// Check the tls asset from ``source`` is readable and contains a PEM
+/// block whose label ends with ``label``
+/// (example: ``CERTIFICATE`` or ``PRIVATE KEY``) and return the PEM
+/// contents
+///
+fn validate_pem(
+    name: &'static str,
+    source: &TlsSource,
+    label: &str,
+) -> Result<String> {
+    let tls_error = |reason: String| Error::TlsAsset {
+        name,
+        path: source.origin(),
+        reason,
+    };
+    let contents = match source {
+        TlsSource::File(path) => {
+            if !path.exists() {
+                return Err(tls_error("file not found".to_string()));
+            }
+            fs::read_to_string(path)
+                .map_err(|e| tls_error(format!("file not readable ({e})")))?
+        }
+        TlsSource::Pem { pem, .. } => String::from_utf8(pem.clone())
+            .map_err(|_| tls_error("PEM contents are not utf-8".to_string()))?,
+    };
+    let has_block = pem_blocks(&contents)
+        .iter()
+        .any(|block| block.label.ends_with(label));
+    if !has_block {
+        return Err(tls_error(format!("missing PEM {label} block")));
+    }
+    Ok(contents)
+}
@@ -0,0 +1,448 @@
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use std::time::Instant;
+
+use openssl::error::ErrorStack;
+use openssl::ssl::SslConnector;
+use openssl::ssl::SslMethod;
+use openssl::ssl::SslStream;
+use openssl::ssl::SslVerifyMode;
+use openssl::x509::X509;
+use rdkafka::producer::Producer;
+
+use crate::cert_inspect::verify_chain;
+use crate::cert_inspect::CertInfo;
+use crate::custom_context::LoggingProducer;
+use crate::error::Error;
+use crate::error::Result;
+use crate::host_overrides::HostOverrides;
+use crate::tls_config::KafkaTlsConfig;
+
+/// how long each network step may take when no timeout is set
+pub const DEFAULT_DOCTOR_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// diagnostic steps in the order they run
+pub const STEPS: [&str; 6] = [
+    "dns",
+    "tcp",
+    "tls handshake",
+    "certificate chain",
+    "hostname",
+    "kafka metadata",
+];
+
+/// Outcome
+///
+/// Result of one diagnostic step, ``Pass`` and ``Fail`` carry the
+/// details to print
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass(String),
+    Fail(String),
+    /// not run because an earlier step failed
+    Skip,
+}
+
+/// Check
+///
+/// One named diagnostic step for a broker
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    /// one of the [`STEPS`](crate::tls_doctor::STEPS)
+    pub step: &'static str,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Outcome::Pass(detail) => {
+                write!(f, "[PASS] {}: {detail}", self.step)
+            }
+            Outcome::Fail(detail) => {
+                write!(f, "[FAIL] {}: {detail}", self.step)
+            }
+            Outcome::Skip => write!(f, "[SKIP] {}", self.step),
+        }
+    }
+}
+
+/// BrokerReport
+///
+/// Every diagnostic step run against one broker, in order
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BrokerReport {
+    /// ``host:port`` from the brokers list
+    pub broker: String,
+    pub checks: Vec<Check>,
+}
+
+impl BrokerReport {
+    /// ``true`` when every step passed
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| matches!(check.outcome, Outcome::Pass(_)))
+    }
+
+    /// the next step passed
+    fn pass(&mut self, detail: String) {
+        let step = STEPS[self.checks.len()];
+        self.checks.push(Check {
+            step,
+            outcome: Outcome::Pass(detail),
+        });
+    }
+
+    /// the next step failed, skip the ones after it
+    fn fail(mut self, detail: String) -> Self {
+        let step = STEPS[self.checks.len()];
+        self.checks.push(Check {
+            step,
+            outcome: Outcome::Fail(detail),
+        });
+        for step in &STEPS[self.checks.len()..] {
+            self.checks.push(Check {
+                step,
+                outcome: Outcome::Skip,
+            });
+        }
+        self
+    }
+
+    /// outcome of ``step``, if it is part of the report
+    pub fn outcome(&self, step: &str) -> Option<&Outcome> {
+        self.checks
+            .iter()
+            .find(|check| check.step == step)
+            .map(|check| &check.outcome)
+    }
+}
+
+impl fmt::Display for BrokerReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.passed() { "PASS" } else { "FAIL" };
+        writeln!(f, "broker {} {verdict}", self.broker)?;
+        for check in &self.checks {
+            writeln!(f, "  {check}")?;
+        }
+        Ok(())
+    }
+}
+
+/// TlsDoctor
+///
+/// Diagnose mutual tls connections to every broker of a
+/// [`KafkaTlsConfig`](crate::tls_config::KafkaTlsConfig). For each
+/// broker it resolves the hostname, opens a tcp connection, performs
+/// the tls handshake with the client certificate, verifies the broker
+/// certificate chains to the CA and matches the hostname, then sends
+/// a kafka metadata request. A failed step skips the steps after it.
+/// This replaces checking each broker with ``openssl s_client``.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+/// use rust_with_kafka_tls::tls_doctor::TlsDoctor;
+///
+/// let tls = KafkaTlsConfig::from_env("cluster-0-broker-0.redten.io:32151");
+/// for report in TlsDoctor::new(&tls).run() {
+///     print!("{report}");
+/// }
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct TlsDoctor {
+    tls: KafkaTlsConfig,
+    timeout: Duration,
+}
+
+impl TlsDoctor {
+    /// new
+    ///
+    /// Diagnose the brokers and client tls assets of ``tls``
+    ///
+    pub fn new(tls: &KafkaTlsConfig) -> Self {
+        TlsDoctor {
+            tls: tls.clone(),
+            timeout: DEFAULT_DOCTOR_TIMEOUT,
+        }
+    }
+
+    /// how long each network step may take
+    /// (default: [`DEFAULT_DOCTOR_TIMEOUT`](crate::tls_doctor::DEFAULT_DOCTOR_TIMEOUT))
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// diagnose every broker in the comma delimited brokers list
+    pub fn run(&self) -> Vec<BrokerReport> {
+        self.tls
+            .brokers()
+            .split(',')
+            .map(str::trim)
+            .filter(|broker| !broker.is_empty())
+            .map(|broker| self.diagnose(broker))
+            .collect()
+    }
+
+    /// diagnose
+    ///
+    /// Run every step against one ``host:port`` broker
+    ///
+    pub fn diagnose(&self, broker: &str) -> BrokerReport {
+        let mut report = BrokerReport {
+            broker: broker.to_string(),
+            checks: Vec::new(),
+        };
+        let (host, addrs) = match resolve(broker, self.tls.overrides()) {
+            Ok(resolved) => resolved,
+            Err(e) => return report.fail(e),
+        };
+        let ips: Vec<String> =
+            addrs.iter().map(|addr| addr.ip().to_string()).collect();
+        match self.tls.overrides().get(&host) {
+            Some(_) => report.pass(format!("{} (host override)", ips[0])),
+            None => report.pass(ips.join(", ")),
+        }
+        let stream = match self.connect(&addrs[0]) {
+            Ok((stream, elapsed)) => {
+                report.pass(format!(
+                    "connected to {} in {}ms",
+                    addrs[0],
+                    elapsed.as_millis()
+                ));
+                stream
+            }
+            Err(e) => return report.fail(e),
+        };
+        let peer_chain = match self.handshake(&host, stream) {
+            Ok((version, peer_chain)) => {
+                report.pass(version);
+                peer_chain
+            }
+            Err(e) => return report.fail(e),
+        };
+        for checked in [
+            self.verify_peer(&peer_chain),
+            verify_hostname(&host, &peer_chain),
+            self.fetch_metadata(broker),
+        ] {
+            match checked {
+                Ok(detail) => report.pass(detail),
+                Err(e) => return report.fail(e),
+            }
+        }
+        report
+    }
+
+    /// tcp connection to ``addr`` and how long it took
+    fn connect(
+        &self,
+        addr: &SocketAddr,
+    ) -> std::result::Result<(TcpStream, Duration), String> {
+        let started = Instant::now();
+        let stream = TcpStream::connect_timeout(addr, self.timeout)
+            .map_err(|e| format!("{addr}: {e}"))?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|_| stream.set_write_timeout(Some(self.timeout)))
+            .map_err(|e| format!("{addr}: {e}"))?;
+        Ok((stream, started.elapsed()))
+    }
+
+    /// handshake
+    ///
+    /// Handshake presenting the client certificate and return the
+    /// negotiated protocol and the broker certificate chain. Broker
+    /// certificate checks are left to the next steps so each problem
+    /// is reported on its own.
+    ///
+    fn handshake(
+        &self,
+        host: &str,
+        stream: TcpStream,
+    ) -> std::result::Result<(String, Vec<X509>), String> {
+        let connector = self.connector().map_err(|e| e.to_string())?;
+        let mut configuration = connector
+            .configure()
+            .map_err(|e| e.to_string())?
+            .verify_hostname(false);
+        configuration.set_verify(SslVerifyMode::NONE);
+        let mut stream = configuration
+            .connect(host, stream)
+            .map_err(|e| e.to_string())?;
+        rejected_after_handshake(&mut stream)?;
+        let ssl = stream.ssl();
+        let chain = ssl
+            .peer_cert_chain()
+            .map(|chain| chain.iter().map(|cert| cert.to_owned()).collect())
+            .unwrap_or_default();
+        Ok((ssl.version_str().to_string(), chain))
+    }
+
+    /// connector presenting the client certificate and key
+    fn connector(&self) -> Result<SslConnector> {
+        let identity = self.tls.identity()?;
+        let tls_error = |e: ErrorStack| Error::Config(e.to_string());
+        let mut builder = SslConnector::builder(SslMethod::tls_client())
+            .map_err(tls_error)?;
+        builder
+            .set_certificate(&identity.chain[0])
+            .map_err(tls_error)?;
+        for cert in &identity.chain[1..] {
+            builder
+                .add_extra_chain_cert(cert.clone())
+                .map_err(tls_error)?;
+        }
+        builder.set_private_key(&identity.key).map_err(tls_error)?;
+        Ok(builder.build())
+    }
+
+    /// check the broker certificate chains to the CA file
+    fn verify_peer(
+        &self,
+        peer_chain: &[X509],
+    ) -> std::result::Result<String, String> {
+        let ca = self.tls.identity().map_err(|e| e.to_string())?.ca;
+        let leaf = peer_chain
+            .first()
+            .ok_or_else(|| "broker sent no certificate".to_string())?;
+        let leaf = CertInfo::from_x509(leaf).map_err(|e| e.to_string())?;
+        match verify_chain(&ca, peer_chain).map_err(|e| e.to_string())? {
+            None => Ok(format!(
+                "{} issued by {} valid until {}",
+                leaf.subject, leaf.issuer, leaf.not_after
+            )),
+            Some(reason) => Err(format!("{}: {reason}", leaf.subject)),
+        }
+    }
+
+    /// metadata request to ``broker`` alone with the librdkafka client
+    fn fetch_metadata(
+        &self,
+        broker: &str,
+    ) -> std::result::Result<String, String> {
+        let producer: LoggingProducer = self
+            .tls
+            .clone()
+            .set("bootstrap.servers", broker)
+            .create_client(&[], |producer: &LoggingProducer| {
+                producer.client().native_ptr()
+            })
+            .map_err(|e| e.to_string())?;
+        let metadata = producer
+            .client()
+            .fetch_metadata(None, self.timeout)
+            .map_err(|e| e.to_string())?;
+        Ok(format!(
+            "{} brokers, {} topics",
+            metadata.brokers().len(),
+            metadata.topics().len()
+        ))
+    }
+}
+
+/// split ``host:port`` and resolve the host unless it is overridden
+fn resolve(
+    broker: &str,
+    overrides: &HostOverrides,
+) -> std::result::Result<(String, Vec<SocketAddr>), String> {
+    let (host, port) = broker
+        .rsplit_once(':')
+        .ok_or_else(|| format!("{broker} is not host:port"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port: u16 = port
+        .parse()
+        .map_err(|_| format!("{broker} has an invalid port"))?;
+    if let Some(ip) = overrides.get(host) {
+        return Ok((host.to_string(), vec![SocketAddr::new(ip, port)]));
+    }
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("{host}: {e}"))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{host} has no addresses"));
+    }
+    Ok((host.to_string(), addrs))
+}
+
+/// tls 1.3 brokers reject a client certificate after the handshake,
+/// a short read surfaces that alert instead of a later hangup
+fn rejected_after_handshake(
+    stream: &mut SslStream<TcpStream>,
+) -> std::result::Result<(), String> {
+    let timeout = Duration::from_millis(500);
+    stream
+        .get_ref()
+        .set_read_timeout(Some(timeout))
+        .map_err(|e| e.to_string())?;
+    let read = stream.ssl_read(&mut [0u8; 1]);
+    let rejected = match read {
+        Ok(_) => None,
+        Err(e) => match e.io_error().map(|io| io.kind()) {
+            Some(ErrorKind::WouldBlock | ErrorKind::TimedOut) => None,
+            _ => Some(e.to_string()),
+        },
+    };
+    match rejected {
+        Some(reason) => Err(format!("client certificate rejected: {reason}")),
+        None => Ok(()),
+    }
+}
+
+/// verify_hostname
+///
+/// Match ``host`` against the SANs of the broker certificate, or its
+/// common name when it has no SANs. ``*.`` wildcards match one label.
+///
+fn verify_hostname(
+    host: &str,
+    peer_chain: &[X509],
+) -> std::result::Result<String, String> {
+    let leaf = peer_chain
+        .first()
+        .ok_or_else(|| "broker sent no certificate".to_string())?;
+    let leaf = CertInfo::from_x509(leaf).map_err(|e| e.to_string())?;
+    let names: Vec<String> = if leaf.sans.is_empty() {
+        leaf.subject
+            .split(", ")
+            .filter_map(|field| field.strip_prefix("CN="))
+            .map(|cn| format!("DNS:{cn}"))
+            .collect()
+    } else {
+        leaf.sans.clone()
+    };
+    let matched = names.iter().find(|name| match host.parse::<IpAddr>() {
+        Ok(ip) => name.strip_prefix("IP:") == Some(&ip.to_string()),
+        Err(_) => name
+            .strip_prefix("DNS:")
+            .is_some_and(|pattern| dns_matches(pattern, host)),
+    });
+    match matched {
+        Some(name) => Ok(format!("{host} matches {name}")),
+        None => Err(format!("{host} not in [{}]", names.join(", "))),
+    }
+}
+
+fn dns_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let host = host.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == domain),
+        None => pattern == host,
+    }
+}
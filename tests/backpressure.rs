@@ -0,0 +1,148 @@
+mod common;
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use rdkafka::consumer::Consumer;
+use rdkafka::message::OwnedMessage;
+use rdkafka::topic_partition_list::Offset;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::backpressure::InFlightLimits;
+use rust_with_kafka_tls::connection_profile::ConnectionProfile;
+use rust_with_kafka_tls::consume_messages::consume_messages;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::error::Result;
+use rust_with_kafka_tls::message_handler::MessageHandler;
+use rust_with_kafka_tls::metrics::Metrics;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+
+use common::TestCluster;
+
+const MESSAGES: usize = 40;
+
+/// counts handled messages once ``release`` is cancelled
+struct Gate {
+    handled: AtomicUsize,
+    release: CancellationToken,
+}
+
+#[async_trait]
+impl MessageHandler for Gate {
+    async fn handle(&self, _message: &OwnedMessage) -> Result<()> {
+        self.release.cancelled().await;
+        self.handled.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// value of the series starting with ``series`` or zero
+fn value(metrics: &Metrics, series: &str) -> f64 {
+    metrics
+        .render()
+        .lines()
+        .find(|line| line.starts_with(series))
+        .and_then(|line| line.rsplit(' ').next()?.parse().ok())
+        .unwrap_or(0.0)
+}
+
+async fn wait_for<F: Fn() -> bool>(done: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !done() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    done()
+}
+
+/// consume ``MESSAGES`` records of ``payload_len`` bytes with blocked
+/// handlers and the ``limits``, returns the number of messages
+/// received while the handlers were blocked
+async fn consume_blocked(
+    topic: &str,
+    payload_len: usize,
+    limits: InFlightLimits,
+) -> usize {
+    let cluster = TestCluster::new(&[(topic, 1)]);
+    let records = (0..MESSAGES)
+        .map(|i| {
+            ProducerRecord::new()
+                .key(format!("user-{i}"))
+                .payload("x".repeat(payload_len))
+        })
+        .collect();
+    cluster.produce(topic, records).await;
+
+    let metrics = Arc::new(Metrics::new());
+    let consumer = KafkaTlsConfig::new(&cluster.bootstrap_servers())
+        .profile(ConnectionProfile::Plain)
+        .metrics(metrics.clone())
+        .set("auto.offset.reset", "earliest")
+        .create_consumer("backpressure-group")
+        .expect("consumer");
+    consumer.subscribe(&[topic]).expect("subscribe");
+    let handler = Gate {
+        handled: AtomicUsize::new(0),
+        release: CancellationToken::new(),
+    };
+    let shutdown = CancellationToken::new();
+    let options = ConsumeOptions::new()
+        .key_workers(4)
+        .in_flight_limits(limits)
+        .shutdown(shutdown.clone());
+    let consumed = consume_messages(&consumer, &handler, &options);
+    let received =
+        format!("kafka_messages_consumed_total{{topic=\"{topic}\"}}");
+    let checks = async {
+        assert!(
+            wait_for(|| value(&metrics, "kafka_consumer_pauses_total") == 1.0)
+                .await
+        );
+        // nothing is fetched while the partition is paused
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let blocked = value(&metrics, &received) as usize;
+        handler.release.cancel();
+        assert!(
+            wait_for(|| handler.handled.load(Ordering::SeqCst) == MESSAGES)
+                .await
+        );
+        shutdown.cancel();
+        blocked
+    };
+    let (consumed, blocked) = tokio::join!(consumed, checks);
+    consumed.expect("consume");
+
+    assert_eq!(value(&metrics, &received) as usize, MESSAGES);
+    assert!(value(&metrics, "kafka_consumer_paused_seconds_total") >= 1.0);
+    assert_eq!(
+        cluster.committed_offset("backpressure-group", topic, 0),
+        Offset::Offset(MESSAGES as i64)
+    );
+    blocked
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn message_limit_pauses_until_the_handlers_drain() {
+    let limits = InFlightLimits::new().messages(8);
+    let blocked = consume_blocked("limited.count", 10, limits).await;
+    // pausing drops what librdkafka prefetched for the partition
+    assert!(
+        (8..=10).contains(&blocked),
+        "received {blocked} while paused"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn byte_budget_pauses_until_the_handlers_drain() {
+    // six messages of a little over 1KiB reach the budget
+    let limits = InFlightLimits::new().bytes(6 * 1024);
+    let blocked = consume_blocked("limited.bytes", 1024, limits).await;
+    assert!(
+        (6..=8).contains(&blocked),
+        "received {blocked} while paused"
+    );
+}
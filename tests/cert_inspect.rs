@@ -0,0 +1,81 @@
+mod common;
+
+use std::time::Duration;
+
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::error::Result;
+
+use common::tls_proxy::ClientCert;
+use common::tls_proxy::TlsBroker;
+
+/// name and reason of a tls asset error
+fn asset_error<T: std::fmt::Debug>(
+    result: Result<T>,
+) -> (&'static str, String) {
+    match result {
+        Err(Error::TlsAsset { name, reason, .. }) => (name, reason),
+        other => panic!("unexpected result: {other:?}"),
+    }
+}
+
+#[test]
+fn inspect_reports_the_client_identity() {
+    let broker = TlsBroker::start(&[]);
+    let config = broker.client(ClientCert::Valid);
+
+    let report = config.inspect().expect("inspect");
+    assert_eq!(report.ca.len(), 1);
+    assert_eq!(report.chain.len(), 1);
+    let cert = report.cert();
+    assert_eq!(cert.subject, "CN=kafka");
+    assert_eq!(cert.issuer, report.ca[0].subject);
+    assert!(cert.sans.is_empty(), "{:?}", cert.sans);
+    assert!(cert.not_before < cert.not_after);
+    assert!(report.key_matches_cert);
+    assert_eq!(report.chain_error, None);
+
+    // issued for one day
+    assert!(cert.expires_within(Duration::from_secs(2 * 24 * 60 * 60)));
+    assert!(!cert.expires_within(Duration::from_secs(60 * 60)));
+    assert_eq!(config.check().expect("check"), report);
+}
+
+#[test]
+fn expired_certificate_fails_fast() {
+    let broker = TlsBroker::start(&[]);
+    let (name, reason) = asset_error(
+        broker
+            .client(ClientCert::Expired)
+            .create_producer()
+            .map(|_| ()),
+    );
+    assert_eq!(name, "certificate");
+    assert!(reason.contains("expired on"), "{reason}");
+}
+
+#[test]
+fn certificate_from_another_ca_fails_fast() {
+    let broker = TlsBroker::start(&[]);
+    let config = broker.client(ClientCert::WrongCa);
+    let (name, reason) = asset_error(config.client_config());
+    assert_eq!(name, "certificate");
+    assert!(reason.contains("not signed by the CA"), "{reason}");
+
+    let report = config
+        .client_chain_check(false)
+        .check()
+        .expect("chain check disabled");
+    assert!(report.chain_error.is_some());
+}
+
+#[test]
+fn key_of_another_certificate_fails_fast() {
+    let broker = TlsBroker::start(&[]);
+    let (other_key, _) = broker.identity(ClientCert::WrongCa);
+    let config = broker.client(ClientCert::Valid).key_location(other_key);
+
+    assert!(!config.inspect().expect("inspect").key_matches_cert);
+    let (name, reason) = asset_error(config.check());
+    assert_eq!(name, "key");
+    assert!(reason.contains("does not match"), "{reason}");
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rdkafka::message::Message;
+use rdkafka::producer::Producer;
+use rdkafka::topic_partition_list::Offset;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::cert_watcher::consume_with_reload;
+use rust_with_kafka_tls::cert_watcher::CertWatcher;
+use rust_with_kafka_tls::cert_watcher::ReloadingProducer;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::publish_options::PublishOptions;
+use rust_with_kafka_tls::publish_records::publish_records;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+
+use common::tls_proxy::write_client_identity;
+use common::CollectingHandler;
+use common::TestCluster;
+
+const TOPIC: &str = "rotation";
+const CHECK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// repo CA and a client identity it issued that a test can rotate
+struct TlsDir {
+    dir: PathBuf,
+}
+
+impl TlsDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir()
+            .join(format!("kafka-rotation-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).expect("tls dir");
+        fs::copy("kubernetes/tls/ca.pem", dir.join("ca.pem")).expect("copy ca");
+        write_client_identity(&dir, "client");
+        TlsDir { dir }
+    }
+
+    /// config for ``brokers`` reading the copied assets, the mock
+    /// cluster is plaintext so the assets are only validated
+    fn config(&self, brokers: &str) -> KafkaTlsConfig {
+        KafkaTlsConfig::new(brokers)
+            .ca_location(self.dir.join("ca.pem"))
+            .key_location(self.dir.join("client-key.pem"))
+            .cert_location(self.dir.join("client.pem"))
+            .set("security.protocol", "plaintext")
+    }
+
+    /// replace the client key and certificate with a new identity
+    fn rotate(&self) {
+        write_client_identity(&self.dir, "client");
+    }
+
+    fn write_cert(&self, contents: &str) {
+        fs::write(self.dir.join("client.pem"), contents).expect("write cert");
+    }
+}
+
+impl Drop for TlsDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn records(range: std::ops::Range<i32>) -> Vec<ProducerRecord> {
+    range
+        .map(|i| ProducerRecord::new().key(format!("key-{i}")).partition(0))
+        .collect()
+}
+
+#[tokio::test]
+async fn invalid_material_is_skipped_until_valid() {
+    let tls = TlsDir::new("invalid");
+    let mut watcher = CertWatcher::new(&tls.config("localhost:9092"))
+        .interval(CHECK_INTERVAL);
+
+    tls.write_cert("-----BEGIN CERTIFICATE-----\nhalf written");
+    let skipped =
+        tokio::time::timeout(Duration::from_millis(300), watcher.changed())
+            .await;
+    assert!(skipped.is_err(), "invalid cert was handed out");
+    assert_eq!(watcher.reloads(), 0);
+
+    tls.rotate();
+    tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+        .await
+        .expect("rotated cert not noticed");
+    assert_eq!(watcher.reloads(), 1);
+}
+
+#[tokio::test]
+async fn reloading_producer_delivers_in_flight_sends() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let tls = TlsDir::new("producer");
+    let config = tls.config(&cluster.bootstrap_servers());
+    let producer = ReloadingProducer::new(&config).expect("producer");
+
+    let replaced = producer.producer();
+    let options = PublishOptions::new();
+    let in_flight = publish_records(&replaced, TOPIC, records(0..5), &options);
+    let mut watcher = CertWatcher::new(&config).interval(CHECK_INTERVAL);
+    tls.rotate();
+    let shutdown = CancellationToken::new();
+    let (reports, ()) = tokio::join!(in_flight, async {
+        let watch = producer.watch(&mut watcher, &shutdown);
+        tokio::pin!(watch);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::select! {
+                _ = &mut watch => {}
+                _ = async {
+                    while producer.producer().client().native_ptr()
+                        == replaced.client().native_ptr()
+                    {
+                        tokio::time::sleep(CHECK_INTERVAL).await;
+                    }
+                } => {}
+            }
+        })
+        .await
+        .expect("producer was not reloaded");
+        shutdown.cancel();
+        watch.await;
+    });
+    for report in reports {
+        report.expect("in-flight delivery");
+    }
+    assert_eq!(watcher.reloads(), 1);
+
+    let reports =
+        publish_records(&producer.producer(), TOPIC, records(5..6), &options)
+            .await;
+    assert_eq!(reports[0].as_ref().expect("delivery").offset, 5);
+}
+
+#[tokio::test]
+async fn consumer_is_rebuilt_without_duplicates() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let tls = TlsDir::new("consumer");
+    cluster.produce(TOPIC, records(0..3)).await;
+
+    let shutdown = CancellationToken::new();
+    let handler = CollectingHandler::new(6, &shutdown);
+    let options = ConsumeOptions::new().shutdown(shutdown);
+    let config = tls
+        .config(&cluster.bootstrap_servers())
+        .set("auto.offset.reset", "earliest");
+    let mut watcher = CertWatcher::new(&config).interval(CHECK_INTERVAL);
+    let consume = consume_with_reload(
+        &mut watcher,
+        "rotating",
+        &[TOPIC],
+        &handler,
+        &options,
+    );
+    let rotate = async {
+        while handler.messages().len() < 3 {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+        tls.rotate();
+        // let the watcher swap the consumer before the next messages
+        tokio::time::sleep(CHECK_INTERVAL * 25).await;
+        cluster.produce(TOPIC, records(3..6)).await;
+    };
+    let (consumed, ()) = tokio::time::timeout(Duration::from_secs(60), async {
+        tokio::join!(consume, rotate)
+    })
+    .await
+    .expect("consume loop timed out");
+    consumed.expect("consume loop failed");
+
+    assert_eq!(watcher.reloads(), 1);
+    let offsets: Vec<i64> =
+        handler.messages().iter().map(|m| m.offset()).collect();
+    assert_eq!(offsets, vec![0, 1, 2, 3, 4, 5]);
+    assert_eq!(
+        cluster.committed_offset("rotating", TOPIC, 0),
+        Offset::Offset(6)
+    );
+}
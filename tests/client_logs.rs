@@ -0,0 +1,168 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
+
+use log::kv::Key;
+use log::Level;
+use log::LevelFilter;
+use log::Log;
+use log::Metadata;
+use log::Record;
+use rdkafka::config::RDKafkaLogLevel;
+use rdkafka::consumer::Consumer;
+use rdkafka::Statistics;
+
+use rust_with_kafka_tls::connection_profile::ConnectionProfile;
+use rust_with_kafka_tls::publish_options::PublishOptions;
+use rust_with_kafka_tls::publish_records::publish_records;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+
+use common::TestCluster;
+
+/// level, target, ``facility`` field and message of a log record
+type Line = (Level, String, Option<String>, String);
+
+/// logger that keeps every record
+struct Capture(Mutex<Vec<Line>>);
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let facility = record
+            .key_values()
+            .get(Key::from("facility"))
+            .map(|value| value.to_string());
+        self.0.lock().unwrap().push((
+            record.level(),
+            record.target().to_string(),
+            facility,
+            record.args().to_string(),
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+fn captured() -> &'static Capture {
+    static CAPTURE: OnceLock<&'static Capture> = OnceLock::new();
+    CAPTURE.get_or_init(|| {
+        let capture = Box::leak(Box::new(Capture(Mutex::new(Vec::new()))));
+        log::set_logger(capture).expect("logger");
+        log::set_max_level(LevelFilter::Trace);
+        capture
+    })
+}
+
+fn wait_for<F: Fn() -> bool>(done: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !done() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    done()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn librdkafka_logs_use_facility_targets() {
+    let capture = captured();
+    let cluster = TestCluster::new(&[("logs", 1)]);
+    let producer = KafkaTlsConfig::new(&cluster.bootstrap_servers())
+        .profile(ConnectionProfile::Plain)
+        .log_level(RDKafkaLogLevel::Debug)
+        .set("debug", "broker,topic")
+        .create_producer()
+        .expect("producer");
+    let records = vec![ProducerRecord::new().key("key").payload("value")];
+    for report in
+        publish_records(&producer, "logs", records, &PublishOptions::new())
+            .await
+    {
+        report.expect("delivered");
+    }
+
+    let debug_lines = || {
+        capture
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(level, target, facility, _)| {
+                *level == Level::Debug
+                    && target.starts_with("librdkafka::")
+                    && facility.as_deref().map(str::to_ascii_lowercase)
+                        == target.strip_prefix("librdkafka::").map(String::from)
+            })
+            .count()
+    };
+    assert!(wait_for(|| debug_lines() > 0));
+    drop(producer);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lost_brokers_only_warn() {
+    let capture = captured();
+    let producer = KafkaTlsConfig::new("127.0.0.1:1")
+        .profile(ConnectionProfile::Plain)
+        .create_producer()
+        .expect("producer");
+
+    let levels = || {
+        capture
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, target, _, message)| {
+                target == "librdkafka" && message.contains("127.0.0.1:1")
+            })
+            .map(|(level, ..)| *level)
+            .collect::<Vec<_>>()
+    };
+    assert!(wait_for(|| !levels().is_empty()));
+    assert!(levels().iter().all(|level| *level == Level::Warn));
+    drop(producer);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn statistics_reach_the_handler_of_producers_and_consumers() {
+    let cluster = TestCluster::new(&[("stats", 1)]);
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let recorded = reports.clone();
+    let tls = KafkaTlsConfig::new(&cluster.bootstrap_servers())
+        .profile(ConnectionProfile::Plain)
+        .stats_handler(
+            Duration::from_millis(100),
+            move |stats: &Statistics, json: &str| {
+                assert!(json.starts_with('{'));
+                recorded.lock().unwrap().push(stats.client_type.clone());
+            },
+        );
+    let producer = tls.create_producer().expect("producer");
+    let consumer = tls.create_consumer("stats-group").expect("consumer");
+    consumer.subscribe(&["stats"]).expect("subscribe");
+
+    let seen = |client_type: &str| {
+        reports
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|reported| reported == client_type)
+    };
+    // consumers serve their statistics while they are polled
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !(seen("producer") && seen("consumer")) && Instant::now() < deadline {
+        tokio::time::timeout(Duration::from_millis(100), consumer.recv())
+            .await
+            .ok();
+    }
+    assert!(seen("producer") && seen("consumer"));
+    drop(producer);
+    drop(consumer);
+}
@@ -0,0 +1,259 @@
+mod common;
+
+use std::ops::Range;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rdkafka::consumer::Consumer;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use rdkafka::topic_partition_list::Offset;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::commit_policy::CommitPolicy;
+use rust_with_kafka_tls::consume_messages::consume_messages;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::custom_context::LoggingConsumer;
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::error::Result;
+use rust_with_kafka_tls::message_handler::MessageHandler;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+
+use common::CollectingHandler;
+use common::TestCluster;
+
+const TOPIC: &str = "commits";
+const MESSAGES: usize = 5;
+
+/// fails every message with an offset in ``failing`` and cancels the
+/// shutdown token once ``stop_after`` messages were seen
+struct TestHandler {
+    failing: Range<i64>,
+    stop_after: usize,
+    seen: AtomicUsize,
+    shutdown: CancellationToken,
+}
+
+impl TestHandler {
+    fn new(failing: Range<i64>, shutdown: &CancellationToken) -> Self {
+        TestHandler {
+            failing,
+            stop_after: MESSAGES,
+            seen: AtomicUsize::new(0),
+            shutdown: shutdown.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl MessageHandler for TestHandler {
+    async fn handle(&self, message: &OwnedMessage) -> Result<()> {
+        if self.seen.fetch_add(1, Ordering::SeqCst) + 1 >= self.stop_after {
+            self.shutdown.cancel();
+        }
+        if self.failing.contains(&message.offset()) {
+            return Err(Error::Handler(format!(
+                "failing offset {}",
+                message.offset()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn consumer(
+    cluster: &TestCluster,
+    group_id: &str,
+    policy: CommitPolicy,
+) -> LoggingConsumer {
+    let mut settings = vec![("auto.commit.interval.ms", "100")];
+    settings.extend(policy.consumer_settings());
+    cluster.consumer_with(group_id, &settings)
+}
+
+/// consume every produced message with ``policy`` and return the
+/// loop result with the committed offset afterwards
+async fn run(
+    policy: CommitPolicy,
+    failing: Range<i64>,
+) -> (Result<()>, Offset) {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let records = (0..MESSAGES)
+        .map(|i| {
+            ProducerRecord::new()
+                .key(format!("key-{i}"))
+                .payload(format!("payload-{i}"))
+                .partition(0)
+        })
+        .collect();
+    cluster.produce(TOPIC, records).await;
+
+    let group_id = format!("commit-policy-{policy:?}");
+    let consumer = consumer(&cluster, &group_id, policy);
+    consumer.subscribe(&[TOPIC]).expect("subscribe");
+    let shutdown = CancellationToken::new();
+    let handler = TestHandler::new(failing, &shutdown);
+    let options = ConsumeOptions::new()
+        .shutdown(shutdown)
+        .commit_policy(policy);
+    let result = tokio::time::timeout(
+        Duration::from_secs(60),
+        consume_messages(&consumer, &handler, &options),
+    )
+    .await
+    .expect("consume loop timed out");
+    // give the auto committer a chance to run for the auto policy
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    (result, cluster.committed_offset(&group_id, TOPIC, 0))
+}
+
+#[tokio::test]
+async fn per_message_sync_commits_handled_messages() {
+    let (result, committed) = run(CommitPolicy::PerMessageSync, 0..0).await;
+    assert!(result.is_ok());
+    assert_eq!(committed, Offset::Offset(MESSAGES as i64));
+}
+
+#[tokio::test]
+async fn periodic_commits_stored_offsets_on_shutdown() {
+    let (result, committed) =
+        run(CommitPolicy::Periodic(Duration::from_millis(100)), 0..0).await;
+    assert!(result.is_ok());
+    assert_eq!(committed, Offset::Offset(MESSAGES as i64));
+}
+
+#[tokio::test]
+async fn after_handler_success_stops_before_failed_offset() {
+    let (result, committed) =
+        run(CommitPolicy::AfterHandlerSuccess, 2..i64::MAX).await;
+    assert!(matches!(result, Err(Error::Handler(_))));
+    assert_eq!(committed, Offset::Offset(2));
+}
+
+#[tokio::test]
+async fn failed_handlers_are_never_committed() {
+    for policy in [
+        CommitPolicy::Auto,
+        CommitPolicy::PerMessageSync,
+        CommitPolicy::PerMessageAsync,
+        CommitPolicy::Periodic(Duration::from_millis(100)),
+    ] {
+        let (result, committed) = run(policy, 0..i64::MAX).await;
+        assert!(result.is_ok(), "{policy:?}");
+        assert_eq!(committed, Offset::Invalid, "{policy:?}");
+    }
+}
+
+#[tokio::test]
+async fn later_successes_are_not_committed_past_a_failure() {
+    for policy in [
+        CommitPolicy::Auto,
+        CommitPolicy::PerMessageSync,
+        CommitPolicy::PerMessageAsync,
+        CommitPolicy::Periodic(Duration::from_millis(100)),
+    ] {
+        let (result, committed) = run(policy, 2..3).await;
+        assert!(result.is_ok(), "{policy:?}");
+        assert_eq!(committed, Offset::Offset(2), "{policy:?}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_skips_the_partitions_revoked_from_the_loop() {
+    let topic = "commits.revoked";
+    let group_id = "commit-policy-revoked";
+    let cluster = TestCluster::new(&[(topic, 2)]);
+    let produce = |count: usize| {
+        let records = (0..2)
+            .flat_map(|partition| {
+                (0..count).map(move |i| {
+                    ProducerRecord::new()
+                        .key(format!("key-{i}"))
+                        .payload(format!("payload-{i}"))
+                        .partition(partition)
+                })
+            })
+            .collect();
+        cluster.produce(topic, records)
+    };
+    produce(2).await;
+
+    // the mock coordinator waits up to a session timeout for the
+    // members to rejoin
+    let settings = [
+        ("enable.auto.commit", "false"),
+        ("session.timeout.ms", "6000"),
+        ("heartbeat.interval.ms", "500"),
+    ];
+    let first = cluster.consumer_with(group_id, &settings);
+    let second = cluster.consumer_with(group_id, &settings);
+    first.subscribe(&[topic]).expect("subscribe");
+    let (first_stop, second_stop) =
+        (CancellationToken::new(), CancellationToken::new());
+    let first_handler = CollectingHandler::new(usize::MAX, &first_stop);
+    let second_handler = CollectingHandler::new(usize::MAX, &second_stop);
+    let options = |stop: &CancellationToken| {
+        ConsumeOptions::new()
+            .commit_policy(CommitPolicy::PerMessageSync)
+            .shutdown(stop.clone())
+    };
+    let (first_options, second_options) =
+        (options(&first_stop), options(&second_stop));
+    let first_done = CancellationToken::new();
+    let first_loop = async {
+        let consumed =
+            consume_messages(&first, &first_handler, &first_options).await;
+        first_done.cancel();
+        consumed
+    };
+    let handled =
+        || first_handler.messages().len() + second_handler.messages().len();
+    let checks = async {
+        assert!(wait_for(|| first_handler.messages().len() == 4).await);
+        second.subscribe(&[topic]).expect("subscribe");
+        let assigned = |consumer: &LoggingConsumer| {
+            consumer.assignment().map(|a| a.count()).unwrap_or(0)
+        };
+        let second_loop =
+            consume_messages(&second, &second_handler, &second_options);
+        let rebalanced = async {
+            assert!(
+                wait_for(|| assigned(&first) == 1 && assigned(&second) == 1)
+                    .await
+            );
+            produce(4).await;
+            assert!(wait_for(|| handled() == 12).await);
+            // the first consumer handled both partitions before the
+            // rebalance and must not rewind the one it lost
+            first_stop.cancel();
+            first_done.cancelled().await;
+            let committed: Vec<Offset> = (0..2)
+                .map(|p| cluster.committed_offset(group_id, topic, p))
+                .collect();
+            second_stop.cancel();
+            committed
+        };
+        let (second_consumed, committed) =
+            tokio::join!(second_loop, rebalanced);
+        second_consumed.expect("second consumer");
+        committed
+    };
+    let (first_consumed, committed) =
+        tokio::time::timeout(Duration::from_secs(60), async {
+            tokio::join!(first_loop, checks)
+        })
+        .await
+        .expect("both loops stop");
+    first_consumed.expect("first consumer");
+    assert_eq!(committed, vec![Offset::Offset(6), Offset::Offset(6)]);
+}
+
+async fn wait_for<F: Fn() -> bool>(done: F) -> bool {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+    while !done() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    done()
+}
@@ -0,0 +1,181 @@
+//! Offline test harness on top of librdkafka's in-process
+//! [`MockCluster`](rdkafka::mocking::MockCluster), no network or
+//! kubernetes cluster needed.
+
+#![allow(dead_code)]
+
+pub mod tls_proxy;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::Consumer;
+use rdkafka::message::Headers;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use rdkafka::mocking::MockCluster;
+use rdkafka::producer::DefaultProducerContext;
+use rdkafka::topic_partition_list::Offset;
+use rdkafka::topic_partition_list::TopicPartitionList;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::custom_context::CustomContext;
+use rust_with_kafka_tls::custom_context::LoggingConsumer;
+use rust_with_kafka_tls::custom_context::LoggingProducer;
+use rust_with_kafka_tls::error::Result;
+use rust_with_kafka_tls::message_handler::MessageHandler;
+use rust_with_kafka_tls::publish_options::PublishOptions;
+use rust_with_kafka_tls::publish_records::publish_records;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+
+/// single broker mock cluster with pre-created topics
+pub struct TestCluster {
+    cluster: MockCluster<'static, DefaultProducerContext>,
+}
+
+impl TestCluster {
+    /// start a broker and create each ``(topic, partitions)``
+    pub fn new(topics: &[(&str, i32)]) -> Self {
+        let cluster = MockCluster::new(1).expect("mock cluster");
+        for (topic, partitions) in topics {
+            cluster
+                .create_topic(topic, *partitions, 1)
+                .expect("create topic");
+        }
+        TestCluster { cluster }
+    }
+
+    pub fn bootstrap_servers(&self) -> String {
+        self.cluster.bootstrap_servers()
+    }
+
+    pub fn mock(&self) -> &MockCluster<'static, DefaultProducerContext> {
+        &self.cluster
+    }
+
+    /// producer with default settings
+    pub fn producer(&self) -> LoggingProducer {
+        self.producer_with(&[])
+    }
+
+    /// producer with extra librdkafka ``settings``
+    pub fn producer_with(&self, settings: &[(&str, &str)]) -> LoggingProducer {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", self.bootstrap_servers());
+        for (key, value) in settings {
+            config.set(*key, *value);
+        }
+        config
+            .create_with_context(CustomContext::new())
+            .expect("producer")
+    }
+
+    /// publish ``records`` and panic on any failed delivery
+    pub async fn produce(&self, topic: &str, records: Vec<ProducerRecord>) {
+        let producer = self.producer();
+        for report in
+            publish_records(&producer, topic, records, &PublishOptions::new())
+                .await
+        {
+            report.expect("delivery");
+        }
+    }
+
+    /// manual-commit consumer reading from the earliest offset
+    pub fn consumer(&self, group_id: &str) -> LoggingConsumer {
+        self.consumer_with(group_id, &[("enable.auto.commit", "false")])
+    }
+
+    /// consumer reading from the earliest offset with extra
+    /// librdkafka ``settings``
+    pub fn consumer_with(
+        &self,
+        group_id: &str,
+        settings: &[(&str, &str)],
+    ) -> LoggingConsumer {
+        let mut config = ClientConfig::new();
+        config
+            .set("bootstrap.servers", self.bootstrap_servers())
+            .set("group.id", group_id)
+            .set("auto.offset.reset", "earliest");
+        for (key, value) in settings {
+            config.set(*key, *value);
+        }
+        config
+            .create_with_context(CustomContext::new())
+            .expect("consumer")
+    }
+
+    /// offset committed by ``group_id`` for ``topic`` ``partition``
+    pub fn committed_offset(
+        &self,
+        group_id: &str,
+        topic: &str,
+        partition: i32,
+    ) -> Offset {
+        let mut tpl = TopicPartitionList::new();
+        tpl.add_partition(topic, partition);
+        self.consumer(group_id)
+            .committed_offsets(tpl, Duration::from_secs(10))
+            .expect("committed offsets")
+            .find_partition(topic, partition)
+            .expect("partition")
+            .offset()
+    }
+
+    /// read the next message from ``topic`` with a new group
+    pub async fn recv(&self, topic: &str, group_id: &str) -> OwnedMessage {
+        let consumer = self.consumer(group_id);
+        consumer.subscribe(&[topic]).expect("subscribe");
+        // drop the borrowed message before the consumer or destroy blocks
+        let message =
+            tokio::time::timeout(Duration::from_secs(30), consumer.recv())
+                .await
+                .expect("no message received")
+                .expect("message");
+        message.detach()
+    }
+}
+
+/// value of the first header called ``name`` as utf-8
+pub fn header(message: &OwnedMessage, name: &str) -> String {
+    let headers = message.headers().expect("headers");
+    let header = headers.iter().find(|h| h.key == name).expect(name);
+    String::from_utf8(header.value.unwrap_or(&[]).to_vec()).expect("utf-8")
+}
+
+/// handler that keeps every message and cancels the shutdown token
+/// once ``stop_after`` messages were handled
+pub struct CollectingHandler {
+    pub messages: Mutex<Vec<OwnedMessage>>,
+    stop_after: usize,
+    shutdown: CancellationToken,
+}
+
+impl CollectingHandler {
+    pub fn new(stop_after: usize, shutdown: &CancellationToken) -> Self {
+        CollectingHandler {
+            messages: Mutex::new(Vec::new()),
+            stop_after,
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    pub fn messages(&self) -> Vec<OwnedMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl MessageHandler for CollectingHandler {
+    async fn handle(&self, message: &OwnedMessage) -> Result<()> {
+        let mut messages = self.messages.lock().unwrap();
+        messages.push(message.clone());
+        if messages.len() >= self.stop_after {
+            self.shutdown.cancel();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+//! TLS-terminating stand-in for a strimzi broker: a proxy that
+//! requires client certificates signed by the repo's
+//! ``kubernetes/tls/ca.pem`` and forwards the decrypted traffic to a
+//! [`TestCluster`](super::TestCluster).
+
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::BigNum;
+use openssl::bn::MsbOption;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::pkey::Private;
+use openssl::rsa::Rsa;
+use openssl::ssl::Ssl;
+use openssl::ssl::SslAcceptor;
+use openssl::ssl::SslMethod;
+use openssl::ssl::SslVerifyMode;
+use openssl::ssl::SslVersion;
+use openssl::symm::Cipher;
+use openssl::x509::extension::BasicConstraints;
+use openssl::x509::extension::ExtendedKeyUsage;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::X509Builder;
+use openssl::x509::X509NameBuilder;
+use openssl::x509::X509;
+use rdkafka::client::ClientContext;
+use rdkafka::config::ClientConfig;
+use rdkafka::config::RDKafkaLogLevel;
+use rdkafka::error::KafkaError;
+use rdkafka::producer::BaseProducer;
+use rdkafka::producer::DeliveryResult;
+use rdkafka::producer::Producer;
+use rdkafka::producer::ProducerContext;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio_openssl::SslStream;
+
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+
+use super::TestCluster;
+
+/// password of the encrypted copy of the valid client key
+pub const KEY_PASSWORD: &str = "changeit";
+/// hostname in the proxy certificate that no dns server resolves
+pub const ADVERTISED_HOST: &str = "broker-0.kafka.test";
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+fn repo_tls(file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("kubernetes")
+        .join("tls")
+        .join(file)
+}
+
+fn load_cert(path: &Path) -> X509 {
+    X509::from_pem(&fs::read(path).expect("read cert")).expect("cert")
+}
+
+fn load_key(path: &Path) -> PKey<Private> {
+    PKey::private_key_from_pem(&fs::read(path).expect("read key")).expect("key")
+}
+
+fn new_key() -> PKey<Private> {
+    PKey::from_rsa(Rsa::generate(2048).expect("rsa")).expect("key")
+}
+
+/// sign a certificate for ``cn`` that is valid for one day from now
+/// with ``issuer`` or self-sign it as a certificate authority when
+/// there is no issuer
+fn issue(
+    cn: &str,
+    key: &PKey<Private>,
+    issuer: Option<(&X509, &PKey<Private>)>,
+    server: bool,
+) -> X509 {
+    let not_before = Asn1Time::days_from_now(0).expect("now");
+    let not_after = Asn1Time::days_from_now(1).expect("tomorrow");
+    issue_valid(cn, key, issuer, server, &not_before, &not_after)
+}
+
+/// sign a client certificate for ``cn`` with ``issuer`` that expired
+/// a day ago
+fn issue_expired(
+    cn: &str,
+    key: &PKey<Private>,
+    issuer: (&X509, &PKey<Private>),
+) -> X509 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("clock")
+        .as_secs() as i64;
+    let day = 24 * 60 * 60;
+    let not_before = Asn1Time::from_unix(now - 2 * day).expect("two days ago");
+    let not_after = Asn1Time::from_unix(now - day).expect("yesterday");
+    issue_valid(cn, key, Some(issuer), false, &not_before, &not_after)
+}
+
+fn issue_valid(
+    cn: &str,
+    key: &PKey<Private>,
+    issuer: Option<(&X509, &PKey<Private>)>,
+    server: bool,
+    not_before: &Asn1Time,
+    not_after: &Asn1Time,
+) -> X509 {
+    let mut name = X509NameBuilder::new().expect("name");
+    name.append_entry_by_text("CN", cn).expect("cn");
+    let name = name.build();
+    let mut serial = BigNum::new().expect("serial");
+    serial.rand(64, MsbOption::MAYBE_ZERO, false).expect("rand");
+
+    let mut builder = X509Builder::new().expect("x509");
+    builder.set_version(2).expect("version");
+    builder
+        .set_serial_number(&serial.to_asn1_integer().expect("serial"))
+        .expect("serial");
+    builder.set_subject_name(&name).expect("subject");
+    builder.set_pubkey(key).expect("pubkey");
+    builder.set_not_before(not_before).expect("not before");
+    builder.set_not_after(not_after).expect("not after");
+    match issuer {
+        Some((issuer_cert, _)) => builder
+            .set_issuer_name(issuer_cert.subject_name())
+            .expect("issuer"),
+        None => {
+            builder.set_issuer_name(&name).expect("issuer");
+            builder
+                .append_extension(
+                    BasicConstraints::new().critical().ca().build().unwrap(),
+                )
+                .expect("ca");
+        }
+    }
+    if server {
+        let san = SubjectAlternativeName::new()
+            .dns("localhost")
+            .dns(ADVERTISED_HOST)
+            .ip("127.0.0.1")
+            .build(&builder.x509v3_context(issuer.map(|(c, _)| &**c), None))
+            .expect("san");
+        builder.append_extension(san).expect("san");
+        builder
+            .append_extension(
+                ExtendedKeyUsage::new().server_auth().build().unwrap(),
+            )
+            .expect("eku");
+    } else if issuer.is_some() {
+        builder
+            .append_extension(
+                ExtendedKeyUsage::new().client_auth().build().unwrap(),
+            )
+            .expect("eku");
+    }
+    let signing_key = issuer.map_or(key, |(_, issuer_key)| issuer_key);
+    builder
+        .sign(signing_key, MessageDigest::sha256())
+        .expect("sign");
+    builder.build()
+}
+
+/// client certificate presented to the proxy
+#[derive(Clone, Copy, Debug)]
+pub enum ClientCert {
+    /// freshly issued by the repo ca
+    Valid,
+    /// issued by the repo ca and already expired
+    Expired,
+    /// issued by a ca the proxy does not trust
+    WrongCa,
+}
+
+/// TlsBroker
+///
+/// Mock cluster behind a TLS proxy that enforces client mTLS with
+/// the repo ca. Only the bootstrap connection goes through the
+/// proxy, the brokers advertised in the metadata stay plaintext, so
+/// tests assert on the first metadata request.
+pub struct TlsBroker {
+    cluster: TestCluster,
+    addr: SocketAddr,
+    dir: PathBuf,
+    shutdown: Option<oneshot::Sender<()>>,
+    proxy: Option<thread::JoinHandle<()>>,
+}
+
+impl TlsBroker {
+    /// start a mock cluster with ``topics`` behind a TLS proxy
+    pub fn start(topics: &[(&str, i32)]) -> Self {
+        let cluster = TestCluster::new(topics);
+        let upstream = cluster.bootstrap_servers();
+        let dir = std::env::temp_dir().join(format!(
+            "kafka-tls-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).expect("tls dir");
+
+        let ca_cert = load_cert(&repo_tls("ca.pem"));
+        let ca_key = load_key(&repo_tls("ca-key.pem"));
+        let server_key = new_key();
+        let server_cert =
+            issue("localhost", &server_key, Some((&ca_cert, &ca_key)), true);
+        let client_key = new_key();
+        let client_cert =
+            issue("kafka", &client_key, Some((&ca_cert, &ca_key)), false);
+        write_identity(&dir, "valid", &client_key, &client_cert);
+        fs::write(
+            dir.join("valid-encrypted-key.pem"),
+            client_key
+                .private_key_to_pem_pkcs8_passphrase(
+                    Cipher::aes_256_cbc(),
+                    KEY_PASSWORD.as_bytes(),
+                )
+                .expect("encrypted key pem"),
+        )
+        .expect("write encrypted key");
+        let other_ca_key = new_key();
+        let other_ca = issue("Other CA", &other_ca_key, None, false);
+        let other_key = new_key();
+        let other_cert =
+            issue("kafka", &other_key, Some((&other_ca, &other_ca_key)), false);
+        write_identity(&dir, "wrong-ca", &other_key, &other_cert);
+        let expired_key = new_key();
+        let expired_cert =
+            issue_expired("kafka", &expired_key, (&ca_cert, &ca_key));
+        write_identity(&dir, "expired", &expired_key, &expired_cert);
+
+        let mut acceptor =
+            SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())
+                .expect("acceptor");
+        acceptor.set_private_key(&server_key).expect("server key");
+        acceptor.set_certificate(&server_cert).expect("server cert");
+        acceptor.cert_store_mut().add_cert(ca_cert).expect("ca");
+        acceptor.set_verify(
+            SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        );
+        // tls 1.2 rejects client certs inside the handshake so the
+        // client sees the alert instead of a dropped connection
+        acceptor
+            .set_max_proto_version(Some(SslVersion::TLS1_2))
+            .expect("tls 1.2");
+        let acceptor = acceptor.build();
+
+        let listener =
+            std::net::TcpListener::bind("127.0.0.1:0").expect("bind proxy");
+        listener.set_nonblocking(true).expect("nonblocking");
+        let addr = listener.local_addr().expect("proxy addr");
+        let (shutdown, stopped) = oneshot::channel();
+        let proxy = thread::spawn(move || {
+            run_proxy(listener, acceptor, upstream, stopped)
+        });
+        TlsBroker {
+            cluster,
+            addr,
+            dir,
+            shutdown: Some(shutdown),
+            proxy: Some(proxy),
+        }
+    }
+
+    /// ``host:port`` of the TLS proxy
+    pub fn brokers(&self) -> String {
+        self.addr.to_string()
+    }
+
+    pub fn cluster(&self) -> &TestCluster {
+        &self.cluster
+    }
+
+    /// repo CA that signed the proxy certificate
+    pub fn ca_path(&self) -> PathBuf {
+        repo_tls("ca.pem")
+    }
+
+    /// ``(key, certificate)`` paths for ``cert``
+    pub fn identity(&self, cert: ClientCert) -> (PathBuf, PathBuf) {
+        match cert {
+            ClientCert::Valid => {
+                (self.dir.join("valid-key.pem"), self.dir.join("valid.pem"))
+            }
+            ClientCert::Expired => (
+                self.dir.join("expired-key.pem"),
+                self.dir.join("expired.pem"),
+            ),
+            ClientCert::WrongCa => (
+                self.dir.join("wrong-ca-key.pem"),
+                self.dir.join("wrong-ca.pem"),
+            ),
+        }
+    }
+
+    /// the valid client key encrypted with ``KEY_PASSWORD``
+    pub fn encrypted_key_path(&self) -> PathBuf {
+        self.dir.join("valid-encrypted-key.pem")
+    }
+
+    /// tls config for the proxy presenting ``cert``
+    pub fn client(&self, cert: ClientCert) -> KafkaTlsConfig {
+        let (key, cert) = self.identity(cert);
+        KafkaTlsConfig::new(&self.brokers())
+            .ca_location(self.ca_path())
+            .key_location(key)
+            .cert_location(cert)
+    }
+}
+
+impl Drop for TlsBroker {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(proxy) = self.proxy.take() {
+            let _ = proxy.join();
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// issue a client key and certificate signed by the repo ca into
+/// ``dir`` and return their ``(key, certificate)`` paths
+pub fn write_client_identity(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+    let ca_cert = load_cert(&repo_tls("ca.pem"));
+    let ca_key = load_key(&repo_tls("ca-key.pem"));
+    let key = new_key();
+    let cert = issue("kafka", &key, Some((&ca_cert, &ca_key)), false);
+    write_identity(dir, name, &key, &cert);
+    (
+        dir.join(format!("{name}-key.pem")),
+        dir.join(format!("{name}.pem")),
+    )
+}
+
+fn write_identity(dir: &Path, name: &str, key: &PKey<Private>, cert: &X509) {
+    fs::write(
+        dir.join(format!("{name}-key.pem")),
+        key.private_key_to_pem_pkcs8().expect("key pem"),
+    )
+    .expect("write key");
+    fs::write(
+        dir.join(format!("{name}.pem")),
+        cert.to_pem().expect("cert pem"),
+    )
+    .expect("write cert");
+}
+
+/// accept tls connections until ``stopped`` fires and pipe each one
+/// to the plaintext ``upstream`` broker
+fn run_proxy(
+    listener: std::net::TcpListener,
+    acceptor: SslAcceptor,
+    upstream: String,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("proxy runtime");
+    runtime.block_on(async move {
+        let listener = TcpListener::from_std(listener).expect("listener");
+        loop {
+            let (socket, _) = tokio::select! {
+                _ = &mut stopped => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(_) => continue,
+                },
+            };
+            let ssl = Ssl::new(acceptor.context()).expect("ssl");
+            let upstream = upstream.clone();
+            tokio::spawn(async move {
+                let mut tls = SslStream::new(ssl, socket).expect("stream");
+                if Pin::new(&mut tls).accept().await.is_err() {
+                    return;
+                }
+                if let Ok(mut broker) = TcpStream::connect(&upstream).await {
+                    let _ =
+                        tokio::io::copy_bidirectional(&mut tls, &mut broker)
+                            .await;
+                }
+            });
+        }
+    });
+}
+
+/// client context that keeps every librdkafka error and log line
+#[derive(Default)]
+pub struct RecordingContext {
+    errors: Mutex<Vec<String>>,
+}
+
+impl RecordingContext {
+    pub fn errors(&self) -> Vec<String> {
+        self.errors.lock().unwrap().clone()
+    }
+}
+
+impl ClientContext for RecordingContext {
+    fn log(&self, level: RDKafkaLogLevel, fac: &str, log_message: &str) {
+        if matches!(
+            level,
+            RDKafkaLogLevel::Emerg
+                | RDKafkaLogLevel::Alert
+                | RDKafkaLogLevel::Critical
+                | RDKafkaLogLevel::Error
+        ) {
+            self.errors
+                .lock()
+                .unwrap()
+                .push(format!("{fac}: {log_message}"));
+        }
+    }
+
+    fn error(&self, error: KafkaError, reason: &str) {
+        self.errors
+            .lock()
+            .unwrap()
+            .push(format!("{error}: {reason}"));
+    }
+}
+
+impl ProducerContext for RecordingContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, _result: &DeliveryResult<'_>, _opaque: ()) {}
+}
+
+/// connect with ``config`` and fetch the topic names from the
+/// cluster metadata, returning the librdkafka errors when the
+/// connection fails
+pub fn fetch_topics(
+    config: &ClientConfig,
+) -> std::result::Result<Vec<String>, Vec<String>> {
+    let producer: BaseProducer<RecordingContext> = config
+        .create_with_context(RecordingContext::default())
+        .expect("producer");
+    producer
+        .client()
+        .fetch_metadata(None, Duration::from_secs(5))
+        .map(|metadata| {
+            metadata
+                .topics()
+                .iter()
+                .map(|topic| topic.name().to_string())
+                .collect()
+        })
+        .map_err(|e| {
+            // error callbacks are served from the main queue
+            producer.poll(Duration::from_secs(1));
+            let mut errors = producer.context().errors();
+            errors.push(e.to_string());
+            errors
+        })
+}
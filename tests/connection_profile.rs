@@ -0,0 +1,99 @@
+mod common;
+
+use std::time::Duration;
+
+use rdkafka::consumer::Consumer;
+use rdkafka::Message;
+
+use rust_with_kafka_tls::connection_profile::ConnectionProfile;
+use rust_with_kafka_tls::connection_profile::DEFAULT_TLS_EXTERNAL_BROKERS;
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::publish_messages::publish_messages;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+
+use common::TestCluster;
+
+const TOPIC: &str = "profiles";
+
+#[test]
+fn profiles_parse_by_name() {
+    for profile in [
+        ConnectionProfile::Plain,
+        ConnectionProfile::TlsInternal,
+        ConnectionProfile::TlsExternal,
+    ] {
+        assert_eq!(
+            profile.to_string().parse::<ConnectionProfile>().unwrap(),
+            profile
+        );
+    }
+    assert!(matches!(
+        "sasl".parse::<ConnectionProfile>(),
+        Err(Error::Config(_))
+    ));
+}
+
+#[test]
+fn profile_ports_are_added_to_bare_hosts() {
+    let tls = KafkaTlsConfig::new("kafka-0, kafka-1:9192,[::1],[::1]:9000")
+        .profile(ConnectionProfile::TlsInternal);
+    assert_eq!(
+        tls.brokers(),
+        "kafka-0:9093,kafka-1:9192,[::1]:9093,[::1]:9000"
+    );
+    assert_eq!(
+        ConnectionProfile::TlsExternal
+            .with_ports("cluster-0-broker-0.redten.io"),
+        "cluster-0-broker-0.redten.io:32150"
+    );
+    assert_eq!(
+        ConnectionProfile::TlsExternal.with_ports(DEFAULT_TLS_EXTERNAL_BROKERS),
+        DEFAULT_TLS_EXTERNAL_BROKERS
+    );
+}
+
+#[test]
+fn profiles_set_the_security_protocol() {
+    let tls_internal = KafkaTlsConfig::new("kafka-0")
+        .ca_location("./kubernetes/tls/ca.pem")
+        .profile(ConnectionProfile::TlsInternal);
+    assert!(matches!(
+        tls_internal
+            .key_location("./missing/client-key.pem")
+            .validate(),
+        Err(Error::TlsAsset { name: "key", .. })
+    ));
+
+    // the plain listener needs no tls assets
+    let plain = KafkaTlsConfig::new("kafka-0")
+        .ca_location("./missing/ca.pem")
+        .profile(ConnectionProfile::Plain);
+    let config = plain.client_config().expect("client config");
+    assert_eq!(config.get("bootstrap.servers"), Some("kafka-0:9092"));
+    assert_eq!(config.get("security.protocol"), Some("PLAINTEXT"));
+    assert_eq!(config.get("ssl.ca.location"), None);
+    assert_eq!(config.get("enable.ssl.certificate.verification"), None);
+}
+
+#[tokio::test]
+async fn plain_profile_produces_and_consumes() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let tls = KafkaTlsConfig::new(&cluster.bootstrap_servers())
+        .ca_location("./missing/ca.pem")
+        .profile(ConnectionProfile::Plain);
+
+    let producer = tls.create_producer().expect("producer");
+    publish_messages(&producer, TOPIC).await.expect("publish");
+
+    let consumer = tls
+        .set("auto.offset.reset", "earliest")
+        .create_consumer("profiles")
+        .expect("consumer");
+    consumer.subscribe(&[TOPIC]).expect("subscribe");
+    let message =
+        tokio::time::timeout(Duration::from_secs(10), consumer.recv())
+            .await
+            .expect("message before the timeout")
+            .expect("message");
+    assert_eq!(message.payload(), Some(&b"Message 0"[..]));
+}
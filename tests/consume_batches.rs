@@ -0,0 +1,329 @@
+mod common;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rdkafka::consumer::Consumer;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use rdkafka::topic_partition_list::Offset;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::commit_policy::CommitPolicy;
+use rust_with_kafka_tls::consume_batches::consume_batches;
+use rust_with_kafka_tls::consume_batches::BatchSize;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::custom_context::LoggingConsumer;
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::error::Result;
+use rust_with_kafka_tls::message_handler::BatchHandler;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+
+use common::TestCluster;
+
+/// records the partition, offset and size of every message of every
+/// batch, fails the batch with index ``fail`` and cancels ``shutdown``
+/// once ``stop_after`` messages were handled
+struct BatchRecorder {
+    batches: Mutex<Vec<Vec<(i32, i64, usize)>>>,
+    fail: Option<usize>,
+    stop_after: usize,
+    shutdown: CancellationToken,
+}
+
+impl BatchRecorder {
+    fn new(stop_after: usize, shutdown: &CancellationToken) -> Self {
+        BatchRecorder {
+            batches: Mutex::new(Vec::new()),
+            fail: None,
+            stop_after,
+            shutdown: shutdown.clone(),
+        }
+    }
+
+    fn batches(&self) -> Vec<Vec<(i32, i64, usize)>> {
+        self.batches.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl BatchHandler for BatchRecorder {
+    async fn handle_batch(&self, messages: &[OwnedMessage]) -> Result<()> {
+        let mut batches = self.batches.lock().unwrap();
+        if self.fail == Some(batches.len()) {
+            batches.push(Vec::new());
+            return Err(Error::Handler("failing on purpose".to_string()));
+        }
+        batches.push(
+            messages
+                .iter()
+                .map(|m| {
+                    let size = m.key().map(|k| k.len()).unwrap_or(0)
+                        + m.payload().map(|p| p.len()).unwrap_or(0);
+                    (m.partition(), m.offset(), size)
+                })
+                .collect(),
+        );
+        if batches.iter().map(|b| b.len()).sum::<usize>() >= self.stop_after {
+            self.shutdown.cancel();
+        }
+        Ok(())
+    }
+}
+
+/// ``count`` records with a ``payload_len`` byte payload for each of
+/// the ``partitions``
+async fn produce(
+    cluster: &TestCluster,
+    topic: &str,
+    partitions: i32,
+    count: usize,
+    payload_len: usize,
+) {
+    let records = (0..partitions)
+        .flat_map(|partition| {
+            (0..count).map(move |i| {
+                ProducerRecord::new()
+                    .key(format!("key-{i}"))
+                    .payload("x".repeat(payload_len))
+                    .partition(partition)
+            })
+        })
+        .collect();
+    cluster.produce(topic, records).await;
+}
+
+async fn consume<H: BatchHandler>(
+    cluster: &TestCluster,
+    topic: &str,
+    group_id: &str,
+    handler: &H,
+    options: &ConsumeOptions,
+) -> Result<()> {
+    let consumer = cluster.consumer(group_id);
+    consumer.subscribe(&[topic]).expect("subscribe");
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        consume_batches(&consumer, handler, options),
+    )
+    .await
+    .expect("batch loop stops")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn full_batches_and_the_timeout_hand_over_messages() {
+    let topic = "batched";
+    let cluster = TestCluster::new(&[(topic, 1)]);
+    produce(&cluster, topic, 1, 25, 10).await;
+
+    let shutdown = CancellationToken::new();
+    let handler = BatchRecorder::new(25, &shutdown);
+    let options = ConsumeOptions::new()
+        .batch_size(
+            BatchSize::new()
+                .messages(10)
+                .timeout(Duration::from_millis(500)),
+        )
+        .shutdown(shutdown);
+    consume(&cluster, topic, "batched-group", &handler, &options)
+        .await
+        .expect("consume");
+
+    // the last five messages are handed over by the timeout
+    let sizes: Vec<usize> = handler.batches().iter().map(Vec::len).collect();
+    assert_eq!(sizes, vec![10, 10, 5]);
+    let offsets: Vec<i64> = handler
+        .batches()
+        .concat()
+        .iter()
+        .map(|(_, offset, _)| *offset)
+        .collect();
+    assert_eq!(offsets, (0..25).collect::<Vec<i64>>());
+    assert_eq!(
+        cluster.committed_offset("batched-group", topic, 0),
+        Offset::Offset(25)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn byte_budget_splits_batches_and_commits_every_partition() {
+    let topic = "batched.bytes";
+    let cluster = TestCluster::new(&[(topic, 3)]);
+    produce(&cluster, topic, 3, 4, 100).await;
+
+    let shutdown = CancellationToken::new();
+    let handler = BatchRecorder::new(12, &shutdown);
+    // three messages of 105 bytes fit, a fourth would not
+    let options = ConsumeOptions::new()
+        .batch_size(
+            BatchSize::new()
+                .bytes(350)
+                .timeout(Duration::from_millis(500)),
+        )
+        .shutdown(shutdown);
+    consume(&cluster, topic, "batched-bytes-group", &handler, &options)
+        .await
+        .expect("consume");
+
+    let batches = handler.batches();
+    assert_eq!(batches.concat().len(), 12);
+    for batch in &batches {
+        let bytes: usize = batch.iter().map(|(_, _, size)| size).sum();
+        assert!(!batch.is_empty() && bytes <= 350, "{batches:?}");
+    }
+    for partition in 0..3 {
+        assert_eq!(
+            cluster.committed_offset("batched-bytes-group", topic, partition),
+            Offset::Offset(4)
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_batch_is_not_committed_after_handler_success() {
+    let topic = "batched.failures";
+    let cluster = TestCluster::new(&[(topic, 1)]);
+    produce(&cluster, topic, 1, 9, 10).await;
+
+    let shutdown = CancellationToken::new();
+    let mut handler = BatchRecorder::new(9, &shutdown);
+    handler.fail = Some(1);
+    let options = ConsumeOptions::new()
+        .batch_size(BatchSize::new().messages(3))
+        .commit_policy(CommitPolicy::AfterHandlerSuccess)
+        .shutdown(shutdown);
+    let consumed =
+        consume(&cluster, topic, "batched-failures", &handler, &options).await;
+
+    assert!(matches!(consumed, Err(Error::Handler(_))), "{consumed:?}");
+    assert_eq!(handler.batches().len(), 2);
+    // only the first batch is committed
+    assert_eq!(
+        cluster.committed_offset("batched-failures", topic, 0),
+        Offset::Offset(3)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_batch_holds_the_partition_commits() {
+    let topic = "batched.held";
+    let cluster = TestCluster::new(&[(topic, 1)]);
+    produce(&cluster, topic, 1, 9, 10).await;
+
+    let shutdown = CancellationToken::new();
+    let mut handler = BatchRecorder::new(9, &shutdown);
+    handler.fail = Some(1);
+    let options = ConsumeOptions::new()
+        .batch_size(BatchSize::new().messages(3))
+        .commit_policy(CommitPolicy::PerMessageSync)
+        .max_messages(9)
+        .shutdown(shutdown);
+    consume(&cluster, topic, "batched-held", &handler, &options)
+        .await
+        .expect("consume");
+
+    // the batch after the failed one succeeds without committing
+    let sizes: Vec<usize> = handler.batches().iter().map(Vec::len).collect();
+    assert_eq!(sizes, vec![3, 0, 3]);
+    assert_eq!(
+        cluster.committed_offset("batched-held", topic, 0),
+        Offset::Offset(3)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_skips_the_partitions_revoked_from_the_batches() {
+    let topic = "batched.revoked";
+    let group_id = "batched-revoked";
+    let cluster = TestCluster::new(&[(topic, 2)]);
+    produce(&cluster, topic, 2, 2, 10).await;
+
+    // the mock coordinator waits up to a session timeout for the
+    // members to rejoin
+    let settings = [
+        ("enable.auto.commit", "false"),
+        ("session.timeout.ms", "6000"),
+        ("heartbeat.interval.ms", "500"),
+    ];
+    let first = cluster.consumer_with(group_id, &settings);
+    let second = cluster.consumer_with(group_id, &settings);
+    first.subscribe(&[topic]).expect("subscribe");
+    let (first_stop, second_stop) =
+        (CancellationToken::new(), CancellationToken::new());
+    let first_handler = BatchRecorder::new(usize::MAX, &first_stop);
+    let second_handler = BatchRecorder::new(usize::MAX, &second_stop);
+    let options = |stop: &CancellationToken| {
+        ConsumeOptions::new()
+            .batch_size(
+                BatchSize::new()
+                    .messages(2)
+                    .timeout(Duration::from_millis(100)),
+            )
+            .commit_policy(CommitPolicy::PerMessageSync)
+            .shutdown(stop.clone())
+    };
+    let (first_options, second_options) =
+        (options(&first_stop), options(&second_stop));
+    let first_done = CancellationToken::new();
+    let first_loop = async {
+        let consumed =
+            consume_batches(&first, &first_handler, &first_options).await;
+        first_done.cancel();
+        consumed
+    };
+    let handled = |handler: &BatchRecorder| {
+        handler.batches().iter().map(|b| b.len()).sum::<usize>()
+    };
+    let checks = async {
+        assert!(wait_for(|| handled(&first_handler) == 4).await);
+        second.subscribe(&[topic]).expect("subscribe");
+        let assigned = |consumer: &LoggingConsumer| {
+            consumer.assignment().map(|a| a.count()).unwrap_or(0)
+        };
+        let second_loop =
+            consume_batches(&second, &second_handler, &second_options);
+        let rebalanced = async {
+            assert!(
+                wait_for(|| assigned(&first) == 1 && assigned(&second) == 1)
+                    .await
+            );
+            produce(&cluster, topic, 2, 4, 10).await;
+            assert!(
+                wait_for(|| {
+                    handled(&first_handler) + handled(&second_handler) == 12
+                })
+                .await
+            );
+            // the first consumer handled both partitions before the
+            // rebalance and must not rewind the one it lost
+            first_stop.cancel();
+            first_done.cancelled().await;
+            let committed: Vec<Offset> = (0..2)
+                .map(|p| cluster.committed_offset(group_id, topic, p))
+                .collect();
+            second_stop.cancel();
+            committed
+        };
+        let (second_consumed, committed) =
+            tokio::join!(second_loop, rebalanced);
+        second_consumed.expect("second consumer");
+        committed
+    };
+    let (first_consumed, committed) =
+        tokio::time::timeout(Duration::from_secs(60), async {
+            tokio::join!(first_loop, checks)
+        })
+        .await
+        .expect("both loops stop");
+    first_consumed.expect("first consumer");
+    assert_eq!(committed, vec![Offset::Offset(6), Offset::Offset(6)]);
+}
+
+async fn wait_for<F: Fn() -> bool>(done: F) -> bool {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(30);
+    while !done() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    done()
+}
@@ -0,0 +1,428 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use rdkafka::consumer::Consumer;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use rdkafka::topic_partition_list::Offset;
+use rdkafka::types::RDKafkaApiKey;
+use rdkafka::types::RDKafkaRespErr;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::commit_policy::CommitPolicy;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::consume_partitions::consume_partitions;
+use rust_with_kafka_tls::custom_context::LoggingConsumer;
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::error::Result;
+use rust_with_kafka_tls::message_handler::MessageHandler;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+
+use common::TestCluster;
+
+const PARTITIONS: i32 = 3;
+
+/// partition and offset of a handled message by consumer name
+type Seen = Arc<Mutex<Vec<(&'static str, i32, i64)>>>;
+
+/// partitions in a handler by consumer name and the messages whose
+/// handler started while another consumer handled the same partition
+#[derive(Default)]
+struct Handling {
+    running: Vec<(&'static str, i32)>,
+    overlaps: Vec<(&'static str, i32, i64)>,
+}
+
+/// records every message, handlers of the ``blocked`` partition wait
+/// until the given number of other messages were recorded, the
+/// ``failing`` partition and offset is not recorded and fails, every
+/// handler takes ``delay`` and tracks itself in ``handling``
+struct Recorder {
+    name: &'static str,
+    seen: Seen,
+    blocked: Option<(i32, usize)>,
+    failing: Option<(i32, i64)>,
+    delay: Duration,
+    handling: Arc<Mutex<Handling>>,
+}
+
+impl Recorder {
+    fn new(name: &'static str, seen: &Seen) -> Self {
+        Recorder {
+            name,
+            seen: seen.clone(),
+            blocked: None,
+            failing: None,
+            delay: Duration::ZERO,
+            handling: Arc::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl MessageHandler for Recorder {
+    async fn handle(&self, message: &OwnedMessage) -> Result<()> {
+        let running = (self.name, message.partition());
+        {
+            let mut handling = self.handling.lock().unwrap();
+            if handling.running.iter().any(|(name, partition)| {
+                *name != self.name && *partition == message.partition()
+            }) {
+                handling.overlaps.push((
+                    self.name,
+                    message.partition(),
+                    message.offset(),
+                ));
+            }
+            handling.running.push(running);
+        }
+        tokio::time::sleep(self.delay).await;
+        self.handling
+            .lock()
+            .unwrap()
+            .running
+            .retain(|other| *other != running);
+        if self.failing == Some((message.partition(), message.offset())) {
+            return Err(Error::Handler("failing".to_string()));
+        }
+        if let Some((partition, others)) = self.blocked {
+            if message.partition() == partition {
+                // a sequential consumer never gets to the others
+                assert!(
+                    wait_for(|| self.seen.lock().unwrap().len() >= others)
+                        .await
+                );
+            }
+        }
+        self.seen.lock().unwrap().push((
+            self.name,
+            message.partition(),
+            message.offset(),
+        ));
+        Ok(())
+    }
+}
+
+/// ``count`` records for every partition of ``topic``
+async fn produce(cluster: &TestCluster, topic: &str, count: usize) {
+    let records = (0..PARTITIONS)
+        .flat_map(|partition| {
+            (0..count).map(move |i| {
+                ProducerRecord::new()
+                    .key(format!("key-{i}"))
+                    .payload(format!("message {i}"))
+                    .partition(partition)
+            })
+        })
+        .collect();
+    cluster.produce(topic, records).await;
+}
+
+/// offsets handled by ``name`` for ``partition`` in handling order
+fn offsets(seen: &Seen, name: &str, partition: i32) -> Vec<i64> {
+    seen.lock()
+        .unwrap()
+        .iter()
+        .filter(|(by, p, _)| *by == name && *p == partition)
+        .map(|(_, _, offset)| *offset)
+        .collect()
+}
+
+/// number of different partitions and offsets handled
+fn distinct(seen: &Seen) -> usize {
+    let mut handled: Vec<_> = seen
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, partition, offset)| (*partition, *offset))
+        .collect();
+    handled.sort();
+    handled.dedup();
+    handled.len()
+}
+
+/// number of partitions assigned to ``consumer``
+fn assigned(consumer: &LoggingConsumer) -> usize {
+    consumer.assignment().map(|a| a.count()).unwrap_or(0)
+}
+
+/// committed offset of ``partition``, 0 without a commit
+fn committed(
+    cluster: &TestCluster,
+    group: &str,
+    topic: &str,
+    partition: i32,
+) -> i64 {
+    match cluster.committed_offset(group, topic, partition) {
+        Offset::Offset(offset) => offset,
+        _ => 0,
+    }
+}
+
+async fn wait_for<F: Fn() -> bool>(done: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !done() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    done()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blocked_partition_does_not_stall_the_others() {
+    let topic = "parallel";
+    let cluster = TestCluster::new(&[(topic, PARTITIONS)]);
+    produce(&cluster, topic, 5).await;
+
+    let seen = Seen::default();
+    let mut recorder = Recorder::new("a", &seen);
+    recorder.blocked = Some((0, 10));
+    let handler = Arc::new(recorder);
+    let consumer = cluster.consumer("parallel-group");
+    consumer.subscribe(&[topic]).expect("subscribe");
+    let shutdown = CancellationToken::new();
+    let options = ConsumeOptions::new().shutdown(shutdown.clone());
+    let running = tokio::spawn(async move {
+        consume_partitions(Arc::new(consumer), handler, &options).await
+    });
+
+    assert!(wait_for(|| seen.lock().unwrap().len() == 15).await);
+    shutdown.cancel();
+    running.await.expect("loop task").expect("consume");
+
+    for partition in 0..PARTITIONS {
+        assert_eq!(offsets(&seen, "a", partition), vec![0, 1, 2, 3, 4]);
+        assert_eq!(
+            cluster.committed_offset("parallel-group", topic, partition),
+            Offset::Offset(5)
+        );
+    }
+    // the other partitions finish while partition 0 is blocked
+    let order = seen.lock().unwrap().clone();
+    assert!(order[10..].iter().all(|(_, p, _)| *p == 0), "{order:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rebalance_moves_partition_tasks_between_consumers() {
+    let topic = "rebalanced";
+    let group_id = "rebalanced-group";
+    let cluster = TestCluster::new(&[(topic, PARTITIONS)]);
+    produce(&cluster, topic, 4).await;
+
+    let seen = Seen::default();
+    let shutdown = CancellationToken::new();
+    let start = |name: &'static str| {
+        // the mock coordinator waits up to a session timeout for the
+        // members to rejoin
+        let consumer = Arc::new(cluster.consumer_with(
+            group_id,
+            &[
+                ("enable.auto.commit", "false"),
+                ("session.timeout.ms", "6000"),
+                ("heartbeat.interval.ms", "500"),
+            ],
+        ));
+        consumer.subscribe(&[topic]).expect("subscribe");
+        let handler = Arc::new(Recorder::new(name, &seen));
+        let options = ConsumeOptions::new().shutdown(shutdown.clone());
+        let loop_consumer = consumer.clone();
+        let running = tokio::spawn(async move {
+            consume_partitions(loop_consumer, handler, &options).await
+        });
+        (consumer, running)
+    };
+
+    let (first, first_running) = start("first");
+    assert!(wait_for(|| seen.lock().unwrap().len() == 12).await);
+
+    // the second member takes over some partitions
+    let (second, second_running) = start("second");
+    assert!(
+        wait_for(|| assigned(&first) > 0
+            && assigned(&second) > 0
+            && assigned(&first) + assigned(&second) == PARTITIONS as usize)
+        .await
+    );
+    produce(&cluster, topic, 4).await;
+    let handled = || {
+        let seen = seen.lock().unwrap();
+        (0..PARTITIONS).all(|partition| {
+            (0..8).all(|offset| {
+                seen.iter().any(|(_, p, o)| *p == partition && *o == offset)
+            })
+        })
+    };
+    assert!(wait_for(handled).await, "{:?}", seen.lock().unwrap());
+    shutdown.cancel();
+    first_running.await.expect("loop task").expect("first");
+    second_running.await.expect("loop task").expect("second");
+
+    assert!(seen
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(by, _, _)| *by == "second"));
+    drop(first);
+    drop(second);
+    for partition in 0..PARTITIONS {
+        assert_eq!(
+            cluster.committed_offset(group_id, topic, partition),
+            Offset::Offset(8)
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_message_holds_its_partition_commits() {
+    let topic = "partition-failures";
+    let cluster = TestCluster::new(&[(topic, PARTITIONS)]);
+    produce(&cluster, topic, 5).await;
+
+    let seen = Seen::default();
+    let mut recorder = Recorder::new("a", &seen);
+    recorder.failing = Some((1, 2));
+    let handler = Arc::new(recorder);
+    let consumer = cluster.consumer("partition-failures-group");
+    consumer.subscribe(&[topic]).expect("subscribe");
+    let shutdown = CancellationToken::new();
+    let options = ConsumeOptions::new().shutdown(shutdown.clone());
+    let running = tokio::spawn(async move {
+        consume_partitions(Arc::new(consumer), handler, &options).await
+    });
+
+    assert!(wait_for(|| seen.lock().unwrap().len() == 14).await);
+    shutdown.cancel();
+    running.await.expect("loop task").expect("consume");
+
+    // the messages after the failed one were handled but not committed
+    assert_eq!(offsets(&seen, "a", 1), vec![0, 1, 3, 4]);
+    for (partition, offset) in [(0, 5), (1, 2), (2, 5)] {
+        assert_eq!(
+            committed(&cluster, "partition-failures-group", topic, partition),
+            offset
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_messages_stops_the_partition_tasks() {
+    let topic = "partition-limit";
+    let cluster = TestCluster::new(&[(topic, PARTITIONS)]);
+    produce(&cluster, topic, 5).await;
+
+    let seen = Seen::default();
+    let handler = Arc::new(Recorder::new("a", &seen));
+    let consumer = cluster.consumer("partition-limit-group");
+    consumer.subscribe(&[topic]).expect("subscribe");
+    let options = ConsumeOptions::new().max_messages(4);
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        consume_partitions(Arc::new(consumer), handler, &options),
+    )
+    .await
+    .expect("stops by itself")
+    .expect("consume");
+
+    assert_eq!(seen.lock().unwrap().len(), 4);
+    let committed: i64 = (0..PARTITIONS)
+        .map(|p| committed(&cluster, "partition-limit-group", topic, p))
+        .sum();
+    assert_eq!(committed, 4);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rebalancing_commit_leaves_the_offset_uncommitted() {
+    let topic = "partition-rebalancing-commit";
+    let group_id = "partition-rebalancing-commit-group";
+    let cluster = TestCluster::new(&[(topic, PARTITIONS)]);
+    produce(&cluster, topic, 3).await;
+    cluster.mock().request_errors(
+        RDKafkaApiKey::OffsetCommit,
+        &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_REBALANCE_IN_PROGRESS],
+    );
+
+    let seen = Seen::default();
+    let handler = Arc::new(Recorder::new("a", &seen));
+    let consumer = cluster.consumer(group_id);
+    consumer.subscribe(&[topic]).expect("subscribe");
+    let options = ConsumeOptions::new()
+        .commit_policy(CommitPolicy::PerMessageSync)
+        .max_messages(PARTITIONS as usize * 3);
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        consume_partitions(Arc::new(consumer), handler, &options),
+    )
+    .await
+    .expect("stops by itself")
+    .expect("the failed commit does not stop the loop");
+
+    // the later offsets of every partition cover the failed commit
+    assert_eq!(seen.lock().unwrap().len(), PARTITIONS as usize * 3);
+    for partition in 0..PARTITIONS {
+        assert_eq!(committed(&cluster, group_id, topic, partition), 3);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn revoke_waits_for_the_in_flight_message() {
+    let topic = "revoke-wait";
+    let group_id = "revoke-wait-group";
+    let cluster = TestCluster::new(&[(topic, PARTITIONS)]);
+    produce(&cluster, topic, 10).await;
+
+    let seen = Seen::default();
+    let handling = Arc::new(Mutex::new(Handling::default()));
+    let shutdown = CancellationToken::new();
+    let start = |name: &'static str| {
+        let consumer = Arc::new(cluster.consumer_with(
+            group_id,
+            &[
+                ("enable.auto.commit", "false"),
+                ("session.timeout.ms", "6000"),
+                ("heartbeat.interval.ms", "500"),
+            ],
+        ));
+        consumer.subscribe(&[topic]).expect("subscribe");
+        let mut recorder = Recorder::new(name, &seen);
+        recorder.delay = Duration::from_millis(200);
+        recorder.handling = handling.clone();
+        let options = ConsumeOptions::new().shutdown(shutdown.clone());
+        let loop_consumer = consumer.clone();
+        let running = tokio::spawn(async move {
+            consume_partitions(loop_consumer, Arc::new(recorder), &options)
+                .await
+        });
+        (consumer, running)
+    };
+
+    let (first, first_running) = start("first");
+    assert!(wait_for(|| !seen.lock().unwrap().is_empty()).await);
+    // the rebalance revokes partitions while their handlers run
+    let (second, second_running) = start("second");
+    assert!(
+        wait_for(|| assigned(&first) > 0
+            && assigned(&second) > 0
+            && assigned(&first) + assigned(&second) == PARTITIONS as usize)
+        .await
+    );
+    produce(&cluster, topic, 4).await;
+    let total = PARTITIONS as usize * 14;
+    assert!(wait_for(|| distinct(&seen) == total).await);
+    shutdown.cancel();
+    first_running.await.expect("loop task").expect("first");
+    second_running.await.expect("loop task").expect("second");
+
+    // the new owner only starts once the revoked handler finished, the
+    // mock cluster rejects the commit during the rebalance so messages
+    // may be handled twice, never at the same time
+    assert_eq!(handling.lock().unwrap().overlaps, vec![]);
+    drop(first);
+    drop(second);
+    for partition in 0..PARTITIONS {
+        assert_eq!(committed(&cluster, group_id, topic, partition), 14);
+    }
+}
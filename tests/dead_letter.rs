@@ -0,0 +1,130 @@
+mod common;
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rdkafka::consumer::Consumer;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use rdkafka::topic_partition_list::Offset;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::consume_and_print::consume_and_print;
+use rust_with_kafka_tls::consume_messages::consume_messages;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::dead_letter::DeadLetterQueue;
+use rust_with_kafka_tls::dead_letter::DLQ_ATTEMPTS_HEADER;
+use rust_with_kafka_tls::dead_letter::DLQ_ERROR_HEADER;
+use rust_with_kafka_tls::dead_letter::DLQ_ORIGINAL_OFFSET_HEADER;
+use rust_with_kafka_tls::dead_letter::DLQ_ORIGINAL_TOPIC_HEADER;
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::error::Result;
+use rust_with_kafka_tls::message_handler::MessageHandler;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+
+use common::header;
+use common::TestCluster;
+
+const TOPIC: &str = "events";
+const DLQ_TOPIC: &str = "events.dlq";
+
+/// fails every call and cancels the shutdown token after ``stop_after``
+struct FailingHandler {
+    calls: AtomicUsize,
+    stop_after: usize,
+    shutdown: CancellationToken,
+}
+
+#[async_trait]
+impl MessageHandler for FailingHandler {
+    async fn handle(&self, _message: &OwnedMessage) -> Result<()> {
+        if self.calls.fetch_add(1, Ordering::SeqCst) + 1 >= self.stop_after {
+            self.shutdown.cancel();
+        }
+        Err(Error::Handler("downstream unavailable".to_string()))
+    }
+}
+
+#[tokio::test]
+async fn failing_handler_is_retried_then_dead_lettered() {
+    let cluster = TestCluster::new(&[(TOPIC, 1), (DLQ_TOPIC, 1)]);
+    cluster
+        .produce(
+            TOPIC,
+            vec![ProducerRecord::new()
+                .key("user-1")
+                .payload("login")
+                .header("trace", "abc")
+                .partition(0)],
+        )
+        .await;
+
+    let shutdown = CancellationToken::new();
+    let handler = FailingHandler {
+        calls: AtomicUsize::new(0),
+        stop_after: 3,
+        shutdown: shutdown.clone(),
+    };
+    let options = ConsumeOptions::new().shutdown(shutdown).dead_letter(
+        DeadLetterQueue::new(cluster.producer(), DLQ_TOPIC)
+            .max_retries(2)
+            .retry_delay(Duration::from_millis(10)),
+    );
+    let consumer = cluster.consumer("dlq-handler");
+    consumer.subscribe(&[TOPIC]).expect("subscribe");
+    tokio::time::timeout(
+        Duration::from_secs(60),
+        consume_messages(&consumer, &handler, &options),
+    )
+    .await
+    .expect("consume loop timed out")
+    .expect("consume loop failed");
+
+    assert_eq!(handler.calls.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        cluster.committed_offset("dlq-handler", TOPIC, 0),
+        Offset::Offset(1)
+    );
+    let dead_letter = cluster.recv(DLQ_TOPIC, "dlq-reader").await;
+    assert_eq!(dead_letter.key(), Some(&b"user-1"[..]));
+    assert_eq!(dead_letter.payload(), Some(&b"login"[..]));
+    assert_eq!(header(&dead_letter, "trace"), "abc");
+    assert_eq!(header(&dead_letter, DLQ_ORIGINAL_TOPIC_HEADER), TOPIC);
+    assert_eq!(header(&dead_letter, DLQ_ORIGINAL_OFFSET_HEADER), "0");
+    assert_eq!(header(&dead_letter, DLQ_ATTEMPTS_HEADER), "3");
+    assert!(header(&dead_letter, DLQ_ERROR_HEADER)
+        .contains("downstream unavailable"));
+}
+
+#[tokio::test]
+async fn invalid_utf8_payload_is_dead_lettered_without_retries() {
+    let cluster = TestCluster::new(&[(TOPIC, 1), (DLQ_TOPIC, 1)]);
+    cluster
+        .produce(
+            TOPIC,
+            vec![ProducerRecord::new().payload([0xff, 0xfe]).partition(0)],
+        )
+        .await;
+
+    let shutdown = CancellationToken::new();
+    let options = ConsumeOptions::new()
+        .shutdown(shutdown.clone())
+        .dead_letter(DeadLetterQueue::new(cluster.producer(), DLQ_TOPIC));
+    let consumer = cluster.consumer("dlq-print");
+    consumer.subscribe(&[TOPIC]).expect("subscribe");
+    let consume = consume_and_print(&consumer, &options);
+    tokio::pin!(consume);
+
+    let dead_letter = tokio::select! {
+        result = &mut consume => panic!("consume loop stopped: {result:?}"),
+        dead_letter = cluster.recv(DLQ_TOPIC, "dlq-reader") => dead_letter,
+    };
+    shutdown.cancel();
+    consume.await.expect("consume loop failed");
+
+    assert_eq!(dead_letter.payload(), Some(&[0xff, 0xfe][..]));
+    assert_eq!(header(&dead_letter, DLQ_ATTEMPTS_HEADER), "1");
+    assert!(header(&dead_letter, DLQ_ERROR_HEADER).contains("not utf-8"));
+}
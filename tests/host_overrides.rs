@@ -0,0 +1,129 @@
+mod common;
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use rdkafka::consumer::Consumer;
+use rdkafka::producer::Producer;
+use rdkafka::Message;
+
+use rust_with_kafka_tls::connection_profile::ConnectionProfile;
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::host_overrides::HostOverrides;
+use rust_with_kafka_tls::publish_messages::publish_messages;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+use rust_with_kafka_tls::tls_doctor::Outcome;
+use rust_with_kafka_tls::tls_doctor::TlsDoctor;
+
+use common::tls_proxy::ClientCert;
+use common::tls_proxy::TlsBroker;
+use common::tls_proxy::ADVERTISED_HOST;
+use common::TestCluster;
+
+const TOPIC: &str = "overrides";
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+/// ``host`` on the port of the ``host:port`` ``broker``
+fn renamed(broker: &str, host: &str) -> String {
+    let (_, port) = broker.rsplit_once(':').unwrap();
+    format!("{host}:{port}")
+}
+
+#[test]
+fn overrides_parse_from_config_and_env() {
+    let overrides =
+        HostOverrides::parse("Broker-0.kafka.test=127.0.0.1, b.test=::1")
+            .expect("overrides");
+    assert_eq!(overrides.get("broker-0.KAFKA.test"), Some(LOCALHOST));
+    assert_eq!(overrides.get("b.test"), Some("::1".parse().unwrap()));
+    assert_eq!(overrides.get("c.test"), None);
+    for invalid in ["b.test", "=127.0.0.1", "b.test=minikube"] {
+        assert!(matches!(
+            HostOverrides::parse(invalid),
+            Err(Error::Config(_))
+        ));
+    }
+
+    std::env::set_var(
+        "KAFKA_HOST_OVERRIDES",
+        "a.test=10.0.0.1,b.test=10.0.0.2",
+    );
+    let overrides =
+        HostOverrides::from_env(["b.test=127.0.0.1"]).expect("overrides");
+    assert_eq!(overrides.len(), 2);
+    assert_eq!(overrides.get("a.test"), Some("10.0.0.1".parse().unwrap()));
+    assert_eq!(overrides.get("b.test"), Some(LOCALHOST));
+    std::env::remove_var("KAFKA_HOST_OVERRIDES");
+}
+
+#[tokio::test]
+async fn overridden_hostnames_connect_without_dns() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let tls = KafkaTlsConfig::new(&renamed(
+        &cluster.bootstrap_servers(),
+        ADVERTISED_HOST,
+    ))
+    .profile(ConnectionProfile::Plain)
+    .host_overrides(HostOverrides::new().host(ADVERTISED_HOST, LOCALHOST));
+
+    let producer = tls.create_producer().expect("producer");
+    publish_messages(&producer, TOPIC).await.expect("publish");
+
+    let consumer = tls
+        .set("auto.offset.reset", "earliest")
+        .create_consumer("overrides")
+        .expect("consumer");
+    consumer.subscribe(&[TOPIC]).expect("subscribe");
+    let message =
+        tokio::time::timeout(Duration::from_secs(10), consumer.recv())
+            .await
+            .expect("message before the timeout")
+            .expect("message");
+    assert_eq!(message.payload(), Some(&b"Message 0"[..]));
+}
+
+#[test]
+fn tls_verifies_the_advertised_hostname() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let overrides = HostOverrides::new()
+        .host(ADVERTISED_HOST, LOCALHOST)
+        .host("other.kafka.test", LOCALHOST);
+    let fetch = |host: &str| {
+        let (key, cert) = broker.identity(ClientCert::Valid);
+        let producer = KafkaTlsConfig::new(&renamed(&broker.brokers(), host))
+            .ca_location(broker.ca_path())
+            .key_location(key)
+            .cert_location(cert)
+            .host_overrides(overrides.clone())
+            .create_producer()
+            .expect("producer");
+        producer
+            .client()
+            .fetch_metadata(None, Duration::from_secs(5))
+            .map(|metadata| metadata.topics().len())
+    };
+
+    assert_eq!(fetch(ADVERTISED_HOST).expect("metadata"), 1);
+    // the override still connects but the name is not in the
+    // broker certificate
+    assert!(fetch("other.kafka.test").is_err());
+}
+
+#[test]
+fn doctor_uses_the_overrides() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let (key, cert) = broker.identity(ClientCert::Valid);
+    let tls = KafkaTlsConfig::new(&renamed(&broker.brokers(), ADVERTISED_HOST))
+        .ca_location(broker.ca_path())
+        .key_location(key)
+        .cert_location(cert)
+        .host_overrides(HostOverrides::new().host(ADVERTISED_HOST, LOCALHOST));
+
+    let reports = TlsDoctor::new(&tls).timeout(Duration::from_secs(5)).run();
+    assert!(reports[0].passed(), "{}", reports[0]);
+    assert_eq!(
+        reports[0].outcome("dns"),
+        Some(&Outcome::Pass("127.0.0.1 (host override)".to_string()))
+    );
+}
@@ -0,0 +1,309 @@
+mod common;
+
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use rdkafka::consumer::Consumer;
+use rdkafka::message::Message;
+use rdkafka::message::OwnedMessage;
+use rdkafka::topic_partition_list::Offset;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::commit_policy::CommitPolicy;
+use rust_with_kafka_tls::consume_messages::consume_messages;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::custom_context::LoggingConsumer;
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::error::Result;
+use rust_with_kafka_tls::keyed_workers::worker_index;
+use rust_with_kafka_tls::message_handler::MessageHandler;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+
+use common::TestCluster;
+
+const WORKERS: usize = 4;
+const SLOW_KEY: &str = "slow";
+
+/// records the key and offset of every message, ``slow`` messages
+/// wait for ``release`` and offsets in ``fail`` return an error after
+/// a short delay
+struct KeyRecorder {
+    seen: Mutex<Vec<(String, i64)>>,
+    release: CancellationToken,
+    fail: Vec<i64>,
+}
+
+impl KeyRecorder {
+    fn new(release: &CancellationToken, fail: Vec<i64>) -> Self {
+        KeyRecorder {
+            seen: Mutex::new(Vec::new()),
+            release: release.clone(),
+            fail,
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.seen.lock().unwrap().len()
+    }
+}
+
+#[async_trait]
+impl MessageHandler for KeyRecorder {
+    async fn handle(&self, message: &OwnedMessage) -> Result<()> {
+        let key = String::from_utf8(message.key().unwrap().to_vec()).unwrap();
+        if key == SLOW_KEY {
+            self.release.cancelled().await;
+        }
+        if self.fail.contains(&message.offset()) {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            return Err(Error::Handler("failing on purpose".to_string()));
+        }
+        self.seen.lock().unwrap().push((key, message.offset()));
+        Ok(())
+    }
+}
+
+/// two keys that never share a worker with the slow key
+fn fast_keys() -> Vec<String> {
+    let slow = worker_index(Some(SLOW_KEY.as_bytes()), 0, WORKERS);
+    (0..)
+        .map(|i| format!("user-{i}"))
+        .filter(|key| worker_index(Some(key.as_bytes()), 0, WORKERS) != slow)
+        .take(2)
+        .collect()
+}
+
+async fn wait_for<F: Fn() -> bool>(done: F) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !done() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    done()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keys_run_concurrently_and_commits_wait_for_earlier_offsets() {
+    let topic = "user.events";
+    let group_id = "keyed-group";
+    let cluster = TestCluster::new(&[(topic, 1)]);
+    let fast = fast_keys();
+    let mut records = vec![ProducerRecord::new().key(SLOW_KEY).payload("0")];
+    for i in 1..=10 {
+        let key = &fast[i % 2];
+        records.push(ProducerRecord::new().key(key).payload(i.to_string()));
+    }
+    cluster.produce(topic, records).await;
+
+    let release = CancellationToken::new();
+    let handler = KeyRecorder::new(&release, Vec::new());
+    let shutdown = CancellationToken::new();
+    let consumer = cluster.consumer(group_id);
+    consumer.subscribe(&[topic]).expect("subscribe");
+    let options = ConsumeOptions::new()
+        .key_workers(WORKERS)
+        .shutdown(shutdown.clone());
+    let consumed = consume_messages(&consumer, &handler, &options);
+    let checks = async {
+        // the fast keys finish while offset 0 is still in flight
+        assert!(wait_for(|| handler.count() == 10).await);
+        assert_eq!(
+            cluster.committed_offset(group_id, topic, 0),
+            Offset::Invalid
+        );
+        release.cancel();
+        assert!(wait_for(|| handler.count() == 11).await);
+        shutdown.cancel();
+    };
+    let (consumed, _) = tokio::join!(consumed, checks);
+    consumed.expect("consume");
+
+    let seen = handler.seen.lock().unwrap().clone();
+    assert_eq!(seen.last().unwrap(), &(SLOW_KEY.to_string(), 0));
+    for key in &fast {
+        let offsets: Vec<i64> = seen
+            .iter()
+            .filter(|(seen_key, _)| seen_key == key)
+            .map(|(_, offset)| *offset)
+            .collect();
+        assert_eq!(offsets.len(), 5);
+        assert!(offsets.windows(2).all(|w| w[0] < w[1]), "{offsets:?}");
+    }
+    assert_eq!(
+        cluster.committed_offset(group_id, topic, 0),
+        Offset::Offset(11)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_offset_is_never_committed_after_handler_success() {
+    let topic = "user.failures";
+    let group_id = "keyed-failures";
+    let cluster = TestCluster::new(&[(topic, 1)]);
+    let records = (0..6)
+        .map(|i| {
+            ProducerRecord::new()
+                .key(format!("user-{i}"))
+                .payload(i.to_string())
+        })
+        .collect();
+    cluster.produce(topic, records).await;
+
+    let release = CancellationToken::new();
+    let handler = KeyRecorder::new(&release, vec![1]);
+    let consumer = cluster.consumer(group_id);
+    consumer.subscribe(&[topic]).expect("subscribe");
+    let options = ConsumeOptions::new()
+        .key_workers(WORKERS)
+        .commit_policy(CommitPolicy::AfterHandlerSuccess);
+    let consumed = tokio::time::timeout(
+        Duration::from_secs(30),
+        consume_messages(&consumer, &handler, &options),
+    )
+    .await
+    .expect("loop stops on the failure");
+
+    assert!(matches!(consumed, Err(Error::Handler(_))), "{consumed:?}");
+    // later offsets were done before offset 1 failed
+    assert!(handler.count() > 1);
+    assert_eq!(
+        cluster.committed_offset(group_id, topic, 0),
+        Offset::Offset(1)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_offset_holds_the_partition_commits() {
+    let topic = "user.held";
+    let group_id = "keyed-held";
+    let cluster = TestCluster::new(&[(topic, 1)]);
+    let records = (0..6)
+        .map(|i| {
+            ProducerRecord::new()
+                .key(format!("user-{i}"))
+                .payload(i.to_string())
+        })
+        .collect();
+    cluster.produce(topic, records).await;
+
+    let release = CancellationToken::new();
+    let handler = KeyRecorder::new(&release, vec![1]);
+    let consumer = cluster.consumer(group_id);
+    consumer.subscribe(&[topic]).expect("subscribe");
+    let options = ConsumeOptions::new()
+        .key_workers(WORKERS)
+        .commit_policy(CommitPolicy::PerMessageSync)
+        .max_messages(6);
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        consume_messages(&consumer, &handler, &options),
+    )
+    .await
+    .expect("loop stops after max_messages")
+    .expect("consume");
+
+    // the loop keeps going, the later offsets never commit past 1
+    assert_eq!(handler.count(), 5);
+    assert_eq!(
+        cluster.committed_offset(group_id, topic, 0),
+        Offset::Offset(1)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_skips_the_partitions_revoked_from_the_workers() {
+    let topic = "user.revoked";
+    let group_id = "keyed-revoked";
+    let cluster = TestCluster::new(&[(topic, 2)]);
+    let produce = |count: usize| {
+        let records = (0..2)
+            .flat_map(|partition| {
+                (0..count).map(move |i| {
+                    ProducerRecord::new()
+                        .key(format!("user-{i}"))
+                        .payload(i.to_string())
+                        .partition(partition)
+                })
+            })
+            .collect();
+        cluster.produce(topic, records)
+    };
+    produce(2).await;
+
+    // the mock coordinator waits up to a session timeout for the
+    // members to rejoin
+    let settings = [
+        ("enable.auto.commit", "false"),
+        ("session.timeout.ms", "6000"),
+        ("heartbeat.interval.ms", "500"),
+    ];
+    let first = cluster.consumer_with(group_id, &settings);
+    let second = cluster.consumer_with(group_id, &settings);
+    first.subscribe(&[topic]).expect("subscribe");
+    let release = CancellationToken::new();
+    let (first_handler, second_handler) = (
+        KeyRecorder::new(&release, Vec::new()),
+        KeyRecorder::new(&release, Vec::new()),
+    );
+    let (first_stop, second_stop) =
+        (CancellationToken::new(), CancellationToken::new());
+    let options = |stop: &CancellationToken| {
+        ConsumeOptions::new()
+            .key_workers(WORKERS)
+            .commit_policy(CommitPolicy::PerMessageSync)
+            .shutdown(stop.clone())
+    };
+    let (first_options, second_options) =
+        (options(&first_stop), options(&second_stop));
+    let first_done = CancellationToken::new();
+    let first_loop = async {
+        let consumed =
+            consume_messages(&first, &first_handler, &first_options).await;
+        first_done.cancel();
+        consumed
+    };
+    let checks = async {
+        assert!(wait_for(|| first_handler.count() == 4).await);
+        second.subscribe(&[topic]).expect("subscribe");
+        let assigned = |consumer: &LoggingConsumer| {
+            consumer.assignment().map(|a| a.count()).unwrap_or(0)
+        };
+        let second_loop =
+            consume_messages(&second, &second_handler, &second_options);
+        let rebalanced = async {
+            assert!(
+                wait_for(|| assigned(&first) == 1 && assigned(&second) == 1)
+                    .await
+            );
+            produce(4).await;
+            assert!(
+                wait_for(|| {
+                    first_handler.count() + second_handler.count() == 12
+                })
+                .await
+            );
+            // the first consumer handled both partitions before the
+            // rebalance and must not rewind the one it lost
+            first_stop.cancel();
+            first_done.cancelled().await;
+            let committed: Vec<Offset> = (0..2)
+                .map(|p| cluster.committed_offset(group_id, topic, p))
+                .collect();
+            second_stop.cancel();
+            committed
+        };
+        let (second_consumed, committed) =
+            tokio::join!(second_loop, rebalanced);
+        second_consumed.expect("second consumer");
+        committed
+    };
+    let (first_consumed, committed) =
+        tokio::time::timeout(Duration::from_secs(60), async {
+            tokio::join!(first_loop, checks)
+        })
+        .await
+        .expect("both loops stop");
+    first_consumed.expect("first consumer");
+    assert_eq!(committed, vec![Offset::Offset(6), Offset::Offset(6)]);
+}
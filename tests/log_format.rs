@@ -0,0 +1,156 @@
+use log::kv::Value;
+use log::Level;
+use log::Record;
+
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::log_utils::format_record;
+use rust_with_kafka_tls::log_utils::LogFormat;
+
+/// format a consumed message record the way ``consume_and_print``
+/// logs it
+fn message_line(format: LogFormat) -> String {
+    std::thread::Builder::new()
+        .name("consumer-0".to_string())
+        .spawn(move || {
+            let fields: [(&str, Value); 4] = [
+                ("topic", Value::from("testing")),
+                ("partition", Value::from(2)),
+                ("offset", Value::from(42i64)),
+                ("key", Value::from("user 1")),
+            ];
+            format_record(
+                format,
+                true,
+                &Record::builder()
+                    .args(format_args!("payload=\"login\"\n"))
+                    .level(Level::Info)
+                    .target("rust_with_kafka_tls::consume_and_print")
+                    .module_path(Some("rust_with_kafka_tls::consume_and_print"))
+                    .file(Some("src/consume_and_print.rs"))
+                    .line(Some(136))
+                    .key_values(&fields)
+                    .build(),
+            )
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// ``true`` for an RFC3339 timestamp with date, millis and timezone
+fn is_rfc3339(time: &str) -> bool {
+    chrono::DateTime::parse_from_rfc3339(time).is_ok()
+        && time.len() == "2022-09-01T12:00:00.000+00:00".len()
+}
+
+#[test]
+fn json_lines_carry_location_and_message_fields() {
+    let line = message_line(LogFormat::Json);
+    assert!(line.starts_with("{\"time\":\""), "{line}");
+    assert!(line.ends_with('}'), "{line}");
+    assert!(!line.contains('\n'), "{line}");
+    let time = &line["{\"time\":\"".len()..];
+    assert!(is_rfc3339(&time[..time.find('"').unwrap()]), "{line}");
+    assert!(line.contains(
+        "\"level\":\"INFO\",\"thread\":\"consumer-0\",\
+        \"target\":\"rust_with_kafka_tls::consume_and_print\",\
+        \"module\":\"rust_with_kafka_tls::consume_and_print\",\
+        \"file\":\"src/consume_and_print.rs\",\"line\":136,\
+        \"message\":\"payload=\\\"login\\\"\\n\",\
+        \"fields\":{\"key\":\"user 1\",\"offset\":42,\
+        \"partition\":2,\"topic\":\"testing\"}}"
+    ));
+}
+
+/// format a record whose fields are named like the line keys
+fn clashing_line(format: LogFormat) -> String {
+    let fields: [(&str, Value); 3] = [
+        ("message", Value::from("from the handler")),
+        ("level", Value::from(7)),
+        ("time", Value::from(true)),
+    ];
+    format_record(
+        format,
+        false,
+        &Record::builder()
+            .args(format_args!("handled"))
+            .level(Level::Warn)
+            .target("handler")
+            .key_values(&fields)
+            .build(),
+    )
+}
+
+#[test]
+fn json_fields_never_replace_the_line_keys() {
+    let line = clashing_line(LogFormat::Json);
+    let json: serde_json::Value = serde_json::from_str(&line).expect(&line);
+    let keys = json.as_object().unwrap().keys().collect::<Vec<_>>();
+    assert_eq!(keys, ["fields", "level", "message", "target", "time"]);
+    // once on the line and once in the fields
+    for key in ["message", "level", "time"] {
+        assert_eq!(line.matches(&format!("\"{key}\":")).count(), 2, "{line}");
+    }
+    assert_eq!(json["message"], "handled");
+    assert_eq!(json["level"], "WARN");
+    assert!(json["time"].is_string(), "{line}");
+    assert_eq!(
+        json["fields"],
+        serde_json::json!({
+            "message": "from the handler",
+            "level": 7,
+            "time": true,
+        })
+    );
+}
+
+#[test]
+fn logfmt_prefixes_fields_named_like_line_keys() {
+    let line = clashing_line(LogFormat::Logfmt);
+    let (_, rest) = line.split_once(' ').unwrap();
+    assert_eq!(
+        rest,
+        "level=WARN target=handler msg=handled \
+        message=\"from the handler\" fields.level=7 \
+        fields.time=true"
+    );
+}
+
+#[test]
+fn logfmt_quotes_values_with_spaces() {
+    let line = message_line(LogFormat::Logfmt);
+    let (time, rest) = line.split_once(' ').unwrap();
+    assert!(is_rfc3339(time.strip_prefix("time=").unwrap()), "{line}");
+    assert_eq!(
+        rest,
+        "level=INFO thread=consumer-0 \
+        target=rust_with_kafka_tls::consume_and_print \
+        module=rust_with_kafka_tls::consume_and_print \
+        file=src/consume_and_print.rs line=136 \
+        msg=\"payload=\\\"login\\\"\\n\" \
+        topic=testing partition=2 offset=42 key=\"user 1\""
+    );
+}
+
+#[test]
+fn text_keeps_the_readable_layout() {
+    let line = message_line(LogFormat::Text);
+    let (time, rest) = line.split_once(' ').unwrap();
+    assert!(is_rfc3339(time), "{line}");
+    assert_eq!(
+        rest,
+        "(t: consumer-0) INFO - rust_with_kafka_tls::consume_and_print - \
+        payload=\"login\"\n topic=testing partition=2 offset=42 \
+        key=\"user 1\""
+    );
+}
+
+#[test]
+fn format_names() {
+    for format in [LogFormat::Text, LogFormat::Json, LogFormat::Logfmt] {
+        assert_eq!(format.name().parse::<LogFormat>().unwrap(), format);
+        assert_eq!(format.to_string(), format.name());
+    }
+    assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+    assert!(matches!("xml".parse::<LogFormat>(), Err(Error::Config(_))));
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use rdkafka::consumer::CommitMode;
+use rdkafka::consumer::Consumer;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::connection_profile::ConnectionProfile;
+use rust_with_kafka_tls::consume_messages::consume_messages;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::metrics::Metrics;
+use rust_with_kafka_tls::publish_messages::publish_messages_with_metrics;
+use rust_with_kafka_tls::publish_options::PublishOptions;
+use rust_with_kafka_tls::publish_records::publish_records;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+
+use common::CollectingHandler;
+use common::TestCluster;
+
+/// ``GET path`` against the metrics endpoint, returns the response
+async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.expect("connect");
+    stream
+        .write_all(
+            format!("GET {path} HTTP/1.1\r\nHost: test\r\n\r\n").as_bytes(),
+        )
+        .await
+        .expect("request");
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .await
+        .expect("response");
+    response
+}
+
+#[test]
+fn render_uses_the_text_exposition_format() {
+    let metrics = Metrics::new();
+    assert_eq!(metrics.render(), "");
+
+    metrics.message_consumed("orders", 10);
+    metrics.message_consumed("orders", 5);
+    metrics.delivery_failed("quote\"topic");
+    metrics.commit_failed();
+    let text = metrics.render();
+
+    assert!(text.contains(
+        "# HELP kafka_messages_consumed_total Messages received by the \
+        consume loop\n\
+        # TYPE kafka_messages_consumed_total counter\n\
+        kafka_messages_consumed_total{topic=\"orders\"} 2\n"
+    ));
+    assert!(text.contains("kafka_consumed_bytes_total{topic=\"orders\"} 15\n"));
+    assert!(text.contains(
+        "kafka_delivery_failures_total{topic=\"quote\\\"topic\"} 1\n"
+    ));
+    assert!(text.contains("kafka_commit_failures_total 1\n"));
+    assert!(!text.contains("kafka_messages_produced_total"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publish_and_consume_paths_feed_the_metrics() {
+    let cluster = TestCluster::new(&[("metrics", 1)]);
+    let metrics = Arc::new(Metrics::new());
+    let tls = KafkaTlsConfig::new(&cluster.bootstrap_servers())
+        .profile(ConnectionProfile::Plain)
+        .metrics(metrics.clone())
+        .set("statistics.interval.ms", "100")
+        .set("auto.offset.reset", "earliest");
+
+    let producer = tls.create_producer().expect("producer");
+    let options = PublishOptions::new().metrics(metrics.clone());
+    let records = vec![
+        ProducerRecord::new().key("k1").payload("one"),
+        ProducerRecord::new().key("k2").payload("two"),
+        ProducerRecord::new().payload("three"),
+        // the topic has no partition 5
+        ProducerRecord::new().payload("lost").partition(5),
+    ];
+    let reports =
+        publish_records(&producer, "metrics", records, &options).await;
+    assert!(reports[3].is_err());
+
+    let shutdown = CancellationToken::new();
+    let handler = CollectingHandler::new(3, &shutdown);
+    let consumer = tls.create_consumer("metrics-group").expect("consumer");
+    consumer.subscribe(&["metrics"]).expect("subscribe");
+    consume_messages(
+        &consumer,
+        &handler,
+        &ConsumeOptions::new().shutdown(shutdown),
+    )
+    .await
+    .expect("consume");
+
+    let text = metrics.render();
+    for expected in [
+        "kafka_messages_produced_total{topic=\"metrics\"} 3\n",
+        "kafka_produced_bytes_total{topic=\"metrics\"} 15\n",
+        "kafka_delivery_failures_total{topic=\"metrics\"} 1\n",
+        "kafka_messages_consumed_total{topic=\"metrics\"} 3\n",
+        "kafka_consumed_bytes_total{topic=\"metrics\"} 15\n",
+        "kafka_rebalances_total{kind=\"assign\"} 1\n",
+        "kafka_client_queue_messages{client=\"",
+        "kafka_broker_rtt_avg_seconds{client=\"",
+    ] {
+        assert!(text.contains(expected), "{expected} missing from\n{text}");
+    }
+
+    // a second group commits its first message, the lag counts the
+    // two messages after the committed offset
+    let lagging = tls.create_consumer("metrics-lag").expect("consumer");
+    lagging.subscribe(&["metrics"]).expect("subscribe");
+    let lagged = || {
+        metrics.render().lines().any(|line| {
+            line.starts_with("kafka_consumer_lag{")
+                && line.ends_with(",topic=\"metrics\",partition=\"0\"} 2")
+        })
+    };
+    let mut committed = false;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !lagged() && Instant::now() < deadline {
+        let received =
+            tokio::time::timeout(Duration::from_millis(100), lagging.recv())
+                .await;
+        if let Ok(Ok(m)) = received {
+            if !committed {
+                lagging
+                    .commit_message(&m, CommitMode::Sync)
+                    .expect("commit");
+                committed = true;
+            }
+        }
+    }
+    assert!(lagged(), "{}", metrics.render());
+}
+
+#[tokio::test]
+async fn serve_answers_get_metrics() {
+    let metrics = Arc::new(Metrics::new());
+    metrics.message_produced("served", 3);
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let shutdown = CancellationToken::new();
+    let server =
+        tokio::spawn(metrics.clone().serve(listener, shutdown.clone()));
+
+    let response = get(addr, "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+    assert!(response.contains("\r\n\r\n# HELP kafka_messages_produced_total "));
+    assert!(response
+        .contains("kafka_messages_produced_total{topic=\"served\"} 1\n"));
+
+    let response = get(addr, "/").await;
+    assert!(
+        response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+        "{response}"
+    );
+
+    // a client that never sends its request is disconnected
+    let mut silent = TcpStream::connect(addr).await.expect("connect");
+    let mut response = Vec::new();
+    let read = tokio::time::timeout(
+        Duration::from_secs(15),
+        silent.read_to_end(&mut response),
+    )
+    .await
+    .expect("silent client disconnected");
+    assert!(response.is_empty(), "{read:?}");
+
+    shutdown.cancel();
+    tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .expect("server stopped")
+        .expect("server task");
+}
+
+#[tokio::test]
+async fn publish_messages_feed_the_metrics() {
+    let cluster = TestCluster::new(&[("metrics.demo", 1)]);
+    let metrics = Arc::new(Metrics::new());
+    publish_messages_with_metrics(
+        &cluster.producer(),
+        "metrics.demo",
+        Some(metrics.clone()),
+    )
+    .await
+    .expect("publish messages");
+
+    let text = metrics.render();
+    assert!(
+        text.contains(
+            "kafka_messages_produced_total{topic=\"metrics.demo\"} 5\n"
+        ),
+        "{text}"
+    );
+    assert!(!text.contains("kafka_delivery_failures_total"), "{text}");
+}
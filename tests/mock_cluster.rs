@@ -0,0 +1,150 @@
+mod common;
+
+use std::time::Duration;
+
+use rdkafka::consumer::Consumer;
+use rdkafka::message::Message;
+use rdkafka::topic_partition_list::Offset;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::commit_policy::CommitPolicy;
+use rust_with_kafka_tls::consume_and_print::consume_and_print;
+use rust_with_kafka_tls::consume_messages::consume_messages;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::publish_messages::publish_messages;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+
+use common::header;
+use common::CollectingHandler;
+use common::TestCluster;
+
+const TOPIC: &str = "testing";
+
+#[tokio::test]
+async fn publish_messages_round_trip() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    publish_messages(&cluster.producer(), TOPIC)
+        .await
+        .expect("publish messages");
+
+    let shutdown = CancellationToken::new();
+    let handler = CollectingHandler::new(5, &shutdown);
+    let options = ConsumeOptions::new()
+        .shutdown(shutdown)
+        .commit_policy(CommitPolicy::PerMessageSync);
+    let consumer = cluster.consumer("round-trip");
+    consumer.subscribe(&[TOPIC]).expect("subscribe");
+    tokio::time::timeout(
+        Duration::from_secs(60),
+        consume_messages(&consumer, &handler, &options),
+    )
+    .await
+    .expect("consume loop timed out")
+    .expect("consume loop failed");
+
+    let messages = handler.messages();
+    assert_eq!(messages.len(), 5);
+    for (i, message) in messages.iter().enumerate() {
+        assert_eq!(message.topic(), TOPIC);
+        assert_eq!(message.offset(), i as i64);
+        assert_eq!(message.key(), Some(format!("Key {i}").as_bytes()));
+        assert_eq!(message.payload(), Some(format!("Message {i}").as_bytes()));
+        assert_eq!(header(message, "header_key"), "header_value");
+    }
+    assert_eq!(
+        cluster.committed_offset("round-trip", TOPIC, 0),
+        Offset::Offset(5)
+    );
+}
+
+#[tokio::test]
+async fn consume_and_print_commits_printed_messages() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let records = (0..3)
+        .map(|i| {
+            ProducerRecord::new()
+                .key(format!("key-{i}"))
+                .payload(format!("payload-{i}"))
+                .header("index", i.to_string())
+        })
+        .collect();
+    cluster.produce(TOPIC, records).await;
+
+    let shutdown = CancellationToken::new();
+    let options = ConsumeOptions::new()
+        .shutdown(shutdown.clone())
+        .commit_policy(CommitPolicy::PerMessageSync);
+    let consumer = cluster.consumer("printer");
+    consumer.subscribe(&[TOPIC]).expect("subscribe");
+    let consume = consume_and_print(&consumer, &options);
+    tokio::pin!(consume);
+
+    let committed = async {
+        loop {
+            let offset = cluster.committed_offset("printer", TOPIC, 0);
+            if offset == Offset::Offset(3) {
+                return offset;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    let committed = tokio::time::timeout(Duration::from_secs(60), async {
+        tokio::select! {
+            result = &mut consume => panic!("consume loop stopped: {result:?}"),
+            committed = committed => committed,
+        }
+    })
+    .await
+    .expect("printed messages were not committed");
+    shutdown.cancel();
+    consume.await.expect("consume loop failed");
+    assert_eq!(committed, Offset::Offset(3));
+}
+
+#[tokio::test]
+async fn keyed_records_commit_per_partition() {
+    let cluster = TestCluster::new(&[(TOPIC, 3)]);
+    let records = (0..9)
+        .map(|i| {
+            ProducerRecord::new()
+                .key(format!("key-{i}"))
+                .payload(format!("payload-{i}"))
+                .partition(i % 3)
+        })
+        .collect();
+    cluster.produce(TOPIC, records).await;
+
+    let policy = CommitPolicy::Periodic(Duration::from_millis(100));
+    let shutdown = CancellationToken::new();
+    let handler = CollectingHandler::new(9, &shutdown);
+    let options = ConsumeOptions::new()
+        .shutdown(shutdown)
+        .commit_policy(policy);
+    let consumer =
+        cluster.consumer_with("partitions", &policy.consumer_settings());
+    consumer.subscribe(&[TOPIC]).expect("subscribe");
+    tokio::time::timeout(
+        Duration::from_secs(60),
+        consume_messages(&consumer, &handler, &options),
+    )
+    .await
+    .expect("consume loop timed out")
+    .expect("consume loop failed");
+
+    let messages = handler.messages();
+    assert_eq!(messages.len(), 9);
+    for message in &messages {
+        let key = std::str::from_utf8(message.key().unwrap()).unwrap();
+        let i: i32 = key.trim_start_matches("key-").parse().unwrap();
+        assert_eq!(message.partition(), i % 3, "{key}");
+        assert_eq!(message.offset(), i64::from(i / 3), "{key}");
+        assert_eq!(message.payload(), Some(format!("payload-{i}").as_bytes()));
+    }
+    for partition in 0..3 {
+        assert_eq!(
+            cluster.committed_offset("partitions", TOPIC, partition),
+            Offset::Offset(3),
+            "partition {partition}"
+        );
+    }
+}
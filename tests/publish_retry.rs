@@ -0,0 +1,210 @@
+mod common;
+
+use std::time::Duration;
+use std::time::Instant;
+
+use rdkafka::error::KafkaError;
+use rdkafka::error::RDKafkaErrorCode;
+use rdkafka::types::RDKafkaApiKey;
+use rdkafka::types::RDKafkaRespErr;
+
+use rust_with_kafka_tls::custom_context::LoggingProducer;
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::publish_options::PublishOptions;
+use rust_with_kafka_tls::publish_records::publish_record;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+use rust_with_kafka_tls::retry_policy::RetryPolicy;
+
+use common::TestCluster;
+
+const TOPIC: &str = "retries";
+
+/// producer that does not retry a failed produce internally, so each
+/// failure reaches the caller, and whose messages time out quickly
+fn producer(cluster: &TestCluster) -> LoggingProducer {
+    cluster.producer_with(&[
+        ("message.timeout.ms", "2000"),
+        ("retries", "0"),
+        ("retry.backoff.ms", "5000"),
+        ("retry.backoff.max.ms", "5000"),
+    ])
+}
+
+fn record() -> ProducerRecord {
+    ProducerRecord::new()
+        .key("key")
+        .payload("payload")
+        .partition(0)
+}
+
+fn retry_options(deadline: Duration) -> PublishOptions {
+    PublishOptions::new().retry(
+        RetryPolicy::new()
+            .initial_interval(Duration::from_millis(10))
+            .max_interval(Duration::from_millis(50))
+            .deadline(deadline),
+    )
+}
+
+#[tokio::test]
+async fn retriable_errors_are_retried_until_delivered() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let producer = producer(&cluster);
+    cluster.mock().request_errors(
+        RDKafkaApiKey::Produce,
+        &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_NOT_ENOUGH_REPLICAS; 3],
+    );
+
+    let report = publish_record(
+        &producer,
+        TOPIC,
+        &record(),
+        &retry_options(Duration::from_secs(30)),
+    )
+    .await
+    .expect("delivered after retries");
+    assert_eq!(report.partition, 0);
+    assert_eq!(report.offset, 0);
+}
+
+#[tokio::test]
+async fn retriable_errors_fail_without_a_retry_policy() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let producer = producer(&cluster);
+    cluster.mock().request_errors(
+        RDKafkaApiKey::Produce,
+        &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_NOT_ENOUGH_REPLICAS],
+    );
+
+    let error =
+        publish_record(&producer, TOPIC, &record(), &PublishOptions::new())
+            .await
+            .expect_err("no retries");
+    assert!(error.is_retriable(), "{error}");
+}
+
+#[tokio::test]
+async fn fatal_errors_are_not_retried() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let producer = producer(&cluster);
+    // a retry would succeed because only the first produce fails
+    cluster.mock().request_errors(
+        RDKafkaApiKey::Produce,
+        &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_TOPIC_AUTHORIZATION_FAILED],
+    );
+
+    let error = publish_record(
+        &producer,
+        TOPIC,
+        &record(),
+        &retry_options(Duration::from_secs(30)),
+    )
+    .await
+    .expect_err("fatal error");
+    assert!(matches!(error, Error::Delivery(_)), "{error}");
+    assert!(!error.is_retriable());
+}
+
+#[tokio::test]
+async fn retries_stop_at_the_deadline() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let producer = producer(&cluster);
+    cluster.mock().request_errors(
+        RDKafkaApiKey::Produce,
+        &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_NOT_ENOUGH_REPLICAS; 1000],
+    );
+
+    let started = Instant::now();
+    let error = publish_record(
+        &producer,
+        TOPIC,
+        &record(),
+        &retry_options(Duration::from_millis(500)),
+    )
+    .await
+    .expect_err("deadline exceeded");
+    // the last retriable error, or a timeout when an attempt was still
+    // waiting at the deadline
+    assert!(
+        error.is_retriable() || matches!(error, Error::Timeout(_)),
+        "{error}"
+    );
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn timed_out_deliveries_are_not_resent() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let producer = producer(&cluster);
+    // librdkafka retries a lost leader internally until the message
+    // times out, so the broker may have written it
+    cluster.mock().request_errors(
+        RDKafkaApiKey::Produce,
+        &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_NOT_LEADER_FOR_PARTITION; 1000],
+    );
+
+    let error = publish_record(
+        &producer,
+        TOPIC,
+        &record(),
+        &retry_options(Duration::from_secs(30)),
+    )
+    .await
+    .expect_err("timed out");
+    assert!(matches!(error, Error::Timeout(_)), "{error}");
+    assert!(!error.is_retriable());
+}
+
+#[tokio::test]
+async fn attempts_do_not_overrun_the_deadline() {
+    let cluster = TestCluster::new(&[(TOPIC, 1)]);
+    let producer = cluster.producer_with(&[("message.timeout.ms", "30000")]);
+    cluster.mock().request_errors(
+        RDKafkaApiKey::Produce,
+        &[RDKafkaRespErr::RD_KAFKA_RESP_ERR_NOT_LEADER_FOR_PARTITION; 1000],
+    );
+
+    let started = Instant::now();
+    let error = publish_record(
+        &producer,
+        TOPIC,
+        &record(),
+        &retry_options(Duration::from_millis(500)),
+    )
+    .await
+    .expect_err("deadline exceeded");
+    assert!(matches!(error, Error::Timeout(_)), "{error}");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn classifies_retriable_kafka_errors() {
+    let retriable = [
+        RDKafkaErrorCode::QueueFull,
+        RDKafkaErrorCode::LeaderNotAvailable,
+        RDKafkaErrorCode::NotLeaderForPartition,
+        RDKafkaErrorCode::NotEnoughReplicas,
+    ];
+    for code in retriable {
+        let error = Error::delivery(KafkaError::MessageProduction(code));
+        assert!(error.is_retriable(), "{code:?}");
+    }
+    let fatal = [
+        RDKafkaErrorCode::MessageSizeTooLarge,
+        RDKafkaErrorCode::TopicAuthorizationFailed,
+        RDKafkaErrorCode::UnknownTopicOrPartition,
+        // the broker may have written the message
+        RDKafkaErrorCode::MessageTimedOut,
+        RDKafkaErrorCode::RequestTimedOut,
+        RDKafkaErrorCode::BrokerTransportFailure,
+        RDKafkaErrorCode::NotEnoughReplicasAfterAppend,
+    ];
+    for code in fatal {
+        let error = Error::delivery(KafkaError::MessageProduction(code));
+        assert!(!error.is_retriable(), "{code:?}");
+    }
+    let transport =
+        KafkaError::MetadataFetch(RDKafkaErrorCode::BrokerTransportFailure);
+    assert!(Error::Kafka(transport).is_retriable());
+    assert!(!Error::Config("bad".to_string()).is_retriable());
+}
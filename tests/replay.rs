@@ -0,0 +1,247 @@
+mod common;
+
+use std::time::Duration;
+
+use chrono::DateTime;
+use chrono::Utc;
+use rdkafka::message::Message;
+use rdkafka::topic_partition_list::Offset;
+use tokio_util::sync::CancellationToken;
+
+use rust_with_kafka_tls::consume_messages::consume_messages;
+use rust_with_kafka_tls::consume_options::ConsumeOptions;
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::publish_records::ProducerRecord;
+use rust_with_kafka_tls::replay::assign_from;
+use rust_with_kafka_tls::replay::StartPosition;
+
+use common::CollectingHandler;
+use common::TestCluster;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ``count`` records for ``partition``
+async fn produce(
+    cluster: &TestCluster,
+    topic: &str,
+    partition: i32,
+    count: usize,
+) {
+    let records = (0..count)
+        .map(|i| {
+            ProducerRecord::new()
+                .key(format!("key-{i}"))
+                .payload(format!("message {i}"))
+                .partition(partition)
+        })
+        .collect();
+    cluster.produce(topic, records).await;
+}
+
+/// partition and offset of every message handled by a loop that
+/// starts at ``position`` and stops after ``max_messages``
+async fn replay(
+    cluster: &TestCluster,
+    topic: &str,
+    group_id: &str,
+    position: &StartPosition,
+    max_messages: usize,
+) -> Vec<(i32, i64)> {
+    let consumer = cluster.consumer(group_id);
+    assign_from(&consumer, &[topic], position, TIMEOUT).expect("assign");
+    // the handler never reaches its own limit
+    let handler = CollectingHandler::new(usize::MAX, &CancellationToken::new());
+    let options = ConsumeOptions::new().max_messages(max_messages);
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        consume_messages(&consumer, &handler, &options),
+    )
+    .await
+    .expect("loop stops after max_messages")
+    .expect("consume");
+    handler
+        .messages()
+        .iter()
+        .map(|m| (m.partition(), m.offset()))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn beginning_and_explicit_offsets_replay_without_the_group_offsets() {
+    let topic = "replayed";
+    let cluster = TestCluster::new(&[(topic, 2)]);
+    produce(&cluster, topic, 0, 5).await;
+    produce(&cluster, topic, 1, 5).await;
+
+    let mut seen =
+        replay(&cluster, topic, "replay-all", &StartPosition::Beginning, 10)
+            .await;
+    seen.sort();
+    let expected: Vec<(i32, i64)> =
+        (0..2).flat_map(|p| (0..5).map(move |o| (p, o))).collect();
+    assert_eq!(seen, expected);
+    assert_eq!(
+        cluster.committed_offset("replay-all", topic, 1),
+        Offset::Offset(5)
+    );
+
+    // only the listed partition is consumed, from the listed offset,
+    // the only consumed topic needs no name
+    let position = StartPosition::from_partition_offsets(["1:3"]).unwrap();
+    let seen = replay(&cluster, topic, "replay-offset", &position, 2).await;
+    assert_eq!(seen, vec![(1, 3), (1, 4)]);
+    assert_eq!(
+        cluster.committed_offset("replay-offset", topic, 0),
+        Offset::Invalid
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn explicit_offsets_only_apply_to_their_topic() {
+    let (wide, narrow) = ("replayed.wide", "replayed.narrow");
+    let cluster = TestCluster::new(&[(wide, 2), (narrow, 1)]);
+    produce(&cluster, wide, 1, 5).await;
+    produce(&cluster, narrow, 0, 5).await;
+
+    // the narrow topic has no partition 1
+    let consumer = cluster.consumer("replay-topics");
+    let position =
+        StartPosition::from_partition_offsets(["replayed.wide:1:3"]).unwrap();
+    assign_from(&consumer, &[wide, narrow], &position, TIMEOUT)
+        .expect("assign");
+    let handler = CollectingHandler::new(usize::MAX, &CancellationToken::new());
+    let options = ConsumeOptions::new().max_messages(2);
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        consume_messages(&consumer, &handler, &options),
+    )
+    .await
+    .expect("loop stops after max_messages")
+    .expect("consume");
+    let seen: Vec<(String, i32, i64)> = handler
+        .messages()
+        .iter()
+        .map(|m| (m.topic().to_string(), m.partition(), m.offset()))
+        .collect();
+    assert_eq!(
+        seen,
+        vec![(wide.to_string(), 1, 3), (wide.to_string(), 1, 4)]
+    );
+
+    let consumer = cluster.consumer("replay-other-topic");
+    let position =
+        StartPosition::from_partition_offsets(["replayed.other:0:1"]).unwrap();
+    let assigned = assign_from(&consumer, &[wide], &position, TIMEOUT);
+    assert!(matches!(assigned, Err(Error::Config(_))), "{assigned:?}");
+
+    // without a topic the offset is ambiguous for two topics
+    let position = StartPosition::from_partition_offsets(["0:1"]).unwrap();
+    let assigned = assign_from(&consumer, &[wide, narrow], &position, TIMEOUT);
+    assert!(matches!(assigned, Err(Error::Config(_))), "{assigned:?}");
+
+    // both name partition 1 of the only consumed topic
+    let position =
+        StartPosition::from_partition_offsets(["1:1", "replayed.wide:1:2"])
+            .unwrap();
+    let assigned = assign_from(&consumer, &[wide], &position, TIMEOUT);
+    assert!(matches!(assigned, Err(Error::Config(_))), "{assigned:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn end_skips_the_existing_messages() {
+    let topic = "replayed.end";
+    let cluster = TestCluster::new(&[(topic, 1)]);
+    produce(&cluster, topic, 0, 4).await;
+
+    let consumer = cluster.consumer("replay-end");
+    assign_from(&consumer, &[topic], &StartPosition::End, TIMEOUT)
+        .expect("assign");
+    let handler = CollectingHandler::new(usize::MAX, &CancellationToken::new());
+    let options = ConsumeOptions::new().max_messages(1);
+    let consumed = consume_messages(&consumer, &handler, &options);
+    let produced = async {
+        // give the consumer time to resolve the end offset
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        produce(&cluster, topic, 0, 1).await;
+    };
+    let (consumed, _) = tokio::time::timeout(Duration::from_secs(30), async {
+        tokio::join!(consumed, produced)
+    })
+    .await
+    .expect("loop stops after max_messages");
+    consumed.expect("consume");
+    let offsets: Vec<i64> =
+        handler.messages().iter().map(|m| m.offset()).collect();
+    assert_eq!(offsets, vec![4]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_messages_waits_for_the_key_workers() {
+    let topic = "replayed.keyed";
+    let cluster = TestCluster::new(&[(topic, 1)]);
+    produce(&cluster, topic, 0, 10).await;
+
+    let consumer = cluster.consumer("replay-keyed");
+    assign_from(&consumer, &[topic], &StartPosition::Beginning, TIMEOUT)
+        .expect("assign");
+    let handler = CollectingHandler::new(usize::MAX, &CancellationToken::new());
+    let options = ConsumeOptions::new().key_workers(4).max_messages(6);
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        consume_messages(&consumer, &handler, &options),
+    )
+    .await
+    .expect("loop stops after max_messages")
+    .expect("consume");
+
+    let mut offsets: Vec<i64> =
+        handler.messages().iter().map(|m| m.offset()).collect();
+    offsets.sort();
+    assert_eq!(offsets, (0..6).collect::<Vec<i64>>());
+    assert_eq!(
+        cluster.committed_offset("replay-keyed", topic, 0),
+        Offset::Offset(6)
+    );
+}
+
+// the mock cluster answers every timestamp lookup with the end offset,
+// so only the parsing of --from-timestamp is checked here
+#[test]
+fn start_positions_parse_timestamps_and_partition_offsets() {
+    let position =
+        StartPosition::from_rfc3339("2026-10-17T10:30:02.500+02:00").unwrap();
+    let expected = DateTime::parse_from_rfc3339("2026-10-17T08:30:02.500Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    assert_eq!(position, StartPosition::Timestamp(expected));
+    assert!(matches!(
+        StartPosition::from_rfc3339("yesterday"),
+        Err(Error::Config(_))
+    ));
+
+    let position = StartPosition::from_partition_offsets([
+        " a:0:7", "a:2: 0", "b:0:1", "3:4",
+    ]);
+    assert_eq!(
+        position.unwrap(),
+        StartPosition::Offsets(vec![
+            (Some("a".to_string()), 0, 7),
+            (Some("a".to_string()), 2, 0),
+            (Some("b".to_string()), 0, 1),
+            (None, 3, 4),
+        ])
+    );
+    for offsets in [
+        &["1"][..],
+        &["a:1"],
+        &[":0:1"],
+        &["a:0:1:2"],
+        &["0:1", "0:2"],
+        &["a:b:1"],
+        &["a:1:-5"],
+        &["a:0:1", "a:0:2"],
+    ] {
+        let parsed = StartPosition::from_partition_offsets(offsets.to_vec());
+        assert!(matches!(parsed, Err(Error::Config(_))), "{offsets:?}");
+    }
+}
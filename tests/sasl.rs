@@ -0,0 +1,129 @@
+mod common;
+
+use std::fs;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use rdkafka::client::OAuthToken;
+
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::sasl::Authentication;
+use rust_with_kafka_tls::sasl::OAuthTokenProvider;
+use rust_with_kafka_tls::sasl::ScramMechanism;
+use rust_with_kafka_tls::sasl::TokenFileProvider;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+
+use common::TestCluster;
+
+const CA: &str = "./kubernetes/tls/ca.pem";
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+#[test]
+fn scram_uses_sasl_ssl_without_client_certificates() {
+    let tls = KafkaTlsConfig::new("localhost:9094")
+        .ca_location(CA)
+        .key_location("./missing/client-key.pem")
+        .cert_location("./missing/client.pem")
+        .scram(ScramMechanism::Sha512, "rust-client", "secret");
+    let config = tls.client_config().expect("client config");
+
+    assert_eq!(config.get("security.protocol"), Some("SASL_SSL"));
+    assert_eq!(config.get("sasl.mechanism"), Some("SCRAM-SHA-512"));
+    assert_eq!(config.get("sasl.username"), Some("rust-client"));
+    assert_eq!(config.get("sasl.password"), Some("secret"));
+    assert_eq!(config.get("ssl.ca.location"), Some(CA));
+    assert_eq!(config.get("ssl.key.location"), None);
+    assert_eq!(config.get("ssl.certificate.location"), None);
+    assert!(!format!("{tls:?}").contains("secret"));
+
+    // the CA is still required to verify the brokers
+    let missing_ca = tls.ca_location("./missing/ca.pem");
+    assert!(matches!(missing_ca.validate(), Err(Error::TlsAsset { .. })));
+}
+
+#[test]
+fn mechanism_and_credentials_from_env() {
+    std::env::set_var("KAFKA_SASL_USERNAME", "rust-client");
+    std::env::set_var("KAFKA_SASL_PASSWORD", "secret");
+    match Authentication::from_env("scram-sha-256").expect("scram") {
+        Authentication::Scram {
+            mechanism,
+            username,
+            password,
+        } => {
+            assert_eq!(mechanism, ScramMechanism::Sha256);
+            assert_eq!(username, "rust-client");
+            assert_eq!(password, "secret");
+        }
+        other => panic!("unexpected authentication {other:?}"),
+    }
+    assert!(!Authentication::from_env("SSL").unwrap().is_sasl());
+    assert!(matches!(
+        Authentication::from_env("OAUTHBEARER"),
+        Err(Error::Config(reason)) if reason.contains("KAFKA_OAUTH_TOKEN_FILE")
+    ));
+    assert!(matches!(
+        Authentication::from_env("PLAIN"),
+        Err(Error::Config(_))
+    ));
+}
+
+#[test]
+fn token_file_is_read_on_every_refresh() {
+    let path = std::env::temp_dir()
+        .join(format!("kafka-oauth-token-{}", std::process::id()));
+    let provider = TokenFileProvider::new(&path)
+        .principal("rust-client")
+        .lifetime(Duration::from_secs(60));
+    assert!(provider.token(None).is_err());
+
+    fs::write(&path, "first-token\n").expect("write token");
+    let token = provider.token(None).expect("token");
+    assert_eq!(token.token, "first-token");
+    assert_eq!(token.principal_name, "rust-client");
+    assert!(token.lifetime_ms > now_ms() + 50_000);
+
+    fs::write(&path, "second-token").expect("write token");
+    assert_eq!(provider.token(None).expect("token").token, "second-token");
+    fs::remove_file(&path).ok();
+}
+
+#[test]
+fn oauth_provider_is_called_through_the_client_context() {
+    let cluster = TestCluster::new(&[("testing", 1)]);
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = calls.clone();
+    // the mock cluster has no sasl support so the connection itself
+    // fails, the token is requested when the client starts
+    let producer = KafkaTlsConfig::new(&cluster.bootstrap_servers())
+        .ca_location(CA)
+        .oauth_bearer(move |_: Option<&str>| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Ok(OAuthToken {
+                token: "token".to_string(),
+                principal_name: "rust-client".to_string(),
+                lifetime_ms: now_ms() + 60_000,
+            })
+        })
+        .set("security.protocol", "SASL_PLAINTEXT")
+        .create_producer()
+        .expect("producer");
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while calls.load(Ordering::SeqCst) == 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(calls.load(Ordering::SeqCst) >= 1);
+    drop(producer);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use rdkafka::config::ClientConfig;
+
+use rust_with_kafka_tls::error::Error;
+
+use common::tls_proxy::fetch_topics;
+use common::tls_proxy::ClientCert;
+use common::tls_proxy::TlsBroker;
+
+const TOPIC: &str = "testing";
+
+/// ``true`` when any recorded error mentions one of ``needles``
+fn mentions(errors: &[String], needles: &[&str]) -> bool {
+    errors
+        .iter()
+        .any(|e| needles.iter().any(|needle| e.contains(needle)))
+}
+
+/// client config that presents ``cert`` to the proxy, bypassing the
+/// checks that make ``KafkaTlsConfig`` refuse it so the broker side
+/// rejection can be observed
+fn presenting(broker: &TlsBroker, cert: ClientCert) -> ClientConfig {
+    let (key, cert) = broker.identity(cert);
+    let mut config = broker
+        .client(ClientCert::Valid)
+        .client_config()
+        .expect("client config");
+    config
+        .set("ssl.key.location", key.to_string_lossy())
+        .set("ssl.certificate.location", cert.to_string_lossy());
+    config
+}
+
+#[test]
+fn valid_client_cert_connects() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let config = broker
+        .client(ClientCert::Valid)
+        .client_config()
+        .expect("client config");
+
+    let topics = fetch_topics(&config).expect("metadata over mtls");
+    assert!(topics.iter().any(|topic| topic == TOPIC), "{topics:?}");
+}
+
+#[test]
+fn expired_client_cert_is_rejected() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let config = presenting(&broker, ClientCert::Expired);
+
+    let errors = fetch_topics(&config).expect_err("expired cert");
+    assert!(mentions(&errors, &["certificate expired"]), "{errors:#?}");
+}
+
+#[test]
+fn client_cert_from_another_ca_is_rejected() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let config = presenting(&broker, ClientCert::WrongCa);
+
+    let errors = fetch_topics(&config).expect_err("untrusted cert");
+    assert!(mentions(&errors, &["unknown ca"]), "{errors:#?}");
+}
+
+#[test]
+fn connection_without_client_cert_is_rejected() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let mut config = broker
+        .client(ClientCert::Valid)
+        .client_config()
+        .expect("client config");
+    config
+        .remove("ssl.key.location")
+        .remove("ssl.certificate.location");
+
+    let errors = fetch_topics(&config).expect_err("no client cert");
+    assert!(
+        mentions(&errors, &["handshake failure", "certificate required"]),
+        "{errors:#?}"
+    );
+}
+
+#[test]
+fn missing_client_cert_file_is_a_config_error() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let result = broker
+        .client(ClientCert::Valid)
+        .cert_location("/nonexistent/client.pem")
+        .create_producer();
+
+    match result {
+        Err(Error::TlsAsset { name, path, .. }) => {
+            assert_eq!(name, "certificate");
+            assert_eq!(path.to_str(), Some("/nonexistent/client.pem"));
+        }
+        Err(e) => panic!("unexpected error: {e}"),
+        Ok(_) => panic!("producer created without a client cert"),
+    }
+}
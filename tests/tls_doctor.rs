@@ -0,0 +1,79 @@
+mod common;
+
+use std::time::Duration;
+
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+use rust_with_kafka_tls::tls_doctor::BrokerReport;
+use rust_with_kafka_tls::tls_doctor::Outcome;
+use rust_with_kafka_tls::tls_doctor::TlsDoctor;
+use rust_with_kafka_tls::tls_doctor::STEPS;
+
+use common::tls_proxy::ClientCert;
+use common::tls_proxy::TlsBroker;
+
+const TOPIC: &str = "testing";
+
+fn diagnose(tls: &KafkaTlsConfig) -> BrokerReport {
+    let mut reports = TlsDoctor::new(tls).timeout(Duration::from_secs(5)).run();
+    assert_eq!(reports.len(), 1);
+    reports.remove(0)
+}
+
+/// assert ``step`` failed mentioning ``needle`` and every later step
+/// was skipped
+fn assert_failed_at(report: &BrokerReport, step: &str, needle: &str) {
+    let position = STEPS.iter().position(|s| *s == step).unwrap();
+    for (i, check) in report.checks.iter().enumerate() {
+        match &check.outcome {
+            Outcome::Pass(_) if i < position => {}
+            Outcome::Fail(detail) if i == position => {
+                assert!(detail.contains(needle), "{report}")
+            }
+            Outcome::Skip if i > position => {}
+            _ => panic!("unexpected outcome for {}\n{report}", check.step),
+        }
+    }
+    assert!(!report.passed());
+}
+
+#[test]
+fn healthy_broker_passes_every_step() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let report = diagnose(&broker.client(ClientCert::Valid));
+
+    assert!(report.passed(), "{report}");
+    let steps: Vec<&str> = report.checks.iter().map(|c| c.step).collect();
+    assert_eq!(steps, STEPS);
+    match report.outcome("hostname") {
+        Some(Outcome::Pass(detail)) => {
+            assert!(detail.contains("IP:127.0.0.1"), "{detail}")
+        }
+        other => panic!("unexpected hostname outcome: {other:?}"),
+    }
+}
+
+#[test]
+fn rejected_client_cert_fails_the_handshake() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let report = diagnose(&broker.client(ClientCert::WrongCa));
+    assert_failed_at(&report, "tls handshake", "unknown ca");
+}
+
+#[test]
+fn broker_cert_from_another_ca_fails_the_chain() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let (_, other_ca) = broker.identity(ClientCert::WrongCa);
+    let report =
+        diagnose(&broker.client(ClientCert::Valid).ca_location(other_ca));
+    assert_failed_at(&report, "certificate chain", "CN=localhost");
+}
+
+#[test]
+fn unreachable_brokers_fail_early() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let tls = broker.client(ClientCert::Valid);
+
+    let doctor = TlsDoctor::new(&tls).timeout(Duration::from_secs(5));
+    assert_failed_at(&doctor.diagnose("no-such-host.invalid:9093"), "dns", "");
+    assert_failed_at(&doctor.diagnose("127.0.0.1:1"), "tcp", "127.0.0.1:1");
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use std::fs;
+use std::io::Cursor;
+
+use rust_with_kafka_tls::error::Error;
+use rust_with_kafka_tls::tls_config::KafkaTlsConfig;
+
+use common::tls_proxy::fetch_topics;
+use common::tls_proxy::ClientCert;
+use common::tls_proxy::TlsBroker;
+use common::tls_proxy::KEY_PASSWORD;
+
+const TOPIC: &str = "testing";
+
+fn read(path: impl AsRef<std::path::Path>) -> Vec<u8> {
+    fs::read(path).expect("read pem")
+}
+
+#[test]
+fn in_memory_pem_connects_without_file_locations() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let (key, cert) = broker.identity(ClientCert::Valid);
+    let config = KafkaTlsConfig::new(&broker.brokers())
+        .ca_pem(read(broker.ca_path()))
+        .key_pem(read(key))
+        .cert_pem(read(cert))
+        .client_config()
+        .expect("client config");
+
+    for location in [
+        "ssl.ca.location",
+        "ssl.key.location",
+        "ssl.certificate.location",
+    ] {
+        assert_eq!(config.get(location), None, "{location}");
+    }
+    assert!(config.get("ssl.ca.pem").is_some());
+    let topics = fetch_topics(&config).expect("metadata over mtls");
+    assert!(topics.iter().any(|topic| topic == TOPIC), "{topics:?}");
+}
+
+#[test]
+fn pem_bundle_from_a_reader_connects() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let (key, cert) = broker.identity(ClientCert::Valid);
+    let mut bundle = read(cert);
+    bundle.extend(read(key));
+    bundle.extend(read(broker.ca_path()));
+
+    let config = KafkaTlsConfig::new(&broker.brokers())
+        .pem_bundle("stdin", Cursor::new(bundle))
+        .expect("pem bundle")
+        .client_config()
+        .expect("client config");
+
+    let topics = fetch_topics(&config).expect("metadata over mtls");
+    assert!(topics.iter().any(|topic| topic == TOPIC), "{topics:?}");
+}
+
+#[test]
+fn pem_bundle_without_a_key_is_rejected() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let (_, cert) = broker.identity(ClientCert::Valid);
+    let mut bundle = read(cert);
+    bundle.extend(read(broker.ca_path()));
+
+    match KafkaTlsConfig::new(&broker.brokers())
+        .pem_bundle("stdin", Cursor::new(bundle))
+    {
+        Err(Error::TlsAsset { name, path, .. }) => {
+            assert_eq!(name, "key");
+            assert_eq!(path.to_str(), Some("stdin"));
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+}
+
+#[test]
+fn encrypted_key_needs_the_key_password() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let (_, cert) = broker.identity(ClientCert::Valid);
+    let config = KafkaTlsConfig::new(&broker.brokers())
+        .ca_pem(read(broker.ca_path()))
+        .key_pem(read(broker.encrypted_key_path()))
+        .cert_pem(read(cert));
+
+    match config.validate() {
+        Err(Error::TlsAsset { name, reason, .. }) => {
+            assert_eq!(name, "key");
+            assert!(reason.contains("encrypted"), "{reason}");
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+
+    let config = config
+        .key_password(KEY_PASSWORD)
+        .client_config()
+        .expect("client config");
+    let topics = fetch_topics(&config).expect("metadata over mtls");
+    assert!(topics.iter().any(|topic| topic == TOPIC), "{topics:?}");
+}
+
+#[test]
+fn pem_env_vars_take_precedence_over_paths() {
+    let broker = TlsBroker::start(&[(TOPIC, 1)]);
+    let (key, cert) = broker.identity(ClientCert::Valid);
+    std::env::set_var("KAFKA_TLS_CLIENT_CA", "/nonexistent/ca.pem");
+    for (var, path) in [
+        ("KAFKA_TLS_CLIENT_CA_PEM", broker.ca_path()),
+        ("KAFKA_TLS_CLIENT_KEY_PEM", key),
+        ("KAFKA_TLS_CLIENT_CERT_PEM", cert),
+    ] {
+        std::env::set_var(var, String::from_utf8(read(path)).unwrap());
+    }
+    std::env::set_var("KAFKA_TLS_CLIENT_KEY_PASSWORD", KEY_PASSWORD);
+
+    let config = KafkaTlsConfig::from_env("localhost:9093")
+        .client_config()
+        .expect("client config");
+    assert_eq!(config.get("ssl.ca.location"), None);
+    assert!(config
+        .get("ssl.ca.pem")
+        .is_some_and(|pem| pem.contains("BEGIN CERTIFICATE")));
+    assert_eq!(config.get("ssl.key.password"), Some(KEY_PASSWORD));
+    let debug = format!("{:?}", KafkaTlsConfig::from_env("localhost:9093"));
+    assert!(debug.contains("env:KAFKA_TLS_CLIENT_CA_PEM"), "{debug}");
+    assert!(!debug.contains("BEGIN CERTIFICATE"), "{debug}");
+    assert!(!debug.contains(KEY_PASSWORD), "{debug}");
+}